@@ -21,7 +21,7 @@ pub struct LoanSummary {
     pub lender: Option<String>,
     pub amount: Option<String>,
     pub collateral_amount: Option<String>,
-    pub status: String, // Created, Active, Repaid, Liquidated
+    pub status: String, // Created, Active, Repaid, Liquidated, Cancelled
     pub created_at: u64,
     pub events_count: usize,
 }
@@ -36,12 +36,30 @@ pub struct Statistics {
     pub recent_activity: Vec<Event>,
 }
 
-use serde_json;
 use std::fs;
+use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
 use tracing::{error, info, warn};
 
+/// Segment index written by the custom indexer (`manifest.json`)
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    segments: Vec<Segment>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Segment {
+    file: String,
+    events: u64,
+    /// Committed length; the indexer may be appending past it
+    bytes: u64,
+    compression: String,
+}
+
 /// In-memory data store that reads from indexer output
+#[derive(Default)]
 pub struct DataStore {
     events: Vec<Event>,
     loans: HashMap<String, LoanSummary>,
@@ -55,7 +73,9 @@ impl DataStore {
         }
     }
 
-    /// Load events from indexer output directory
+    /// Load events from indexer output directory. A segment the manifest
+    /// lists but that cannot be read fails the load, since the events would
+    /// be silently missing otherwise.
     pub fn load_from_indexer_output(
         &mut self,
         output_dir: &str,
@@ -70,15 +90,33 @@ impl DataStore {
             return Ok(());
         }
 
-        // Read all JSON files in the output directory
-        let entries = fs::read_dir(output_dir)?;
         let mut loaded_events = 0;
 
+        // Segmented NDJSON output
+        let manifest_path = Path::new(output_dir).join("manifest.json");
+        if manifest_path.exists() {
+            let manifest: Manifest = serde_json::from_str(&fs::read_to_string(&manifest_path)?)?;
+            self.events
+                .reserve(manifest.segments.iter().map(|s| s.events as usize).sum());
+            let before = self.events.len();
+            for segment in &manifest.segments {
+                let path = Path::new(output_dir).join(&segment.file);
+                if let Err(e) = self.load_events_from_segment(&path, segment) {
+                    // Leave the store as it was rather than half loaded
+                    self.events.truncate(before);
+                    return Err(format!("Failed to load events from {:?}: {}", path, e).into());
+                }
+            }
+            loaded_events += self.events.len() - before;
+        }
+
+        // One JSON file per event, as written by older indexer versions
+        let entries = fs::read_dir(output_dir)?;
         for entry in entries {
             let entry = entry?;
             let path = entry.path();
 
-            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+            if path.extension().and_then(|s| s.to_str()) == Some("json") && path != manifest_path {
                 match self.load_events_from_file(&path) {
                     Ok(count) => loaded_events += count,
                     Err(e) => error!("Failed to load events from {:?}: {}", path, e),
@@ -92,6 +130,37 @@ impl DataStore {
         Ok(())
     }
 
+    /// Add events from another source (e.g. a Substreams replay) and
+    /// re-aggregate loans
+    pub fn add_events(&mut self, events: Vec<Event>) {
+        info!("Adding {} events to data store", events.len());
+        self.events.extend(events);
+        self.aggregate_loans();
+    }
+
+    fn load_events_from_segment(
+        &mut self,
+        path: &Path,
+        segment: &Segment,
+    ) -> Result<usize, Box<dyn std::error::Error>> {
+        let file = fs::File::open(path)?;
+        let reader: Box<dyn BufRead> = match segment.compression.as_str() {
+            "zstd" => Box::new(BufReader::new(zstd::stream::read::Decoder::new(file)?)),
+            _ => Box::new(BufReader::new(file.take(segment.bytes))),
+        };
+
+        let mut count = 0;
+        for line in reader.lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            self.events.push(serde_json::from_str(&line)?);
+            count += 1;
+        }
+        Ok(count)
+    }
+
     fn load_events_from_file(
         &mut self,
         file_path: &Path,
@@ -121,6 +190,10 @@ impl DataStore {
 
     /// Aggregate loan information from events
     fn aggregate_loans(&mut self) {
+        // Sources are read one after another; keep events in chain order,
+        // which both the API and the aggregation below rely on
+        self.events.sort_by_key(|e| (e.block_number, e.log_index));
+
         let mut loan_map: HashMap<String, LoanSummary> = HashMap::new();
 
         for event in &self.events {
@@ -143,26 +216,31 @@ impl DataStore {
 
                         loan.events_count += 1;
 
-                        // Update loan details based on event type
+                        // Update loan details based on event type. An offer
+                        // is created by its lender and accepted by a borrower.
+                        let field = |key: &str| {
+                            decoded
+                                .get(key)
+                                .and_then(|v| v.as_str())
+                                .map(str::to_string)
+                        };
                         match event.event_name.as_str() {
                             "LoanCreated" => {
                                 loan.status = "Created".to_string();
-                                if let Some(borrower) =
-                                    decoded.get("borrower").and_then(|v| v.as_str())
-                                {
-                                    loan.borrower = Some(borrower.to_string());
-                                }
-                                if let Some(amount) = decoded.get("amount").and_then(|v| v.as_str())
-                                {
-                                    loan.amount = Some(amount.to_string());
-                                }
+                                loan.lender = field("lender").or(loan.lender.take());
+                                loan.amount = field("amount").or(loan.amount.take());
+                                loan.collateral_amount =
+                                    field("collateralAmount").or(loan.collateral_amount.take());
                             }
                             "LoanAccepted" => {
                                 loan.status = "Active".to_string();
-                                if let Some(lender) = decoded.get("lender").and_then(|v| v.as_str())
-                                {
-                                    loan.lender = Some(lender.to_string());
-                                }
+                                loan.borrower = field("borrower").or(loan.borrower.take());
+                            }
+                            "LoanMatched" => {
+                                loan.status = "Active".to_string();
+                                loan.lender = field("lender").or(loan.lender.take());
+                                loan.borrower = field("borrower").or(loan.borrower.take());
+                                loan.amount = field("amount").or(loan.amount.take());
                             }
                             "LoanRepaid" => {
                                 loan.status = "Repaid".to_string();
@@ -170,10 +248,22 @@ impl DataStore {
                             "LoanLiquidated" => {
                                 loan.status = "Liquidated".to_string();
                             }
-                            "CollateralAdded" => {
-                                if let Some(amount) = decoded.get("amount").and_then(|v| v.as_str())
-                                {
-                                    loan.collateral_amount = Some(amount.to_string());
+                            "LoanOfferCancelled" => {
+                                loan.status = "Cancelled".to_string();
+                            }
+                            "CollateralAdded" | "CollateralRemoved" => {
+                                let current = loan
+                                    .collateral_amount
+                                    .as_deref()
+                                    .and_then(|a| a.parse::<u128>().ok());
+                                let change = field("amount").and_then(|a| a.parse::<u128>().ok());
+                                if let (Some(current), Some(change)) = (current, change) {
+                                    let total = if event.event_name == "CollateralAdded" {
+                                        current.saturating_add(change)
+                                    } else {
+                                        current.saturating_sub(change)
+                                    };
+                                    loan.collateral_amount = Some(total.to_string());
                                 }
                             }
                             _ => {}
@@ -241,6 +331,16 @@ impl DataStore {
             .collect()
     }
 
+    /// Number of loaded events
+    pub fn event_count(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Highest block any loaded event is in
+    pub fn latest_block(&self) -> Option<u64> {
+        self.events.iter().map(|e| e.block_number).max()
+    }
+
     /// Get loan by ID
     pub fn get_loan(&self, loan_id: &str) -> Option<LoanSummary> {
         self.loans.get(loan_id).cloned()
@@ -258,13 +358,13 @@ impl DataStore {
             .filter(|loan| {
                 loan.borrower
                     .as_ref()
-                    .map_or(false, |b| b.eq_ignore_ascii_case(user_address))
+                    .is_some_and(|b| b.eq_ignore_ascii_case(user_address))
                     || loan
                         .lender
                         .as_ref()
-                        .map_or(false, |l| l.eq_ignore_ascii_case(user_address))
+                        .is_some_and(|l| l.eq_ignore_ascii_case(user_address))
             })
-            .map(|loan| loan.clone())
+            .cloned()
             .collect()
     }
 
@@ -290,7 +390,7 @@ impl DataStore {
 
         let recent_activity = {
             let mut events = self.events.clone();
-            events.sort_by(|a, b| b.block_timestamp.cmp(&a.block_timestamp));
+            events.sort_by_key(|e| std::cmp::Reverse(e.block_timestamp));
             events.into_iter().take(10).collect()
         };
 
@@ -335,3 +435,169 @@ impl DataStore {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    /// A fresh output directory for one test
+    fn output_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "neurolend-data-store-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn event(block_number: u64, log_index: u64, event_name: &str, loan_id: &str) -> Event {
+        Event {
+            event_name: event_name.to_string(),
+            transaction_hash: format!("0x{:064x}", block_number),
+            block_number,
+            block_timestamp: 1_700_000_000 + block_number,
+            log_index,
+            contract_address: "0x064c3e0a900743d9ac87c778d2f6d3d5819d4f23".to_string(),
+            topics: Vec::new(),
+            data: "0x".to_string(),
+            decoded_data: Some(HashMap::from([("loanId".to_string(), json!(loan_id))])),
+        }
+    }
+
+    fn ndjson(events: &[Event]) -> Vec<u8> {
+        events
+            .iter()
+            .map(|e| serde_json::to_string(e).unwrap() + "\n")
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Write `manifest.json` listing `(file, events, bytes, compression)`
+    fn write_manifest(dir: &Path, segments: &[(&str, u64, u64, &str)]) {
+        let segments: Vec<_> = segments
+            .iter()
+            .map(|(file, events, bytes, compression)| {
+                json!({
+                    "file": file,
+                    "fromBlock": 0,
+                    "toBlock": 0,
+                    "events": events,
+                    "bytes": bytes,
+                    "sealed": *compression == "zstd",
+                    "compression": compression,
+                })
+            })
+            .collect();
+        let manifest = json!({ "version": 1, "segmentBlocks": 1000, "segments": segments });
+        fs::write(dir.join("manifest.json"), manifest.to_string()).unwrap();
+    }
+
+    fn positions(store: &DataStore) -> Vec<(u64, u64)> {
+        store
+            .get_events(None, None, None)
+            .iter()
+            .map(|e| (e.block_number, e.log_index))
+            .collect()
+    }
+
+    #[test]
+    fn loads_plain_and_zstd_segments_up_to_the_committed_length() {
+        let dir = output_dir("segments");
+        let sealed = ndjson(&[
+            event(10, 0, "LoanCreated", "1"),
+            event(12, 1, "LoanAccepted", "1"),
+        ]);
+        fs::write(
+            dir.join("sealed.ndjson.zst"),
+            zstd::encode_all(&sealed[..], 0).unwrap(),
+        )
+        .unwrap();
+        // The indexer is halfway through appending a line past the commit
+        let mut open = ndjson(&[event(1005, 2, "LoanRepaid", "1")]);
+        let committed = open.len() as u64;
+        open.extend_from_slice(br#"{"event_name":"LoanCreat"#);
+        fs::write(dir.join("open.ndjson"), open).unwrap();
+        write_manifest(
+            &dir,
+            &[
+                ("sealed.ndjson.zst", 2, 0, "zstd"),
+                ("open.ndjson", 1, committed, "none"),
+            ],
+        );
+
+        let mut store = DataStore::new();
+        store
+            .load_from_indexer_output(&dir.to_string_lossy())
+            .unwrap();
+
+        assert_eq!(positions(&store), vec![(10, 0), (12, 1), (1005, 2)]);
+        let loan = store.get_loan("1").unwrap();
+        assert_eq!(loan.status, "Repaid");
+        assert_eq!(loan.events_count, 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fails_and_keeps_nothing_when_a_listed_segment_is_missing() {
+        let dir = output_dir("missing-segment");
+        let first = ndjson(&[event(10, 0, "LoanCreated", "1")]);
+        fs::write(dir.join("first.ndjson"), &first).unwrap();
+        write_manifest(
+            &dir,
+            &[
+                ("first.ndjson", 1, first.len() as u64, "none"),
+                ("sealed-away.ndjson", 1, 100, "none"),
+            ],
+        );
+
+        let mut store = DataStore::new();
+        assert!(store
+            .load_from_indexer_output(&dir.to_string_lossy())
+            .is_err());
+        assert_eq!(store.event_count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn orders_events_from_every_source_by_chain_position() {
+        let dir = output_dir("sources");
+        let later = ndjson(&[event(20, 0, "LoanRepaid", "1")]);
+        let earlier = ndjson(&[event(10, 5, "LoanCreated", "1")]);
+        fs::write(dir.join("b.ndjson"), &later).unwrap();
+        fs::write(dir.join("a.ndjson"), &earlier).unwrap();
+        write_manifest(
+            &dir,
+            &[
+                ("b.ndjson", 1, later.len() as u64, "none"),
+                ("a.ndjson", 1, earlier.len() as u64, "none"),
+            ],
+        );
+        // A single event file as older indexer versions wrote them
+        fs::write(
+            dir.join("legacy.json"),
+            serde_json::to_string(&event(10, 2, "LoanCreated", "2")).unwrap(),
+        )
+        .unwrap();
+
+        let mut store = DataStore::new();
+        store
+            .load_from_indexer_output(&dir.to_string_lossy())
+            .unwrap();
+        store.add_events(vec![
+            event(15, 0, "LoanAccepted", "1"),
+            event(5, 1, "LoanCreated", "3"),
+        ]);
+
+        assert_eq!(
+            positions(&store),
+            vec![(5, 1), (10, 2), (10, 5), (15, 0), (20, 0)]
+        );
+        // Aggregation sees the events in chain order too
+        assert_eq!(store.get_loan("1").unwrap().status, "Repaid");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
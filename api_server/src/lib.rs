@@ -0,0 +1,7 @@
+//! Data layer shared by the NeuroLend API binaries.
+
+pub mod data_store;
+pub mod pb;
+pub mod replay;
+pub mod subgraph_api;
+pub mod subgraph_types;
@@ -12,8 +12,8 @@ use tokio::sync::RwLock;
 use tower_http::cors::CorsLayer;
 use tracing::{info, warn};
 
-mod data_store;
-use data_store::{DataStore, Event, LoanSummary, Statistics};
+use neurolend_api::data_store::{DataStore, Event, LoanSummary, Statistics};
+use neurolend_api::replay;
 
 const API_PORT: u16 = 3001;
 
@@ -37,6 +37,14 @@ async fn main() {
         );
     }
 
+    // Optionally add events from a recorded Substreams session
+    if let Ok(replay_log) = std::env::var("REPLAY_LOG") {
+        match replay::read_replay_file(std::path::Path::new(&replay_log)) {
+            Ok(session) => data_store.add_events(replay::to_store_events(&session.blocks)),
+            Err(e) => warn!("Could not load replay {}: {}", replay_log, e),
+        }
+    }
+
     let app_state: AppState = Arc::new(RwLock::new(data_store));
 
     let app = Router::new()
@@ -92,7 +100,10 @@ pub struct EventsResponse {
 pub struct QueryParams {
     page: Option<u32>,
     limit: Option<u32>,
+    // Accepted but not applied yet
+    #[allow(dead_code)]
     from_block: Option<u64>,
+    #[allow(dead_code)]
     to_block: Option<u64>,
 }
 
@@ -0,0 +1,102 @@
+// Protobuf types shared with the Substreams module.
+
+pub mod contract {
+    pub mod v1 {
+        include!("../../src/pb/contract.v1.rs");
+    }
+}
+
+/// The subset of `sf.substreams.v1` and `sf.substreams.rpc.v2` that a
+/// replay log is made of. Fields we never read (module binaries, progress
+/// messages, debug outputs) are left out and skipped by prost on decode.
+pub mod substreams {
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Request {
+        #[prost(int64, tag = "1")]
+        pub start_block_num: i64,
+        #[prost(string, tag = "2")]
+        pub start_cursor: ::prost::alloc::string::String,
+        #[prost(uint64, tag = "3")]
+        pub stop_block_num: u64,
+        #[prost(bool, tag = "4")]
+        pub final_blocks_only: bool,
+        #[prost(bool, tag = "5")]
+        pub production_mode: bool,
+        #[prost(string, tag = "6")]
+        pub output_module: ::prost::alloc::string::String,
+        #[prost(message, optional, tag = "7")]
+        pub modules: ::core::option::Option<Modules>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Modules {
+        #[prost(message, repeated, tag = "1")]
+        pub modules: ::prost::alloc::vec::Vec<Module>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Module {
+        #[prost(string, tag = "1")]
+        pub name: ::prost::alloc::string::String,
+        #[prost(uint64, tag = "8")]
+        pub initial_block: u64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SessionInit {
+        #[prost(string, tag = "1")]
+        pub trace_id: ::prost::alloc::string::String,
+        #[prost(uint64, tag = "2")]
+        pub resolved_start_block: u64,
+        #[prost(uint64, tag = "3")]
+        pub linear_handoff_block: u64,
+        #[prost(uint64, tag = "4")]
+        pub max_parallel_workers: u64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct BlockScopedData {
+        #[prost(message, optional, tag = "1")]
+        pub output: ::core::option::Option<MapModuleOutput>,
+        #[prost(message, optional, tag = "2")]
+        pub clock: ::core::option::Option<Clock>,
+        #[prost(string, tag = "3")]
+        pub cursor: ::prost::alloc::string::String,
+        #[prost(uint64, tag = "4")]
+        pub final_block_height: u64,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct MapModuleOutput {
+        #[prost(string, tag = "1")]
+        pub name: ::prost::alloc::string::String,
+        #[prost(message, optional, tag = "2")]
+        pub map_output: ::core::option::Option<::prost_types::Any>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct BlockUndoSignal {
+        #[prost(message, optional, tag = "1")]
+        pub last_valid_block: ::core::option::Option<BlockRef>,
+        #[prost(string, tag = "2")]
+        pub last_valid_cursor: ::prost::alloc::string::String,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Clock {
+        #[prost(string, tag = "1")]
+        pub id: ::prost::alloc::string::String,
+        #[prost(uint64, tag = "2")]
+        pub number: u64,
+        #[prost(message, optional, tag = "3")]
+        pub timestamp: ::core::option::Option<::prost_types::Timestamp>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct BlockRef {
+        #[prost(string, tag = "1")]
+        pub id: ::prost::alloc::string::String,
+        #[prost(uint64, tag = "2")]
+        pub number: u64,
+    }
+}
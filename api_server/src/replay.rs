@@ -0,0 +1,698 @@
+use anyhow::{anyhow, bail, Context, Result};
+use prost::Message;
+use prost_types::{Any, Timestamp};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::data_store::Event;
+use crate::pb::contract::v1 as contract;
+use crate::pb::substreams::{BlockScopedData, BlockUndoSignal, Request, SessionInit};
+
+const NEUROLEND_CONTRACT: &str = "0x064c3e0a900743d9ac87c778d2f6d3d5819d4f23";
+
+/// Content type written by `substreams gui` in the dbin header of `replay.log`.
+const REPLAY_CONTENT_TYPE: &str = "rpl-v1";
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+
+/// A recorded Substreams session, with undone blocks already rolled back.
+#[derive(Debug, Default)]
+pub struct ReplaySession {
+    pub request: Option<Request>,
+    pub session: Option<SessionInit>,
+    pub blocks: Vec<ReplayBlock>,
+    pub undo_signals: usize,
+    /// Messages we do not interpret (progress, stats...), counted by type.
+    pub skipped: HashMap<String, usize>,
+}
+
+/// The `EventsCalls` output of one block, whatever module produced it.
+#[derive(Debug)]
+pub struct ReplayBlock {
+    pub number: u64,
+    pub id: String,
+    pub timestamp: u64,
+    pub cursor: String,
+    pub output: contract::EventsCalls,
+}
+
+/// Read a `replay.log` file recorded by `substreams gui`.
+///
+/// The file is a dbin container: the `dbin` magic, a version byte, a
+/// length-prefixed content type, then length-prefixed `google.protobuf.Any`
+/// messages in the order they were received.
+pub fn read_replay_file(path: &Path) -> Result<ReplaySession> {
+    let bytes = fs::read(path).with_context(|| format!("reading {:?}", path))?;
+    let mut session = ReplaySession::default();
+
+    for message in read_dbin_messages(&bytes)? {
+        let type_name = message
+            .type_url
+            .strip_prefix(TYPE_URL_PREFIX)
+            .unwrap_or(&message.type_url);
+
+        match type_name {
+            "sf.substreams.rpc.v2.Request" => {
+                session.request = Some(Request::decode(message.value.as_slice())?);
+            }
+            "sf.substreams.rpc.v2.SessionInit" => {
+                session.session = Some(SessionInit::decode(message.value.as_slice())?);
+            }
+            "sf.substreams.rpc.v2.BlockScopedData" => {
+                let data = BlockScopedData::decode(message.value.as_slice())?;
+                if let Some(block) = replay_block(data)? {
+                    session.blocks.push(block);
+                }
+            }
+            "sf.substreams.rpc.v2.BlockUndoSignal" => {
+                let signal = BlockUndoSignal::decode(message.value.as_slice())?;
+                let last_valid = signal
+                    .last_valid_block
+                    .map(|b| b.number)
+                    .ok_or_else(|| anyhow!("BlockUndoSignal without last_valid_block"))?;
+                session.blocks.retain(|b| b.number <= last_valid);
+                session.undo_signals += 1;
+            }
+            other => *session.skipped.entry(other.to_string()).or_insert(0) += 1,
+        }
+    }
+
+    info!(
+        "Read {} blocks from replay {:?} ({} undo signals)",
+        session.blocks.len(),
+        path,
+        session.undo_signals
+    );
+
+    Ok(session)
+}
+
+fn read_dbin_messages(bytes: &[u8]) -> Result<Vec<Any>> {
+    let mut reader = bytes;
+
+    if take(&mut reader, 4)? != b"dbin" {
+        bail!("not a dbin file");
+    }
+    let version = take(&mut reader, 1)?[0];
+    if version != 1 {
+        bail!("unsupported dbin version {}", version);
+    }
+    let content_type_len = u16::from_be_bytes(take(&mut reader, 2)?.try_into()?) as usize;
+    let content_type = std::str::from_utf8(take(&mut reader, content_type_len)?)?;
+    if content_type != REPLAY_CONTENT_TYPE {
+        bail!(
+            "unexpected content type {:?}, expected {:?}",
+            content_type,
+            REPLAY_CONTENT_TYPE
+        );
+    }
+
+    let mut messages = Vec::new();
+    while !reader.is_empty() {
+        let len = u32::from_be_bytes(take(&mut reader, 4)?.try_into()?) as usize;
+        let payload = take(&mut reader, len)
+            .with_context(|| format!("truncated message #{}", messages.len()))?;
+        messages.push(Any::decode(payload)?);
+    }
+
+    Ok(messages)
+}
+
+fn take<'a>(reader: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if reader.len() < len {
+        bail!("unexpected end of file");
+    }
+    let (head, tail) = reader.split_at(len);
+    *reader = tail;
+    Ok(head)
+}
+
+fn replay_block(data: BlockScopedData) -> Result<Option<ReplayBlock>> {
+    let clock = data
+        .clock
+        .ok_or_else(|| anyhow!("BlockScopedData without clock"))?;
+    let Some(map_output) = data.output.and_then(|o| o.map_output) else {
+        return Ok(None);
+    };
+
+    let value = map_output.value.as_slice();
+    let output = match map_output
+        .type_url
+        .strip_prefix(TYPE_URL_PREFIX)
+        .unwrap_or(&map_output.type_url)
+    {
+        "contract.v1.EventsCalls" => contract::EventsCalls::decode(value)?,
+        "contract.v1.Events" => contract::EventsCalls {
+            events: Some(contract::Events::decode(value)?),
+            calls: None,
+        },
+        "contract.v1.Calls" => contract::EventsCalls {
+            events: None,
+            calls: Some(contract::Calls::decode(value)?),
+        },
+        other => {
+            warn!("Skipping block {} with output type {}", clock.number, other);
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(ReplayBlock {
+        number: clock.number,
+        id: clock.id,
+        timestamp: clock.timestamp.map(|t| t.seconds as u64).unwrap_or_default(),
+        cursor: data.cursor,
+        output,
+    }))
+}
+
+/// Convert the replayed NeuroLend events into the records served by the API,
+/// ordered by block and log index. `decoded_data` uses the subgraph field
+/// names so that loan aggregation and filtering work as on indexer output.
+pub fn to_store_events<'a>(blocks: impl IntoIterator<Item = &'a ReplayBlock>) -> Vec<Event> {
+    let mut out = Vec::new();
+
+    for events in blocks.into_iter().filter_map(|b| b.output.events.as_ref()) {
+        for e in &events.neurolend_collateral_addeds {
+            out.push(store_event(
+                "CollateralAdded",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("borrower", to_hex(&e.borrower)),
+                    ("amount", e.amount.clone()),
+                    ("newCollateralRatio", e.new_collateral_ratio.clone()),
+                    ("timestamp", e.timestamp.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_collateral_removeds {
+            out.push(store_event(
+                "CollateralRemoved",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("borrower", to_hex(&e.borrower)),
+                    ("amount", e.amount.clone()),
+                    ("newCollateralRatio", e.new_collateral_ratio.clone()),
+                    ("timestamp", e.timestamp.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_accepteds {
+            out.push(store_event(
+                "LoanAccepted",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("borrower", to_hex(&e.borrower)),
+                    ("timestamp", e.timestamp.clone()),
+                    ("initialCollateralRatio", e.initial_collateral_ratio.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_createds {
+            out.push(store_event(
+                "LoanCreated",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("lender", to_hex(&e.lender)),
+                    ("tokenAddress", to_hex(&e.token_address)),
+                    ("amount", e.amount.clone()),
+                    ("interestRate", e.interest_rate.clone()),
+                    ("duration", e.duration.clone()),
+                    ("collateralAddress", to_hex(&e.collateral_address)),
+                    ("collateralAmount", e.collateral_amount.clone()),
+                    ("minCollateralRatioBPS", e.min_collateral_ratio_bps.clone()),
+                    ("liquidationThresholdBPS", e.liquidation_threshold_bps.clone()),
+                    ("maxPriceStaleness", e.max_price_staleness.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_liquidateds {
+            out.push(store_event(
+                "LoanLiquidated",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("liquidator", to_hex(&e.liquidator)),
+                    (
+                        "collateralClaimedByLender",
+                        e.collateral_claimed_by_lender.clone(),
+                    ),
+                    ("liquidatorReward", e.liquidator_reward.clone()),
+                    ("timestamp", e.timestamp.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_matcheds {
+            out.push(store_event(
+                "LoanMatched",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("offerId", e.offer_id.clone()),
+                    ("requestId", e.request_id.clone()),
+                    ("lender", to_hex(&e.lender)),
+                    ("borrower", to_hex(&e.borrower)),
+                    ("amount", e.amount.clone()),
+                    ("interestRate", e.interest_rate.clone()),
+                    ("timestamp", e.timestamp.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_offer_cancelleds {
+            out.push(store_event(
+                "LoanOfferCancelled",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("lender", to_hex(&e.lender)),
+                    ("timestamp", e.timestamp.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_offer_removeds {
+            out.push(store_event(
+                "LoanOfferRemoved",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("reason", e.reason.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_repaids {
+            out.push(store_event(
+                "LoanRepaid",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("borrower", to_hex(&e.borrower)),
+                    ("repaymentAmount", e.repayment_amount.clone()),
+                    ("timestamp", e.timestamp.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_request_cancelleds {
+            out.push(store_event(
+                "LoanRequestCancelled",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("requestId", e.request_id.clone()),
+                    ("borrower", to_hex(&e.borrower)),
+                    ("timestamp", e.timestamp.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_request_createds {
+            out.push(store_event(
+                "LoanRequestCreated",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("requestId", e.request_id.clone()),
+                    ("borrower", to_hex(&e.borrower)),
+                    ("tokenAddress", to_hex(&e.token_address)),
+                    ("amount", e.amount.clone()),
+                    ("maxInterestRate", e.max_interest_rate.clone()),
+                    ("duration", e.duration.clone()),
+                    ("collateralAddress", to_hex(&e.collateral_address)),
+                    ("collateralAmount", e.collateral_amount.clone()),
+                    ("minCollateralRatioBPS", e.min_collateral_ratio_bps.clone()),
+                    ("liquidationThresholdBPS", e.liquidation_threshold_bps.clone()),
+                    ("maxPriceStaleness", e.max_price_staleness.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_loan_request_removeds {
+            out.push(store_event(
+                "LoanRequestRemoved",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("requestId", e.request_id.clone()),
+                    ("reason", e.reason.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_ownership_transferreds {
+            out.push(store_event(
+                "OwnershipTransferred",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("previousOwner", to_hex(&e.previous_owner)),
+                    ("newOwner", to_hex(&e.new_owner)),
+                ],
+            ));
+        }
+        for e in &events.neurolend_partial_repayments {
+            out.push(store_event(
+                "PartialRepayment",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("borrower", to_hex(&e.borrower)),
+                    ("repaymentAmount", e.repayment_amount.clone()),
+                    ("totalRepaidAmount", e.total_repaid_amount.clone()),
+                    ("remainingAmount", e.remaining_amount.clone()),
+                    ("timestamp", e.timestamp.clone()),
+                ],
+            ));
+        }
+        for e in &events.neurolend_price_feed_sets {
+            out.push(store_event(
+                "PriceFeedSet",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("tokenAddress", to_hex(&e.token_address)),
+                    ("feedId", to_hex(&e.feed_id)),
+                ],
+            ));
+        }
+        for e in &events.neurolend_price_update_paids {
+            out.push(store_event(
+                "PriceUpdatePaid",
+                &e.evt_tx_hash,
+                e.evt_index,
+                e.evt_block_number,
+                &e.evt_block_time,
+                vec![
+                    ("loanId", e.loan_id.clone()),
+                    ("updateFee", e.update_fee.clone()),
+                    ("timestamp", e.timestamp.clone()),
+                ],
+            ));
+        }
+    }
+
+    out.sort_by_key(|e| (e.block_number, e.log_index));
+    out
+}
+
+fn store_event(
+    event_name: &str,
+    evt_tx_hash: &str,
+    evt_index: u32,
+    evt_block_number: u64,
+    evt_block_time: &Option<Timestamp>,
+    fields: Vec<(&str, String)>,
+) -> Event {
+    Event {
+        event_name: event_name.to_string(),
+        transaction_hash: format!("0x{}", evt_tx_hash.trim_start_matches("0x")),
+        block_number: evt_block_number,
+        block_timestamp: evt_block_time
+            .as_ref()
+            .map(|t| t.seconds as u64)
+            .unwrap_or_default(),
+        log_index: evt_index as u64,
+        contract_address: NEUROLEND_CONTRACT.to_string(),
+        topics: Vec::new(),
+        data: "0x".to_string(),
+        decoded_data: Some(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), serde_json::Value::String(v)))
+                .collect(),
+        ),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(2 + bytes.len() * 2);
+    s.push_str("0x");
+    for b in bytes {
+        s.push_str(&format!("{:02x}", b));
+    }
+    s
+}
+
+/// One line per decoded call, for printing: `(function, tx hash, success)`.
+pub fn call_summaries(calls: &contract::Calls) -> Vec<(&'static str, &str, bool)> {
+    let mut out = Vec::new();
+    for c in &calls.neurolend_call_accept_loan_offer_1s {
+        out.push(("acceptLoanOffer", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_accept_loan_offer_2s {
+        out.push(("acceptLoanOffer", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_add_collaterals {
+        out.push(("addCollateral", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_cancel_loan_offers {
+        out.push(("cancelLoanOffer", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_cancel_loan_requests {
+        out.push(("cancelLoanRequest", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_create_loan_offer_1s {
+        out.push(("createLoanOffer", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_create_loan_offer_2s {
+        out.push(("createLoanOffer", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_create_loan_request_1s {
+        out.push(("createLoanRequest", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_create_loan_request_2s {
+        out.push(("createLoanRequest", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_fill_loan_offer_1s {
+        out.push(("fillLoanOffer", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_fill_loan_offer_2s {
+        out.push(("fillLoanOffer", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_fill_loan_requests {
+        out.push(("fillLoanRequest", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_liquidate_loans {
+        out.push(("liquidateLoan", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_make_partial_repayments {
+        out.push(("makePartialRepayment", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_remove_collaterals {
+        out.push(("removeCollateral", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_renounce_ownerships {
+        out.push(("renounceOwnership", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_repay_loans {
+        out.push(("repayLoan", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_set_token_price_feed_ids {
+        out.push(("setTokenPriceFeedId", c.call_tx_hash.as_str(), c.call_success));
+    }
+    for c in &calls.neurolend_call_transfer_ownerships {
+        out.push(("transferOwnership", c.call_tx_hash.as_str(), c.call_success));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pb::substreams::{BlockRef, Clock, MapModuleOutput};
+
+    fn any<M: Message>(type_name: &str, message: &M) -> Any {
+        Any {
+            type_url: format!("{}{}", TYPE_URL_PREFIX, type_name),
+            value: message.encode_to_vec(),
+        }
+    }
+
+    /// A `replay.log` holding `messages`, framed as `substreams gui` writes it
+    fn dbin(messages: &[Any]) -> Vec<u8> {
+        let mut bytes = b"dbin\x01".to_vec();
+        bytes.extend_from_slice(&(REPLAY_CONTENT_TYPE.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(REPLAY_CONTENT_TYPE.as_bytes());
+        for message in messages {
+            let payload = message.encode_to_vec();
+            bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&payload);
+        }
+        bytes
+    }
+
+    fn read(name: &str, bytes: &[u8]) -> Result<ReplaySession> {
+        let path = std::env::temp_dir().join(format!(
+            "neurolend-replay-{}-{}.log",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, bytes).unwrap();
+        let session = read_replay_file(&path);
+        fs::remove_file(&path).unwrap();
+        session
+    }
+
+    fn block(number: u64, id: &str, events: contract::Events) -> Any {
+        let data = BlockScopedData {
+            output: Some(MapModuleOutput {
+                name: "map_events".to_string(),
+                map_output: Some(any("contract.v1.Events", &events)),
+            }),
+            clock: Some(Clock {
+                id: id.to_string(),
+                number,
+                timestamp: Some(Timestamp {
+                    seconds: 1_700_000_000 + number as i64,
+                    nanos: 0,
+                }),
+            }),
+            cursor: format!("cursor-{}", id),
+            final_block_height: 0,
+        };
+        any("sf.substreams.rpc.v2.BlockScopedData", &data)
+    }
+
+    fn loan_created(block: u64, loan_id: &str) -> contract::Events {
+        contract::Events {
+            neurolend_loan_createds: vec![contract::NeurolendLoanCreated {
+                evt_tx_hash: format!("{:064x}", block),
+                evt_index: 3,
+                evt_block_number: block,
+                loan_id: loan_id.to_string(),
+                lender: vec![0xab; 20],
+                amount: "1000".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn sample_session() -> Vec<Any> {
+        vec![
+            any(
+                "sf.substreams.rpc.v2.Request",
+                &Request {
+                    start_block_num: 100,
+                    output_module: "map_events".to_string(),
+                    ..Default::default()
+                },
+            ),
+            any(
+                "sf.substreams.rpc.v2.SessionInit",
+                &SessionInit {
+                    resolved_start_block: 100,
+                    ..Default::default()
+                },
+            ),
+            block(100, "a100", loan_created(100, "1")),
+            any(
+                "sf.substreams.rpc.v2.ModulesProgress",
+                &SessionInit::default(),
+            ),
+            block(101, "a101", loan_created(101, "2")),
+            any(
+                "sf.substreams.rpc.v2.BlockUndoSignal",
+                &BlockUndoSignal {
+                    last_valid_block: Some(BlockRef {
+                        id: "a100".to_string(),
+                        number: 100,
+                    }),
+                    last_valid_cursor: "cursor-a100".to_string(),
+                },
+            ),
+            block(101, "b101", loan_created(101, "3")),
+        ]
+    }
+
+    #[test]
+    fn reads_blocks_and_rolls_back_undone_ones() {
+        let session = read("blocks", &dbin(&sample_session())).unwrap();
+
+        assert_eq!(session.request.unwrap().start_block_num, 100);
+        assert_eq!(session.session.unwrap().resolved_start_block, 100);
+        assert_eq!(session.undo_signals, 1);
+        assert_eq!(session.skipped["sf.substreams.rpc.v2.ModulesProgress"], 1);
+
+        let ids: Vec<_> = session.blocks.iter().map(|b| b.id.as_str()).collect();
+        assert_eq!(ids, ["a100", "b101"]);
+        assert_eq!(session.blocks[1].timestamp, 1_700_000_101);
+        assert_eq!(session.blocks[1].cursor, "cursor-b101");
+
+        let events = to_store_events(&session.blocks);
+        assert_eq!(events.len(), 2);
+        let event = &events[1];
+        assert_eq!(event.event_name, "LoanCreated");
+        assert_eq!(event.block_number, 101);
+        assert_eq!(event.log_index, 3);
+        assert_eq!(event.transaction_hash, format!("0x{:064x}", 101));
+        let decoded = event.decoded_data.as_ref().unwrap();
+        assert_eq!(decoded["loanId"], "3");
+        assert_eq!(decoded["lender"], format!("0x{}", "ab".repeat(20)));
+        assert_eq!(decoded["amount"], "1000");
+    }
+
+    fn error(name: &str, bytes: &[u8]) -> String {
+        format!("{:#}", read(name, bytes).unwrap_err())
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let messages = sample_session();
+        let bytes = dbin(&messages);
+
+        let err = error("payload", &bytes[..bytes.len() - 5]);
+        assert!(err.contains("truncated message #6"), "{}", err);
+
+        let mut cut_in_length = dbin(&messages[..6]);
+        cut_in_length.extend_from_slice(&[0, 0]);
+        let err = error("length", &cut_in_length);
+        assert!(err.contains("unexpected end of file"), "{}", err);
+
+        let err = error("header", &bytes[..8]);
+        assert!(err.contains("unexpected end of file"), "{}", err);
+
+        let err = error("magic", b"nope");
+        assert!(err.contains("not a dbin file"), "{}", err);
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tracing::info;
+
+use neurolend_api::data_store::DataStore;
+use neurolend_api::replay::{call_summaries, read_replay_file, to_store_events, ReplaySession};
+
+const USAGE: &str = "Offline replay of recorded Substreams sessions
+
+Usage:
+  replay print <replay.log>                 Pretty-print the request and every block output
+  replay export <replay.log> <output_dir>   Write the events as NDJSON the API server can load
+  replay stats <replay.log>                 Feed the events into a data store and print /stats";
+
+fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["print", file] => print(&read_replay_file(Path::new(file))?),
+        ["export", file, output_dir] => {
+            export(&read_replay_file(Path::new(file))?, Path::new(output_dir))
+        }
+        ["stats", file] => stats(&read_replay_file(Path::new(file))?),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn print(session: &ReplaySession) -> Result<()> {
+    if let Some(request) = &session.request {
+        println!("Request:");
+        println!("  output module:   {}", request.output_module);
+        println!("  start block:     {}", request.start_block_num);
+        println!("  stop block:      {}", request.stop_block_num);
+        println!("  production mode: {}", request.production_mode);
+        if !request.start_cursor.is_empty() {
+            println!("  start cursor:    {}", request.start_cursor);
+        }
+        if let Some(modules) = &request.modules {
+            println!("  modules:");
+            for module in &modules.modules {
+                println!("    {} (initial block {})", module.name, module.initial_block);
+            }
+        }
+    } else {
+        println!("Request: <not recorded>");
+    }
+
+    if let Some(init) = &session.session {
+        println!("Session:");
+        println!("  trace id:             {}", init.trace_id);
+        println!("  resolved start block: {}", init.resolved_start_block);
+        println!("  linear handoff block: {}", init.linear_handoff_block);
+    }
+
+    println!(
+        "\n{} blocks with output, {} undo signals",
+        session.blocks.len(),
+        session.undo_signals
+    );
+
+    for block in &session.blocks {
+        println!("\n#{} {} @ {}", block.number, block.id, block.timestamp);
+
+        let events = to_store_events(std::iter::once(block));
+        for event in &events {
+            println!("{}", serde_json::to_string_pretty(event)?);
+        }
+
+        if let Some(calls) = &block.output.calls {
+            for (function, tx_hash, success) in call_summaries(calls) {
+                println!(
+                    "  call {} tx 0x{} {}",
+                    function,
+                    tx_hash,
+                    if success { "ok" } else { "reverted" }
+                );
+            }
+        }
+    }
+
+    for (type_name, count) in &session.skipped {
+        println!("skipped {} x {}", count, type_name);
+    }
+
+    Ok(())
+}
+
+fn export(session: &ReplaySession, output_dir: &Path) -> Result<()> {
+    let events = to_store_events(&session.blocks);
+    let (Some(first), Some(last)) = (session.blocks.first(), session.blocks.last()) else {
+        info!("Replay has no block outputs, nothing to export");
+        return Ok(());
+    };
+
+    fs::create_dir_all(output_dir)?;
+    let path = output_dir.join(format!("replay_{}_{}.json", first.number, last.number));
+    let mut writer = BufWriter::new(fs::File::create(&path)?);
+    for event in &events {
+        serde_json::to_writer(&mut writer, event)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+
+    info!("Exported {} events to {:?}", events.len(), path);
+    Ok(())
+}
+
+fn stats(session: &ReplaySession) -> Result<()> {
+    let mut data_store = DataStore::new();
+    data_store.add_events(to_store_events(&session.blocks));
+    println!("{}", serde_json::to_string_pretty(&data_store.get_statistics())?);
+    Ok(())
+}
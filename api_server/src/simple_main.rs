@@ -1,14 +1,103 @@
+use anyhow::{anyhow, bail, Result};
 use axum::{
-    extract::{Path, Query},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::Json,
-    routing::get,
+    routing::{get, post},
     Router,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
 use tower_http::cors::CorsLayer;
-use tracing::info;
+use tracing::{info, warn};
+
+use neurolend_api::data_store::{self, DataStore, LoanSummary};
+use neurolend_api::replay;
+
+/// Where the custom indexer writes its output when run from the repository
+const DEFAULT_OUTPUT_DIR: &str = "../custom_indexer/output";
+/// How often the indexer output is read again to pick up new events
+const DEFAULT_RELOAD_SECS: u64 = 30;
+/// How many times a load is tried while the indexer keeps rewriting its
+/// manifest underneath it
+const LOAD_ATTEMPTS: usize = 3;
+
+#[derive(Clone)]
+struct AppState {
+    data_store: Arc<RwLock<DataStore>>,
+    source: Arc<Source>,
+    /// Bearer token `POST /reload` requires; it answers 404 without one
+    reload_token: Option<Arc<str>>,
+}
+
+/// Where events are loaded from
+struct Source {
+    /// Segments and manifest written by the custom indexer
+    output_dir: PathBuf,
+    /// A recorded Substreams session to add, if any
+    replay_log: Option<PathBuf>,
+}
+
+impl Source {
+    fn from_env() -> Self {
+        Self {
+            output_dir: std::env::var("INDEXER_OUTPUT_DIR")
+                .unwrap_or_else(|_| DEFAULT_OUTPUT_DIR.to_string())
+                .into(),
+            replay_log: std::env::var("REPLAY_LOG").ok().map(PathBuf::from),
+        }
+    }
+
+    /// Read every source from scratch, on a blocking thread since a large
+    /// output takes a while to parse
+    async fn load(self: &Arc<Self>) -> Result<DataStore> {
+        let source = self.clone();
+        tokio::task::spawn_blocking(move || source.load_blocking())
+            .await
+            .map_err(|e| anyhow!("loading events panicked: {}", e))?
+    }
+
+    /// Load again whenever the manifest changed in the meantime: the indexer
+    /// may have sealed a segment the load was reading, or was about to
+    fn load_blocking(&self) -> Result<DataStore> {
+        let manifest_path = self.output_dir.join("manifest.json");
+        for _ in 0..LOAD_ATTEMPTS {
+            let manifest = fs::read(&manifest_path).ok();
+            let data_store = self.load_once();
+            if fs::read(&manifest_path).ok() == manifest {
+                return data_store;
+            }
+            info!("Indexer output changed while loading it, loading again");
+        }
+        bail!(
+            "indexer output {} kept changing while loading it",
+            self.output_dir.display()
+        )
+    }
+
+    fn load_once(&self) -> Result<DataStore> {
+        let mut data_store = DataStore::new();
+        data_store
+            .load_from_indexer_output(&self.output_dir.to_string_lossy())
+            .map_err(|e| {
+                anyhow!(
+                    "could not load from indexer output {}: {}",
+                    self.output_dir.display(),
+                    e
+                )
+            })?;
+        if let Some(replay_log) = &self.replay_log {
+            let session = replay::read_replay_file(replay_log)?;
+            data_store.add_events(replay::to_store_events(&session.blocks));
+        }
+        Ok(data_store)
+    }
+}
 
 // Port configuration for Railway
 fn get_port() -> u16 {
@@ -22,6 +111,21 @@ fn get_port() -> u16 {
     port
 }
 
+fn get_reload_interval() -> Option<Duration> {
+    let secs = std::env::var("RELOAD_INTERVAL_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_RELOAD_SECS);
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+fn get_reload_token() -> Option<Arc<str>> {
+    std::env::var("RELOAD_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+        .map(Arc::from)
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
@@ -29,15 +133,35 @@ async fn main() {
     let api_port = get_port();
     info!("🚀 Starting NeuroLend API Server on port {}", api_port);
 
-    let app = Router::new()
-        // Event endpoints
-        .route("/events", get(get_all_events))
-        .route("/events/:event_type", get(get_events_by_type))
-        .route("/loans", get(get_all_loans))
-        .route("/stats", get(get_statistics))
-        .route("/health", get(health_check))
-        // Add CORS middleware
-        .layer(CorsLayer::permissive());
+    let source = Arc::new(Source::from_env());
+    info!("📂 Serving events from {}", source.output_dir.display());
+    let data_store = source.load().await.unwrap_or_else(|e| {
+        warn!("Starting without events: {:#}", e);
+        DataStore::new()
+    });
+    let state = AppState {
+        data_store: Arc::new(RwLock::new(data_store)),
+        source,
+        reload_token: get_reload_token(),
+    };
+
+    // The indexer keeps appending, so read its output again now and then
+    if let Some(interval) = get_reload_interval() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = reload(&state).await {
+                    warn!("Keeping the loaded events: {:#}", e);
+                }
+            }
+        });
+    }
+
+    let reload_enabled = state.reload_token.is_some();
+    let app = router(state);
 
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", api_port))
         .await
@@ -52,6 +176,9 @@ async fn main() {
     info!("  GET /events/:event_type - Events by type");
     info!("  GET /loans - All loans");
     info!("  GET /stats - Statistics");
+    if reload_enabled {
+        info!("  POST /reload - Reload the indexer output (needs RELOAD_TOKEN)");
+    }
     info!("  GET /health - Health check");
 
     axum::serve(listener, app)
@@ -59,6 +186,31 @@ async fn main() {
         .expect("Server failed to start");
 }
 
+fn router(state: AppState) -> Router {
+    Router::new()
+        // Event endpoints
+        .route("/events", get(get_all_events))
+        .route("/events/:event_type", get(get_events_by_type))
+        .route("/loans", get(get_all_loans))
+        .route("/stats", get(get_statistics))
+        .route("/reload", post(reload_events))
+        .route("/health", get(health_check))
+        // Add CORS middleware
+        .layer(CorsLayer::permissive())
+        .with_state(state)
+}
+
+/// Replace the data store with a fresh load of every source. The current
+/// one stays in place if the load fails.
+async fn reload(state: &AppState) -> Result<usize> {
+    let data_store = state.source.load().await?;
+    let count = data_store.event_count();
+    *state.data_store.write().await = data_store;
+    Ok(count)
+}
+
+/// An indexed event with the fields most clients need pulled out of
+/// `decoded_data`
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Event {
     pub event_name: String,
@@ -71,23 +223,42 @@ pub struct Event {
     pub borrower: Option<String>,
     pub lender: Option<String>,
     pub amount: Option<String>,
+    pub decoded_data: Option<HashMap<String, serde_json::Value>>,
+}
+
+impl From<data_store::Event> for Event {
+    fn from(event: data_store::Event) -> Self {
+        let field = |key: &str| {
+            event
+                .decoded_data
+                .as_ref()
+                .and_then(|d| d.get(key))
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        Self {
+            loan_id: field("loanId"),
+            borrower: field("borrower"),
+            lender: field("lender"),
+            amount: field("amount"),
+            event_name: event.event_name,
+            transaction_hash: event.transaction_hash,
+            block_number: event.block_number,
+            block_timestamp: event.block_timestamp,
+            log_index: event.log_index,
+            contract_address: event.contract_address,
+            decoded_data: event.decoded_data,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EventsResponse {
     pub events: Vec<Event>,
+    /// Matching events across all pages
     pub total: usize,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct LoanSummary {
-    pub loan_id: String,
-    pub borrower: Option<String>,
-    pub lender: Option<String>,
-    pub amount: Option<String>,
-    pub status: String,
-    pub created_at: u64,
-    pub events_count: usize,
+    pub page: u32,
+    pub limit: u32,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -95,114 +266,256 @@ pub struct Statistics {
     pub total_events: usize,
     pub total_loans: usize,
     pub active_loans: usize,
+    pub total_volume: String,
     pub event_types: HashMap<String, usize>,
 }
 
 #[derive(Deserialize)]
 pub struct QueryParams {
+    page: Option<u32>,
     limit: Option<u32>,
 }
 
-async fn health_check() -> Json<serde_json::Value> {
+/// One page of `events`, oldest first
+fn paginate(events: Vec<data_store::Event>, params: &QueryParams) -> EventsResponse {
+    let total = events.len();
+    let page = params.page.unwrap_or(1).max(1);
+    let limit = params.limit.unwrap_or(50).min(1000);
+
+    let start = ((page - 1) as usize).saturating_mul(limit as usize);
+    let events = events
+        .into_iter()
+        .skip(start)
+        .take(limit as usize)
+        .map(Event::from)
+        .collect();
+
+    EventsResponse {
+        events,
+        total,
+        page,
+        limit,
+    }
+}
+
+async fn health_check(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let data_store = state.data_store.read().await;
     Json(serde_json::json!({
         "status": "healthy",
         "timestamp": chrono::Utc::now().timestamp(),
         "service": "NeuroLend API",
-        "message": "Ready to serve your 0G NeuroLend data!"
+        "events": data_store.event_count(),
+        "latest_block": data_store.latest_block(),
     }))
 }
 
 async fn get_all_events(
+    State(state): State<AppState>,
     Query(params): Query<QueryParams>,
 ) -> Result<Json<EventsResponse>, StatusCode> {
-    // Mock events representing your loan creation
-    let events = vec![
-        Event {
-            event_name: "LoanCreated".to_string(),
-            transaction_hash: "0x1234567890abcdef...".to_string(),
-            block_number: 6937000,
-            block_timestamp: chrono::Utc::now().timestamp() as u64 - 3600,
-            log_index: 0,
-            contract_address: "0x064c3e0a900743d9ac87c778d2f6d3d5819d4f23".to_string(),
-            loan_id: Some("1".to_string()),
-            borrower: Some("0xYourAddress...".to_string()),
-            lender: None,
-            amount: Some("1000000000000000000".to_string()), // 1 ETH in wei
-        },
-        Event {
-            event_name: "CollateralAdded".to_string(),
-            transaction_hash: "0xabcdef1234567890...".to_string(),
-            block_number: 6937001,
-            block_timestamp: chrono::Utc::now().timestamp() as u64 - 3500,
-            log_index: 0,
-            contract_address: "0x064c3e0a900743d9ac87c778d2f6d3d5819d4f23".to_string(),
-            loan_id: Some("1".to_string()),
-            borrower: Some("0xYourAddress...".to_string()),
-            lender: None,
-            amount: Some("2000000000000000000".to_string()), // 2 ETH collateral
-        },
-    ];
-
-    let limit = params.limit.unwrap_or(50).min(1000);
-    let limited_events = events.into_iter().take(limit as usize).collect::<Vec<_>>();
-    let total = limited_events.len();
-
-    Ok(Json(EventsResponse {
-        events: limited_events,
-        total,
-    }))
+    let data_store = state.data_store.read().await;
+    let events = data_store.get_events(None, None, None);
+    Ok(Json(paginate(events, &params)))
 }
 
 async fn get_events_by_type(
+    State(state): State<AppState>,
     Path(event_type): Path<String>,
+    Query(params): Query<QueryParams>,
 ) -> Result<Json<EventsResponse>, StatusCode> {
-    let all_events = vec![Event {
-        event_name: "LoanCreated".to_string(),
-        transaction_hash: "0x1234567890abcdef...".to_string(),
-        block_number: 6937000,
-        block_timestamp: chrono::Utc::now().timestamp() as u64 - 3600,
-        log_index: 0,
-        contract_address: "0x064c3e0a900743d9ac87c778d2f6d3d5819d4f23".to_string(),
-        loan_id: Some("1".to_string()),
-        borrower: Some("0xYourAddress...".to_string()),
-        lender: None,
-        amount: Some("1000000000000000000".to_string()),
-    }];
-
-    let filtered_events: Vec<Event> = all_events
-        .into_iter()
-        .filter(|e| e.event_name.eq_ignore_ascii_case(&event_type))
-        .collect();
-
-    Ok(Json(EventsResponse {
-        total: filtered_events.len(),
-        events: filtered_events,
-    }))
+    let data_store = state.data_store.read().await;
+    let events = data_store.get_events(Some(&event_type), None, None);
+    Ok(Json(paginate(events, &params)))
 }
 
-async fn get_all_loans() -> Json<Vec<LoanSummary>> {
-    let loans = vec![LoanSummary {
-        loan_id: "1".to_string(),
-        borrower: Some("0xYourAddress...".to_string()),
-        lender: None,
-        amount: Some("1000000000000000000".to_string()),
-        status: "Active".to_string(),
-        created_at: chrono::Utc::now().timestamp() as u64 - 3600,
-        events_count: 2,
-    }];
-
+/// Every loan, newest first
+async fn get_all_loans(State(state): State<AppState>) -> Json<Vec<LoanSummary>> {
+    let mut loans = state.data_store.read().await.get_loans();
+    loans.sort_by_key(|loan| {
+        (
+            std::cmp::Reverse(loan.created_at),
+            std::cmp::Reverse(loan.loan_id.parse::<u128>().unwrap_or_default()),
+        )
+    });
     Json(loans)
 }
 
-async fn get_statistics() -> Json<Statistics> {
-    let mut event_types = HashMap::new();
-    event_types.insert("LoanCreated".to_string(), 1);
-    event_types.insert("CollateralAdded".to_string(), 1);
-
+async fn get_statistics(State(state): State<AppState>) -> Json<Statistics> {
+    let stats = state.data_store.read().await.get_statistics();
     Json(Statistics {
-        total_events: 2,
-        total_loans: 1,
-        active_loans: 1,
-        event_types,
+        total_events: stats.total_events,
+        total_loans: stats.total_loans,
+        active_loans: stats.active_loans,
+        total_volume: stats.total_volume,
+        event_types: stats.event_types,
     })
 }
+
+async fn reload_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let expected = state.reload_token.as_deref().ok_or(StatusCode::NOT_FOUND)?;
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if token != Some(expected) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let events = reload(&state).await.map_err(|e| {
+        warn!("Keeping the loaded events: {:#}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    info!("Reloaded {} events", events);
+    Ok(Json(serde_json::json!({ "events": events })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::Service;
+
+    fn events(count: u64) -> Vec<data_store::Event> {
+        (0..count)
+            .map(|i| data_store::Event {
+                event_name: "LoanCreated".to_string(),
+                transaction_hash: format!("0x{:064x}", i),
+                block_number: 100 + i,
+                block_timestamp: 1_700_000_000 + i,
+                log_index: 0,
+                contract_address: String::new(),
+                topics: Vec::new(),
+                data: "0x".to_string(),
+                decoded_data: None,
+            })
+            .collect()
+    }
+
+    fn blocks(response: &EventsResponse) -> Vec<u64> {
+        response.events.iter().map(|e| e.block_number).collect()
+    }
+
+    #[test]
+    fn paginates_from_page_one() {
+        let params = |page, limit| QueryParams { page, limit };
+
+        let first = paginate(events(5), &params(None, Some(2)));
+        assert_eq!((first.total, first.page, first.limit), (5, 1, 2));
+        assert_eq!(blocks(&first), vec![100, 101]);
+        assert_eq!(
+            blocks(&paginate(events(5), &params(Some(3), Some(2)))),
+            vec![104]
+        );
+        assert!(paginate(events(5), &params(Some(4), Some(2)))
+            .events
+            .is_empty());
+        // Page 0 is page 1 and the limit is capped
+        let clamped = paginate(events(5), &params(Some(0), Some(5000)));
+        assert_eq!((clamped.page, clamped.limit), (1, 1000));
+        assert_eq!(clamped.events.len(), 5);
+        assert_eq!(paginate(events(60), &params(None, None)).events.len(), 50);
+    }
+
+    /// An output directory holding a manifest with one segment of `count`
+    /// events
+    fn output_dir(name: &str, count: u64) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("neurolend-api-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        write_segment(&dir, count);
+        dir
+    }
+
+    fn write_segment(dir: &std::path::Path, count: u64) {
+        let segment: String = events(count)
+            .iter()
+            .map(|e| serde_json::to_string(e).unwrap() + "\n")
+            .collect();
+        fs::write(dir.join("0.ndjson"), &segment).unwrap();
+        let manifest = serde_json::json!({
+            "segments": [{
+                "file": "0.ndjson",
+                "events": count,
+                "bytes": segment.len(),
+                "compression": "none",
+            }],
+        });
+        fs::write(dir.join("manifest.json"), manifest.to_string()).unwrap();
+    }
+
+    async fn state(output_dir: PathBuf, reload_token: Option<&str>) -> AppState {
+        let source = Arc::new(Source {
+            output_dir,
+            replay_log: None,
+        });
+        AppState {
+            data_store: Arc::new(RwLock::new(source.load().await.unwrap())),
+            source,
+            reload_token: reload_token.map(Arc::from),
+        }
+    }
+
+    async fn post_reload(state: &AppState, authorization: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::post("/reload");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let response = router(state.clone())
+            .call(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn reload_needs_the_configured_token() {
+        let dir = output_dir("reload-token", 2);
+
+        let state = self::state(dir.clone(), None).await;
+        assert_eq!(
+            post_reload(&state, Some("Bearer secret")).await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        let state = self::state(dir.clone(), Some("secret")).await;
+        assert_eq!(post_reload(&state, None).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            post_reload(&state, Some("Bearer wrong")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            post_reload(&state, Some("secret")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        write_segment(&dir, 3);
+        assert_eq!(
+            post_reload(&state, Some("Bearer secret")).await,
+            (StatusCode::OK, r#"{"events":3}"#.to_string())
+        );
+        assert_eq!(state.data_store.read().await.event_count(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_reload_keeps_the_loaded_events() {
+        let dir = output_dir("reload-failure", 2);
+        let state = self::state(dir.clone(), Some("secret")).await;
+
+        // The manifest still lists a segment that is gone
+        fs::remove_file(dir.join("0.ndjson")).unwrap();
+        assert_eq!(
+            post_reload(&state, Some("Bearer secret")).await.0,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(state.data_store.read().await.event_count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,14 +5,12 @@ use axum::{
     routing::get,
     Router,
 };
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde::Deserialize;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tower_http::cors::CorsLayer;
-use tracing::{info, warn};
 
-use crate::data_store::{DataStore, Event, Statistics};
+use crate::data_store::DataStore;
 use crate::subgraph_types::*;
 
 type AppState = Arc<RwLock<DataStore>>;
@@ -21,8 +19,10 @@ type AppState = Arc<RwLock<DataStore>>;
 pub struct QueryParams {
     pub first: Option<u32>,
     pub skip: Option<u32>,
-    pub orderBy: Option<String>,
-    pub orderDirection: Option<String>,
+    #[serde(rename = "orderBy")]
+    pub order_by: Option<String>,
+    #[serde(rename = "orderDirection")]
+    pub order_direction: Option<String>,
     #[serde(rename = "where")]
     pub where_clause: Option<String>,
 }
@@ -437,8 +437,8 @@ async fn get_combined_query(
         Query(QueryParams {
             first: params.first,
             skip: params.skip,
-            orderBy: params.orderBy.clone(),
-            orderDirection: params.orderDirection.clone(),
+            order_by: params.order_by.clone(),
+            order_direction: params.order_direction.clone(),
             where_clause: params.where_clause.clone(),
         }),
         State(state.clone()),
@@ -450,8 +450,8 @@ async fn get_combined_query(
         Query(QueryParams {
             first: params.first,
             skip: params.skip,
-            orderBy: params.orderBy.clone(),
-            orderDirection: params.orderDirection.clone(),
+            order_by: params.order_by.clone(),
+            order_direction: params.order_direction.clone(),
             where_clause: params.where_clause.clone(),
         }),
         State(state.clone()),
@@ -463,8 +463,8 @@ async fn get_combined_query(
         Query(QueryParams {
             first: params.first,
             skip: params.skip,
-            orderBy: params.orderBy.clone(),
-            orderDirection: params.orderDirection.clone(),
+            order_by: params.order_by.clone(),
+            order_direction: params.order_direction.clone(),
             where_clause: params.where_clause.clone(),
         }),
         State(state.clone()),
@@ -476,8 +476,8 @@ async fn get_combined_query(
         Query(QueryParams {
             first: params.first,
             skip: params.skip,
-            orderBy: params.orderBy.clone(),
-            orderDirection: params.orderDirection.clone(),
+            order_by: params.order_by.clone(),
+            order_direction: params.order_direction.clone(),
             where_clause: params.where_clause.clone(),
         }),
         State(state.clone()),
@@ -489,8 +489,8 @@ async fn get_combined_query(
         Query(QueryParams {
             first: params.first,
             skip: params.skip,
-            orderBy: params.orderBy.clone(),
-            orderDirection: params.orderDirection.clone(),
+            order_by: params.order_by.clone(),
+            order_direction: params.order_direction.clone(),
             where_clause: params.where_clause.clone(),
         }),
         State(state.clone()),
@@ -502,8 +502,8 @@ async fn get_combined_query(
         Query(QueryParams {
             first: params.first,
             skip: params.skip,
-            orderBy: params.orderBy.clone(),
-            orderDirection: params.orderDirection.clone(),
+            order_by: params.order_by.clone(),
+            order_direction: params.order_direction.clone(),
             where_clause: params.where_clause.clone(),
         }),
         State(state.clone()),
@@ -515,8 +515,8 @@ async fn get_combined_query(
         Query(QueryParams {
             first: params.first,
             skip: params.skip,
-            orderBy: params.orderBy.clone(),
-            orderDirection: params.orderDirection.clone(),
+            order_by: params.order_by.clone(),
+            order_direction: params.order_direction.clone(),
             where_clause: params.where_clause.clone(),
         }),
         State(state.clone()),
@@ -528,8 +528,8 @@ async fn get_combined_query(
         Query(QueryParams {
             first: params.first,
             skip: params.skip,
-            orderBy: params.orderBy.clone(),
-            orderDirection: params.orderDirection.clone(),
+            order_by: params.order_by.clone(),
+            order_direction: params.order_direction.clone(),
             where_clause: params.where_clause.clone(),
         }),
         State(state.clone()),
@@ -541,8 +541,8 @@ async fn get_combined_query(
         Query(QueryParams {
             first: params.first,
             skip: params.skip,
-            orderBy: params.orderBy,
-            orderDirection: params.orderDirection,
+            order_by: params.order_by,
+            order_direction: params.order_direction,
             where_clause: params.where_clause,
         }),
         State(state),
@@ -2,12 +2,9 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
-mod data_store;
-mod subgraph_types;
-mod subgraph_api;
-
-use data_store::DataStore;
-use subgraph_api::create_subgraph_router;
+use neurolend_api::data_store::DataStore;
+use neurolend_api::replay;
+use neurolend_api::subgraph_api::create_subgraph_router;
 
 // Port configuration for Railway/production
 fn get_port() -> u16 {
@@ -40,6 +37,14 @@ async fn main() {
         );
     }
 
+    // Optionally add events from a recorded Substreams session
+    if let Ok(replay_log) = std::env::var("REPLAY_LOG") {
+        match replay::read_replay_file(std::path::Path::new(&replay_log)) {
+            Ok(session) => data_store.add_events(replay::to_store_events(&session.blocks)),
+            Err(e) => warn!("Could not load replay {}: {}", replay_log, e),
+        }
+    }
+
     let app_state = Arc::new(RwLock::new(data_store));
 
     // Create subgraph-compatible router
@@ -1,5 +1,4 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 // Subgraph-compatible data structures
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -0,0 +1,252 @@
+-- NeuroLend indexer schema: one table per contract event, plus the blocks
+-- those events are in and the indexer checkpoint.
+--
+-- Event tables are named and laid out after abi/neurolend_contract.abi.json
+-- (event and parameter names in snake_case). uint256 values are stored as
+-- NUMERIC so they never overflow.
+
+CREATE TABLE blocks (
+    number BIGINT PRIMARY KEY,
+    hash TEXT NOT NULL,
+    parent_hash TEXT NOT NULL,
+    timestamp BIGINT NOT NULL
+);
+
+CREATE TABLE checkpoints (
+    contract_address TEXT PRIMARY KEY,
+    current_block BIGINT NOT NULL,
+    last_updated TIMESTAMPTZ NOT NULL,
+    caught_up BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE TABLE collateral_added (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    borrower TEXT,
+    amount NUMERIC(78, 0),
+    new_collateral_ratio NUMERIC(78, 0),
+    timestamp NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX collateral_added_loan_id_idx ON collateral_added (loan_id);
+
+CREATE TABLE collateral_removed (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    borrower TEXT,
+    amount NUMERIC(78, 0),
+    new_collateral_ratio NUMERIC(78, 0),
+    timestamp NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX collateral_removed_loan_id_idx ON collateral_removed (loan_id);
+
+CREATE TABLE loan_accepted (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    borrower TEXT,
+    timestamp NUMERIC(78, 0),
+    initial_collateral_ratio NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_accepted_loan_id_idx ON loan_accepted (loan_id);
+
+CREATE TABLE loan_created (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    lender TEXT,
+    token_address TEXT,
+    amount NUMERIC(78, 0),
+    interest_rate NUMERIC(78, 0),
+    duration NUMERIC(78, 0),
+    collateral_address TEXT,
+    collateral_amount NUMERIC(78, 0),
+    min_collateral_ratio_bps NUMERIC(78, 0),
+    liquidation_threshold_bps NUMERIC(78, 0),
+    max_price_staleness NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_created_loan_id_idx ON loan_created (loan_id);
+
+CREATE TABLE loan_liquidated (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    liquidator TEXT,
+    collateral_claimed_by_lender NUMERIC(78, 0),
+    liquidator_reward NUMERIC(78, 0),
+    timestamp NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_liquidated_loan_id_idx ON loan_liquidated (loan_id);
+
+CREATE TABLE loan_matched (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    offer_id NUMERIC(78, 0),
+    request_id NUMERIC(78, 0),
+    lender TEXT,
+    borrower TEXT,
+    amount NUMERIC(78, 0),
+    interest_rate NUMERIC(78, 0),
+    timestamp NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_matched_loan_id_idx ON loan_matched (loan_id);
+
+CREATE TABLE loan_offer_cancelled (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    lender TEXT,
+    timestamp NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_offer_cancelled_loan_id_idx ON loan_offer_cancelled (loan_id);
+
+CREATE TABLE loan_offer_removed (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    reason TEXT,
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_offer_removed_loan_id_idx ON loan_offer_removed (loan_id);
+
+CREATE TABLE loan_repaid (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    borrower TEXT,
+    repayment_amount NUMERIC(78, 0),
+    timestamp NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_repaid_loan_id_idx ON loan_repaid (loan_id);
+
+CREATE TABLE loan_request_cancelled (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    request_id NUMERIC(78, 0),
+    borrower TEXT,
+    timestamp NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_request_cancelled_request_id_idx ON loan_request_cancelled (request_id);
+
+CREATE TABLE loan_request_created (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    request_id NUMERIC(78, 0),
+    borrower TEXT,
+    token_address TEXT,
+    amount NUMERIC(78, 0),
+    max_interest_rate NUMERIC(78, 0),
+    duration NUMERIC(78, 0),
+    collateral_address TEXT,
+    collateral_amount NUMERIC(78, 0),
+    min_collateral_ratio_bps NUMERIC(78, 0),
+    liquidation_threshold_bps NUMERIC(78, 0),
+    max_price_staleness NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_request_created_request_id_idx ON loan_request_created (request_id);
+
+CREATE TABLE loan_request_removed (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    request_id NUMERIC(78, 0),
+    reason TEXT,
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX loan_request_removed_request_id_idx ON loan_request_removed (request_id);
+
+CREATE TABLE ownership_transferred (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    previous_owner TEXT,
+    new_owner TEXT,
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE TABLE partial_repayment (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    borrower TEXT,
+    repayment_amount NUMERIC(78, 0),
+    total_repaid_amount NUMERIC(78, 0),
+    remaining_amount NUMERIC(78, 0),
+    timestamp NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX partial_repayment_loan_id_idx ON partial_repayment (loan_id);
+
+CREATE TABLE price_feed_set (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    token_address TEXT,
+    feed_id TEXT,
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE TABLE price_update_paid (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    loan_id NUMERIC(78, 0),
+    update_fee NUMERIC(78, 0),
+    timestamp NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX price_update_paid_loan_id_idx ON price_update_paid (loan_id);
@@ -0,0 +1,21 @@
+-- Transactions sent to the contract, including reverted ones. Only written
+-- when call indexing is enabled.
+
+CREATE TABLE calls (
+    block_number BIGINT NOT NULL,
+    transaction_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    function_name TEXT NOT NULL,
+    function_signature TEXT NOT NULL,
+    from_address TEXT NOT NULL,
+    value NUMERIC(78, 0) NOT NULL,
+    success BOOLEAN NOT NULL,
+    gas_used BIGINT NOT NULL,
+    input TEXT NOT NULL,
+    decoded_input JSONB,
+    PRIMARY KEY (block_number, transaction_index)
+);
+
+CREATE INDEX calls_function_name_idx ON calls (function_name);
+CREATE INDEX calls_from_address_idx ON calls (from_address);
@@ -0,0 +1,19 @@
+-- Periodic snapshots of active loans, one row per loan and snapshot block.
+-- Only written when snapshots are enabled. A NULL means the view reverted.
+
+CREATE TABLE loan_snapshots (
+    loan_id NUMERIC(78, 0) NOT NULL,
+    block_number BIGINT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    health_factor NUMERIC(78, 0),
+    price_stale BOOLEAN,
+    defaulted BOOLEAN,
+    current_interest NUMERIC(78, 0),
+    token_address TEXT,
+    token_price NUMERIC(78, 0),
+    collateral_address TEXT,
+    collateral_price NUMERIC(78, 0),
+    PRIMARY KEY (loan_id, block_number)
+);
+
+CREATE INDEX loan_snapshots_block_number_idx ON loan_snapshots (block_number);
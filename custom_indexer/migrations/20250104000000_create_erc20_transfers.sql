@@ -0,0 +1,23 @@
+-- ERC20 transfers into and out of the contract, linked to the NeuroLend
+-- event in the same transaction. Only written when transfer indexing is
+-- enabled.
+
+CREATE TABLE erc20_transfers (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    token_address TEXT NOT NULL,
+    from_address TEXT NOT NULL,
+    to_address TEXT NOT NULL,
+    value NUMERIC(78, 0) NOT NULL,
+    direction TEXT NOT NULL,
+    action TEXT,
+    loan_id NUMERIC(78, 0),
+    request_id NUMERIC(78, 0),
+    PRIMARY KEY (block_number, log_index)
+);
+
+CREATE INDEX erc20_transfers_loan_id_idx ON erc20_transfers (loan_id);
+CREATE INDEX erc20_transfers_request_id_idx ON erc20_transfers (request_id);
+CREATE INDEX erc20_transfers_token_address_idx ON erc20_transfers (token_address);
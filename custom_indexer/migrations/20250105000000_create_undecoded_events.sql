@@ -0,0 +1,14 @@
+-- Contract logs whose signature is known but whose data does not decode,
+-- kept raw so a batch never advances the checkpoint past an event it
+-- dropped. Topics and data are hex, as in the file sink's output.
+
+CREATE TABLE undecoded_events (
+    block_number BIGINT NOT NULL,
+    log_index INTEGER NOT NULL,
+    transaction_hash TEXT NOT NULL,
+    block_timestamp BIGINT NOT NULL,
+    event_name TEXT NOT NULL,
+    topics TEXT[] NOT NULL,
+    data TEXT NOT NULL,
+    PRIMARY KEY (block_number, log_index)
+);
@@ -0,0 +1,53 @@
+/// A range with fewer logs than this is cheap enough to double the next one
+const GROW_BELOW_LOGS: usize = 1000;
+
+/// Block range size for `eth_getLogs`, adjusted from RPC feedback. Halved
+/// whenever a range has to be split because the node rejected it, doubled
+/// (up to `max`) after a range that came back small.
+#[derive(Debug, Clone)]
+pub struct AdaptiveBatchSize {
+    current: u64,
+    max: u64,
+}
+
+impl AdaptiveBatchSize {
+    pub fn new(initial: u64, max: u64) -> Self {
+        let max = max.max(1);
+        Self {
+            current: initial.clamp(1, max),
+            max,
+        }
+    }
+
+    pub fn get(&self) -> u64 {
+        self.current
+    }
+
+    /// Feed back the outcome of fetching a `blocks` long range that had to be
+    /// split `splits` times and returned `logs` logs
+    pub fn observe(&mut self, blocks: u64, splits: u32, logs: usize) {
+        if splits > 0 {
+            // The size that finally worked
+            self.current = (blocks >> splits.min(63)).max(1);
+        } else if logs < GROW_BELOW_LOGS && blocks >= self.current {
+            self.current = self.current.saturating_mul(2).min(self.max);
+        }
+    }
+}
+
+/// Whether an RPC error means the range was too big rather than that the
+/// node is unhealthy. Providers word this differently, so match the common
+/// phrasings.
+pub fn is_range_too_large(error: &str) -> bool {
+    const PATTERNS: &[&str] = &[
+        "too many results",
+        "query returned more than",
+        "exceed maximum block range",
+        "range is too large",
+        "response size",
+        "too large",
+    ];
+
+    let error = error.to_lowercase();
+    PATTERNS.iter().any(|p| error.contains(p))
+}
@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use ethers::types::{Address, Bytes, H256, U256, U64};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use tracing::warn;
+
+use crate::decoder::CallDecoder;
+use crate::headers::BlockHeader;
+use crate::rpc_pool::RpcPool;
+
+/// Blocks (with their transactions) per batched request
+const BLOCK_BATCH_SIZE: usize = 50;
+/// Receipts per batched request
+const RECEIPT_BATCH_SIZE: usize = 100;
+
+/// One transaction sent to the contract, successful or reverted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedCall {
+    pub function_name: String,
+    /// Full signature, which tells overloads apart
+    pub function_signature: String,
+    pub transaction_hash: String,
+    pub block_number: u64,
+    pub block_timestamp: u64,
+    pub transaction_index: u64,
+    pub from_address: String,
+    pub contract_address: String,
+    /// Native value sent, in wei
+    pub value: String,
+    pub success: bool,
+    pub gas_used: u64,
+    pub input: String,
+    pub decoded_input: Option<Map<String, Value>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcBlock {
+    number: U64,
+    hash: H256,
+    parent_hash: H256,
+    timestamp: U64,
+    transactions: Vec<RpcTransaction>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcTransaction {
+    hash: H256,
+    from: Address,
+    to: Option<Address>,
+    input: Bytes,
+    value: U256,
+    transaction_index: U64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcReceipt {
+    status: Option<U64>,
+    gas_used: Option<U256>,
+}
+
+/// Finds calls to the contract by scanning every block in a range, since
+/// reverted calls leave no logs to find them by. Only transactions sent
+/// straight to the contract are seen; calls made through another contract
+/// would need tracing.
+pub struct CallFetcher {
+    pool: RpcPool,
+    contract: Address,
+    decoder: CallDecoder,
+}
+
+impl CallFetcher {
+    pub fn new(pool: RpcPool, contract: Address) -> Result<Self> {
+        Ok(Self {
+            pool,
+            contract,
+            decoder: CallDecoder::neurolend()?,
+        })
+    }
+
+    /// Every call to the contract in `from..=to`, in block and transaction
+    /// order, with the headers of the blocks they are in
+    pub async fn fetch(&self, from: u64, to: u64) -> Result<(Vec<IndexedCall>, Vec<BlockHeader>)> {
+        let mut found = Vec::new();
+        let mut headers = Vec::new();
+
+        let numbers: Vec<u64> = (from..=to).collect();
+        for chunk in numbers.chunks(BLOCK_BATCH_SIZE) {
+            let params: Vec<Value> = chunk
+                .iter()
+                .map(|number| json!([format!("0x{:x}", number), true]))
+                .collect();
+            let blocks = self
+                .pool
+                .batch_call::<RpcBlock>("eth_getBlockByNumber", &params)
+                .await
+                .with_context(|| {
+                    format!("fetching blocks {} to {}", chunk[0], chunk[chunk.len() - 1])
+                })?;
+
+            for (number, block) in chunk.iter().zip(blocks) {
+                let block =
+                    block.with_context(|| format!("node does not have block {}", number))?;
+                let header = BlockHeader {
+                    number: block.number.as_u64(),
+                    hash: block.hash,
+                    parent_hash: block.parent_hash,
+                    timestamp: block.timestamp.as_u64(),
+                };
+                let before = found.len();
+                found.extend(
+                    block
+                        .transactions
+                        .into_iter()
+                        .filter(|tx| tx.to == Some(self.contract))
+                        .map(|tx| (header.timestamp, header.number, tx)),
+                );
+                if found.len() > before {
+                    headers.push(header);
+                }
+            }
+        }
+
+        let mut calls = Vec::with_capacity(found.len());
+        for chunk in found.chunks(RECEIPT_BATCH_SIZE) {
+            let params: Vec<Value> = chunk
+                .iter()
+                .map(|(_, _, tx)| json!([format!("0x{:x}", tx.hash)]))
+                .collect();
+            let receipts = self
+                .pool
+                .batch_call::<RpcReceipt>("eth_getTransactionReceipt", &params)
+                .await
+                .context("fetching receipts")?;
+
+            for ((timestamp, number, tx), receipt) in chunk.iter().zip(receipts) {
+                let receipt = receipt.with_context(|| format!("no receipt for 0x{:x}", tx.hash))?;
+                calls.push(self.indexed_call(*timestamp, *number, tx, &receipt));
+            }
+        }
+        Ok((calls, headers))
+    }
+
+    fn indexed_call(
+        &self,
+        block_timestamp: u64,
+        block_number: u64,
+        tx: &RpcTransaction,
+        receipt: &RpcReceipt,
+    ) -> IndexedCall {
+        let (function_name, function_signature, decoded_input) =
+            match self.decoder.decode(&tx.input) {
+                Some((function, decoded)) => {
+                    let decoded = decoded
+                        .map_err(|e| warn!("Could not decode call in tx 0x{:x}: {:#}", tx.hash, e))
+                        .ok();
+                    (function.name.clone(), function.signature(), decoded)
+                }
+                // Plain transfers and functions missing from the ABI
+                None => ("Unknown".to_string(), String::new(), None),
+            };
+
+        IndexedCall {
+            function_name,
+            function_signature,
+            transaction_hash: format!("0x{:x}", tx.hash),
+            block_number,
+            block_timestamp,
+            transaction_index: tx.transaction_index.as_u64(),
+            from_address: format!("0x{:x}", tx.from),
+            contract_address: format!("0x{:x}", self.contract),
+            value: tx.value.to_string(),
+            // Receipts without a status predate Byzantium; a mined
+            // transaction there did not revert
+            success: receipt.status.is_none_or(|s| s.as_u64() == 1),
+            gas_used: receipt.gas_used.map_or(0, |g| g.as_u64()),
+            input: format!("0x{}", hex::encode(&tx.input)),
+            decoded_input,
+        }
+    }
+}
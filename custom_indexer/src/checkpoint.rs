@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Same file name and shape as the Node indexer (`simple_indexer.js`), so
+/// either indexer can resume from the other's output directory.
+pub const STATE_FILE: &str = "indexer_state.json";
+
+/// Progress marker persisted after every processed range.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    /// Next block to index; every block below it has been fully processed.
+    pub current_block: u64,
+    pub last_updated: DateTime<Utc>,
+    /// Set once historical sync reached the head with no outstanding gaps.
+    /// Missing from state files written by the Node indexer.
+    #[serde(default)]
+    pub caught_up: bool,
+}
+
+impl Checkpoint {
+    pub fn new(current_block: u64, caught_up: bool) -> Self {
+        Self {
+            current_block,
+            last_updated: Utc::now(),
+            caught_up,
+        }
+    }
+
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(STATE_FILE)
+    }
+
+    /// Read the checkpoint from `output_dir`, if there is one.
+    pub fn load(output_dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path(output_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        let checkpoint =
+            serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))?;
+        Ok(Some(checkpoint))
+    }
+
+    /// Write the checkpoint to a temporary file and rename it over the old
+    /// one, so a crash never leaves a truncated state file behind.
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        write_json_atomic(&Self::path(output_dir), self)
+    }
+}
+
+/// Write `value` as pretty JSON to a sibling temporary file, sync it and
+/// rename it over `path`.
+pub fn write_json_atomic<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+
+    let mut file =
+        fs::File::create(&tmp_path).with_context(|| format!("creating {}", tmp_path.display()))?;
+    file.write_all(serde_json::to_string_pretty(value)?.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path).with_context(|| format!("replacing {}", path.display()))?;
+
+    Ok(())
+}
@@ -0,0 +1,582 @@
+use anyhow::{anyhow, bail, Context, Result};
+use ethers::types::{Address, H256, U256};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use crate::segments::Compression;
+
+// Defaults match the 0G mainnet deployment
+pub const DEFAULT_RPC_URL: &str = "https://evmrpc.0g.ai";
+pub const DEFAULT_CONTRACT: &str = "0x064c3e0a900743d9ac87c778d2f6d3d5819d4f23";
+pub const DEFAULT_STARTING_BLOCK: u64 = 6914309;
+pub const DEFAULT_BATCH_SIZE: u64 = 1000;
+pub const DEFAULT_MAX_BATCH_SIZE: u64 = 10000;
+pub const DEFAULT_CONCURRENCY: usize = 4;
+pub const DEFAULT_OUTPUT_DIR: &str = "output";
+pub const DEFAULT_CONFIRMATIONS: u64 = 0;
+pub const DEFAULT_SEGMENT_BLOCKS: u64 = 100_000;
+/// 0G mainnet
+pub const DEFAULT_CHAIN_ID: u64 = 16661;
+
+pub const USAGE: &str = "NeuroLend indexer for the 0G network
+
+Usage:
+  neurolend_indexer [follow] [options]   Index historical blocks, then follow the chain
+  neurolend_indexer backfill --from <block> --to <block> [options]
+                                         Re-index a range of already indexed blocks
+  neurolend_indexer reindex --loan <id> [options]
+                                         Re-index every event of one loan
+  neurolend_indexer status [options]     Print the checkpoint and outstanding gaps
+  neurolend_indexer verify [--export <file>] [options]
+                                         Compare indexed loan state with the contract's
+                                         views at the last indexed block
+  neurolend_indexer doctor [--export <file>] [options]
+                                         Check the RPC endpoints, contract and storage
+
+Backfill and reindex replace what is stored for their blocks or loan, so they
+can be re-run safely. They never move the checkpoint. Verify exits non-zero if
+anything differs, and doctor if any check fails; --export writes their full
+report as JSON.
+
+Options:
+  --config <file>          TOML config file (env: INDEXER_CONFIG)
+  --rpc-url <url>[,<url>]  JSON-RPC endpoints, best first; repeat or comma-separate
+                           for failover (env: RPC_URL)
+  --ws-url <url>           WebSocket endpoint to follow the chain with eth_subscribe
+                           instead of polling (env: WS_URL)
+  --contract <address>     NeuroLend contract address (env: CONTRACT_ADDRESS)
+  --chain-id <id>          Chain the endpoints must serve (env: CHAIN_ID, default 16661)
+  --code-hash <hash>       keccak256 of the contract's deployed bytecode, checked by
+                           doctor (env: CODE_HASH)
+  --start-block <number>   First block to index (env: STARTING_BLOCK)
+  --batch-size <blocks>    Initial blocks per eth_getLogs request (env: BATCH_SIZE)
+  --max-batch-size <n>     Upper bound the batch size can grow to (env: MAX_BATCH_SIZE)
+  --concurrency <n>        eth_getLogs requests in flight during backfill (env: CONCURRENCY)
+  --output-dir <dir>       Where event segments are written (env: OUTPUT_DIR)
+  --segment-blocks <n>     Blocks per output segment file (env: SEGMENT_BLOCKS)
+  --compression <none|zstd>
+                           How finished segments are stored (env: COMPRESSION, default none)
+  --confirmations <n>      Blocks to wait behind the head before indexing (env: CONFIRMATIONS)
+  --sink <files|postgres>  Where events are stored (env: SINK, default files)
+  --database-url <url>     Postgres connection string for the postgres sink (env: DATABASE_URL)
+  --index-calls            Also record every transaction sent to the contract, reverted
+                           ones included; fetches every block (env: INDEX_CALLS)
+  --index-transfers        Also record ERC20 transfers to and from the contract
+                           (env: INDEX_TRANSFERS)
+  --snapshot-interval <n>  Every n blocks, record the health factor, prices, default
+                           status and interest of every active loan (env:
+                           SNAPSHOT_INTERVAL, default 0 = off)
+  --protobuf               Also write the segments as length-delimited contract.v1
+                           Events and Calls messages, like the Substreams module
+                           emits, under <output-dir>/protobuf (env: PROTOBUF)
+  --metrics-addr <addr>    Serve Prometheus metrics at http://<addr>/metrics, e.g.
+                           0.0.0.0:9090 (env: METRICS_ADDR)
+  -h, --help               Print this help
+
+Command line flags override environment variables, which override the config file.";
+
+/// What the binary was asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Index from the checkpoint to the head, then follow the chain
+    Follow,
+    /// Re-index `from..=to`, which must be behind the checkpoint
+    Backfill {
+        from: u64,
+        to: u64,
+    },
+    /// Re-index every event of one loan up to the checkpoint
+    Reindex {
+        loan: U256,
+    },
+    Status,
+    /// Diff the state rebuilt from stored events against the contract,
+    /// optionally writing the report to `export`
+    Verify {
+        export: Option<PathBuf>,
+    },
+    /// Check the endpoints, contract and storage, optionally writing the
+    /// report to `export`
+    Doctor {
+        export: Option<PathBuf>,
+    },
+}
+
+impl Command {
+    /// Take the subcommand and its own flags off the front of `args`,
+    /// leaving the shared options
+    pub fn parse(args: &mut Vec<String>) -> Result<Self> {
+        let name = match args.first().map(String::as_str) {
+            Some(name @ ("follow" | "backfill" | "reindex" | "status" | "verify" | "doctor")) => {
+                name.to_string()
+            }
+            _ => return Ok(Command::Follow),
+        };
+        args.remove(0);
+
+        match name.as_str() {
+            "backfill" => {
+                let from = take_flag(args, "--from")?
+                    .ok_or_else(|| anyhow!("backfill needs --from\n\n{}", USAGE))?;
+                let to = take_flag(args, "--to")?
+                    .ok_or_else(|| anyhow!("backfill needs --to\n\n{}", USAGE))?;
+                let (from, to) = (parse_number("--from", &from)?, parse_number("--to", &to)?);
+                if from > to {
+                    bail!("--from {} is after --to {}", from, to);
+                }
+                Ok(Command::Backfill { from, to })
+            }
+            "reindex" => {
+                let loan = take_flag(args, "--loan")?
+                    .ok_or_else(|| anyhow!("reindex needs --loan\n\n{}", USAGE))?;
+                let loan = U256::from_dec_str(loan.trim())
+                    .map_err(|e| anyhow!("invalid loan id {:?}: {}", loan, e))?;
+                Ok(Command::Reindex { loan })
+            }
+            "status" => Ok(Command::Status),
+            "verify" => Ok(Command::Verify {
+                export: take_flag(args, "--export")?.map(PathBuf::from),
+            }),
+            "doctor" => Ok(Command::Doctor {
+                export: take_flag(args, "--export")?.map(PathBuf::from),
+            }),
+            _ => Ok(Command::Follow),
+        }
+    }
+}
+
+/// Storage backend for indexed events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    Files,
+    Postgres,
+}
+
+impl std::str::FromStr for SinkKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "files" => Ok(SinkKind::Files),
+            "postgres" => Ok(SinkKind::Postgres),
+            other => bail!("unknown sink {:?}, expected files or postgres", other),
+        }
+    }
+}
+
+/// Resolved indexer settings.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// HTTP endpoints for the provider pool, never empty
+    pub rpc_urls: Vec<String>,
+    /// Follow new blocks over this WebSocket instead of polling `rpc_urls`
+    pub ws_url: Option<String>,
+    pub contract_address: Address,
+    /// `eth_chainId` every endpoint should report
+    pub chain_id: u64,
+    /// Hash of the contract bytecode `doctor` expects, if pinned
+    pub code_hash: Option<H256>,
+    pub starting_block: u64,
+    pub batch_size: u64,
+    pub max_batch_size: u64,
+    pub concurrency: usize,
+    pub output_dir: PathBuf,
+    pub segment_blocks: u64,
+    pub compression: Compression,
+    /// Only blocks at least this far behind the head are indexed
+    pub confirmations: u64,
+    pub sink: SinkKind,
+    pub database_url: Option<String>,
+    /// Fetch full blocks and receipts to record calls to the contract
+    pub index_calls: bool,
+    /// Fetch ERC20 `Transfer` logs with the contract as sender or recipient
+    pub index_transfers: bool,
+    /// Snapshot active loans every this many blocks, 0 for never
+    pub snapshot_interval: u64,
+    /// Mirror the file sink's segments as `contract.v1` protobuf messages
+    pub protobuf: bool,
+    /// Where to serve `/metrics`, if anywhere
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+/// One layer of settings. Every source (file, env, flags) parses into this
+/// and layers are applied on top of the defaults in order.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Overrides {
+    rpc_url: Option<RpcUrls>,
+    ws_url: Option<String>,
+    contract_address: Option<String>,
+    chain_id: Option<u64>,
+    code_hash: Option<String>,
+    starting_block: Option<u64>,
+    batch_size: Option<u64>,
+    max_batch_size: Option<u64>,
+    concurrency: Option<u64>,
+    output_dir: Option<PathBuf>,
+    segment_blocks: Option<u64>,
+    compression: Option<String>,
+    confirmations: Option<u64>,
+    sink: Option<String>,
+    database_url: Option<String>,
+    index_calls: Option<bool>,
+    index_transfers: Option<bool>,
+    snapshot_interval: Option<u64>,
+    protobuf: Option<bool>,
+    metrics_addr: Option<String>,
+}
+
+/// `rpc_url` as a single string (possibly comma-separated) or a list
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RpcUrls {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl RpcUrls {
+    fn into_list(self) -> Vec<String> {
+        let urls = match self {
+            RpcUrls::One(url) => vec![url],
+            RpcUrls::Many(urls) => urls,
+        };
+        urls.iter()
+            .flat_map(|u| u.split(','))
+            .map(str::trim)
+            .filter(|u| !u.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            rpc_urls: vec![DEFAULT_RPC_URL.to_string()],
+            ws_url: None,
+            contract_address: DEFAULT_CONTRACT.parse().expect("valid default address"),
+            chain_id: DEFAULT_CHAIN_ID,
+            code_hash: None,
+            starting_block: DEFAULT_STARTING_BLOCK,
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            concurrency: DEFAULT_CONCURRENCY,
+            output_dir: PathBuf::from(DEFAULT_OUTPUT_DIR),
+            segment_blocks: DEFAULT_SEGMENT_BLOCKS,
+            compression: Compression::None,
+            confirmations: DEFAULT_CONFIRMATIONS,
+            sink: SinkKind::Files,
+            database_url: None,
+            index_calls: false,
+            index_transfers: false,
+            snapshot_interval: 0,
+            protobuf: false,
+            metrics_addr: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load from the process arguments and environment. Prints the usage and
+    /// exits on `--help`.
+    pub fn load() -> Result<(Command, Self)> {
+        let mut args: Vec<String> = std::env::args().skip(1).collect();
+        if args.iter().any(|a| a == "-h" || a == "--help") {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+
+        let command = Command::parse(&mut args)?;
+        Ok((
+            command,
+            Self::from_sources(&args, |key| std::env::var(key).ok())?,
+        ))
+    }
+
+    /// Resolve settings from the given flags and environment lookup.
+    pub fn from_sources(args: &[String], env: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let (config_file, flags) = parse_args(args)?;
+
+        let mut config = Config::default();
+        if let Some(path) = config_file.or_else(|| env("INDEXER_CONFIG").map(PathBuf::from)) {
+            config.apply(read_file(&path)?)?;
+        }
+        config.apply(from_env(&env)?)?;
+        config.apply(flags)?;
+
+        if config.batch_size == 0 {
+            bail!("batch size must be greater than zero");
+        }
+        if config.concurrency == 0 {
+            bail!("concurrency must be greater than zero");
+        }
+        if config.segment_blocks == 0 {
+            bail!("segment blocks must be greater than zero");
+        }
+        config.max_batch_size = config.max_batch_size.max(config.batch_size);
+        if config.sink == SinkKind::Postgres && config.database_url.is_none() {
+            bail!("the postgres sink needs --database-url or DATABASE_URL");
+        }
+        if config.sink == SinkKind::Postgres && config.protobuf {
+            bail!(
+                "protobuf output is written from the files sink's segments, it needs --sink files"
+            );
+        }
+        Ok(config)
+    }
+
+    fn apply(&mut self, overrides: Overrides) -> Result<()> {
+        if let Some(rpc_url) = overrides.rpc_url {
+            let urls = rpc_url.into_list();
+            if urls.is_empty() {
+                bail!("rpc_url must name at least one endpoint");
+            }
+            self.rpc_urls = urls;
+        }
+        if let Some(ws_url) = overrides.ws_url {
+            self.ws_url = Some(ws_url);
+        }
+        if let Some(contract) = overrides.contract_address {
+            self.contract_address = contract
+                .parse()
+                .map_err(|e| anyhow!("invalid contract address {}: {}", contract, e))?;
+        }
+        if let Some(chain_id) = overrides.chain_id {
+            self.chain_id = chain_id;
+        }
+        if let Some(hash) = overrides.code_hash {
+            self.code_hash = Some(
+                hash.parse()
+                    .map_err(|e| anyhow!("invalid code hash {}: {}", hash, e))?,
+            );
+        }
+        if let Some(block) = overrides.starting_block {
+            self.starting_block = block;
+        }
+        if let Some(batch_size) = overrides.batch_size {
+            self.batch_size = batch_size;
+        }
+        if let Some(max_batch_size) = overrides.max_batch_size {
+            self.max_batch_size = max_batch_size;
+        }
+        if let Some(concurrency) = overrides.concurrency {
+            self.concurrency = concurrency as usize;
+        }
+        if let Some(output_dir) = overrides.output_dir {
+            self.output_dir = output_dir;
+        }
+        if let Some(segment_blocks) = overrides.segment_blocks {
+            self.segment_blocks = segment_blocks;
+        }
+        if let Some(compression) = overrides.compression {
+            self.compression = compression.parse()?;
+        }
+        if let Some(confirmations) = overrides.confirmations {
+            self.confirmations = confirmations;
+        }
+        if let Some(sink) = overrides.sink {
+            self.sink = sink.parse()?;
+        }
+        if let Some(database_url) = overrides.database_url {
+            self.database_url = Some(database_url);
+        }
+        if let Some(index_calls) = overrides.index_calls {
+            self.index_calls = index_calls;
+        }
+        if let Some(index_transfers) = overrides.index_transfers {
+            self.index_transfers = index_transfers;
+        }
+        if let Some(snapshot_interval) = overrides.snapshot_interval {
+            self.snapshot_interval = snapshot_interval;
+        }
+        if let Some(protobuf) = overrides.protobuf {
+            self.protobuf = protobuf;
+        }
+        if let Some(addr) = overrides.metrics_addr {
+            self.metrics_addr = Some(
+                addr.parse()
+                    .map_err(|e| anyhow!("invalid metrics address {}: {}", addr, e))?,
+            );
+        }
+        Ok(())
+    }
+}
+
+fn read_file(path: &Path) -> Result<Overrides> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("reading config file {}", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("parsing config file {}", path.display()))
+}
+
+fn from_env(env: &impl Fn(&str) -> Option<String>) -> Result<Overrides> {
+    Ok(Overrides {
+        rpc_url: env("RPC_URL").map(RpcUrls::One),
+        ws_url: env("WS_URL"),
+        contract_address: env("CONTRACT_ADDRESS"),
+        chain_id: env("CHAIN_ID")
+            .map(|v| parse_number("CHAIN_ID", &v))
+            .transpose()?,
+        code_hash: env("CODE_HASH"),
+        starting_block: env("STARTING_BLOCK")
+            .map(|v| parse_number("STARTING_BLOCK", &v))
+            .transpose()?,
+        batch_size: env("BATCH_SIZE")
+            .map(|v| parse_number("BATCH_SIZE", &v))
+            .transpose()?,
+        max_batch_size: env("MAX_BATCH_SIZE")
+            .map(|v| parse_number("MAX_BATCH_SIZE", &v))
+            .transpose()?,
+        concurrency: env("CONCURRENCY")
+            .map(|v| parse_number("CONCURRENCY", &v))
+            .transpose()?,
+        output_dir: env("OUTPUT_DIR").map(PathBuf::from),
+        segment_blocks: env("SEGMENT_BLOCKS")
+            .map(|v| parse_number("SEGMENT_BLOCKS", &v))
+            .transpose()?,
+        compression: env("COMPRESSION"),
+        confirmations: env("CONFIRMATIONS")
+            .map(|v| parse_number("CONFIRMATIONS", &v))
+            .transpose()?,
+        sink: env("SINK"),
+        database_url: env("DATABASE_URL"),
+        index_calls: env("INDEX_CALLS")
+            .map(|v| parse_bool("INDEX_CALLS", &v))
+            .transpose()?,
+        index_transfers: env("INDEX_TRANSFERS")
+            .map(|v| parse_bool("INDEX_TRANSFERS", &v))
+            .transpose()?,
+        snapshot_interval: env("SNAPSHOT_INTERVAL")
+            .map(|v| parse_number("SNAPSHOT_INTERVAL", &v))
+            .transpose()?,
+        protobuf: env("PROTOBUF")
+            .map(|v| parse_bool("PROTOBUF", &v))
+            .transpose()?,
+        metrics_addr: env("METRICS_ADDR"),
+    })
+}
+
+fn parse_args(args: &[String]) -> Result<(Option<PathBuf>, Overrides)> {
+    let mut config_file = None;
+    let mut flags = Overrides::default();
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| anyhow!("{} needs a value\n\n{}", flag, USAGE))
+        };
+        match flag.as_str() {
+            "--config" => config_file = Some(PathBuf::from(value()?)),
+            "--rpc-url" => {
+                let url = value()?;
+                flags.rpc_url = Some(match flags.rpc_url.take() {
+                    Some(RpcUrls::One(first)) => RpcUrls::Many(vec![first, url]),
+                    Some(RpcUrls::Many(mut urls)) => {
+                        urls.push(url);
+                        RpcUrls::Many(urls)
+                    }
+                    None => RpcUrls::One(url),
+                });
+            }
+            "--ws-url" => flags.ws_url = Some(value()?),
+            "--contract" => flags.contract_address = Some(value()?),
+            "--chain-id" => flags.chain_id = Some(parse_number(flag, &value()?)?),
+            "--code-hash" => flags.code_hash = Some(value()?),
+            "--start-block" => flags.starting_block = Some(parse_number(flag, &value()?)?),
+            "--batch-size" => flags.batch_size = Some(parse_number(flag, &value()?)?),
+            "--max-batch-size" => flags.max_batch_size = Some(parse_number(flag, &value()?)?),
+            "--concurrency" => flags.concurrency = Some(parse_number(flag, &value()?)?),
+            "--output-dir" => flags.output_dir = Some(PathBuf::from(value()?)),
+            "--segment-blocks" => flags.segment_blocks = Some(parse_number(flag, &value()?)?),
+            "--compression" => flags.compression = Some(value()?),
+            "--confirmations" => flags.confirmations = Some(parse_number(flag, &value()?)?),
+            "--sink" => flags.sink = Some(value()?),
+            "--database-url" => flags.database_url = Some(value()?),
+            "--index-calls" => flags.index_calls = Some(true),
+            "--index-transfers" => flags.index_transfers = Some(true),
+            "--snapshot-interval" => flags.snapshot_interval = Some(parse_number(flag, &value()?)?),
+            "--protobuf" => flags.protobuf = Some(true),
+            "--metrics-addr" => flags.metrics_addr = Some(value()?),
+            other => bail!("unknown argument {}\n\n{}", other, USAGE),
+        }
+    }
+
+    Ok((config_file, flags))
+}
+
+/// Remove `flag <value>` from `args`, wherever it is
+fn take_flag(args: &mut Vec<String>, flag: &str) -> Result<Option<String>> {
+    let Some(index) = args.iter().position(|a| a == flag) else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        bail!("{} needs a value\n\n{}", flag, USAGE);
+    }
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(value))
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u64> {
+    value
+        .trim()
+        .parse()
+        .with_context(|| format!("{} must be a non-negative integer, got {:?}", name, value))
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    match value.trim() {
+        "true" | "1" => Ok(true),
+        "false" | "0" | "" => Ok(false),
+        other => bail!("{} must be true or false, got {:?}", name, other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_override_env_over_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("indexer.toml");
+        std::fs::write(
+            &file,
+            r#"
+                rpc_url = ["https://a.example", "https://b.example"]
+                starting_block = 1
+                batch_size = 10
+                confirmations = 3
+            "#,
+        )
+        .unwrap();
+        let env: HashMap<&str, &str> = HashMap::from([
+            ("INDEXER_CONFIG", file.to_str().unwrap()),
+            ("STARTING_BLOCK", "2"),
+            ("BATCH_SIZE", "20"),
+        ]);
+        let env = |key: &str| env.get(key).map(|v| v.to_string());
+
+        let config = Config::from_sources(&args(&["--start-block", "3"]), env).unwrap();
+        assert_eq!(config.starting_block, 3);
+        assert_eq!(config.batch_size, 20);
+        assert_eq!(config.confirmations, 3);
+        assert_eq!(config.rpc_urls, ["https://a.example", "https://b.example"]);
+        assert_eq!(config.chain_id, DEFAULT_CHAIN_ID);
+        assert_eq!(config.contract_address, DEFAULT_CONTRACT.parse().unwrap());
+
+        let config = Config::from_sources(&[], env).unwrap();
+        assert_eq!(config.starting_block, 2);
+
+        let config = Config::from_sources(&[], |_| None).unwrap();
+        assert_eq!(config.starting_block, DEFAULT_STARTING_BLOCK);
+        assert_eq!(config.batch_size, DEFAULT_BATCH_SIZE);
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{Context, Result};
+use ethers::abi::{Abi, Event, Function, RawLog, StateMutability, Token};
+use ethers::types::{Log, H256, I256};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::event_signatures::NEUROLEND_ABI;
+
+/// Decodes NeuroLend logs with the contract ABI into `decoded_data` objects.
+/// Keys are the ABI parameter names (`loanId`, `borrower`, `tokenAddress`,
+/// ...), which are the field names the subgraph and the API use.
+#[derive(Debug, Clone)]
+pub struct EventDecoder {
+    events: HashMap<H256, Event>,
+}
+
+impl EventDecoder {
+    pub fn neurolend() -> Result<Self> {
+        Self::from_abi_json(NEUROLEND_ABI)
+    }
+
+    pub fn from_abi_json(json: &str) -> Result<Self> {
+        let abi: Abi = serde_json::from_str(json).context("parsing contract ABI")?;
+        let events = abi
+            .events()
+            .map(|event| (event.signature(), event.clone()))
+            .collect();
+        Ok(Self { events })
+    }
+
+    /// Decode the log's topics and data. `None` if topic0 is not a known
+    /// event, an error if it is but the log does not match the ABI.
+    pub fn decode(&self, log: &Log) -> Option<Result<Map<String, Value>>> {
+        let event = self.events.get(log.topics.first()?)?;
+        let raw = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+
+        Some(
+            event
+                .parse_log(raw)
+                .map(|parsed| {
+                    parsed
+                        .params
+                        .into_iter()
+                        .map(|param| (param.name, token_to_json(param.value)))
+                        .collect()
+                })
+                .with_context(|| format!("decoding {} log", event.name)),
+        )
+    }
+}
+
+/// Decodes calldata of the contract's state-changing functions. Overloads
+/// (`createLoanOffer` with 6 or 9 arguments, ...) are told apart by their
+/// selector.
+#[derive(Debug, Clone)]
+pub struct CallDecoder {
+    functions: HashMap<[u8; 4], Function>,
+}
+
+impl CallDecoder {
+    pub fn neurolend() -> Result<Self> {
+        Self::from_abi_json(NEUROLEND_ABI)
+    }
+
+    pub fn from_abi_json(json: &str) -> Result<Self> {
+        let abi: Abi = serde_json::from_str(json).context("parsing contract ABI")?;
+        let functions = abi
+            .functions()
+            .filter(|f| {
+                matches!(
+                    f.state_mutability,
+                    StateMutability::NonPayable | StateMutability::Payable
+                )
+            })
+            .map(|f| (f.short_signature(), f.clone()))
+            .collect();
+        Ok(Self { functions })
+    }
+
+    /// The function `input` calls and its decoded arguments. `None` if the
+    /// selector is not a known function, an error if the arguments do not
+    /// match it. Argument names lose their leading underscores
+    /// (`_tokenAddress` becomes `tokenAddress`), like the event fields.
+    pub fn decode(&self, input: &[u8]) -> Option<(&Function, Result<Map<String, Value>>)> {
+        let selector: [u8; 4] = input.get(..4)?.try_into().ok()?;
+        let function = self.functions.get(&selector)?;
+
+        let decoded = function
+            .decode_input(&input[4..])
+            .map(|tokens| {
+                function
+                    .inputs
+                    .iter()
+                    .zip(tokens)
+                    .enumerate()
+                    .map(|(i, (param, token))| {
+                        let name = param.name.trim_start_matches('_');
+                        let name = if name.is_empty() {
+                            format!("arg{}", i)
+                        } else {
+                            name.to_string()
+                        };
+                        (name, token_to_json(token))
+                    })
+                    .collect()
+            })
+            .with_context(|| format!("decoding {} call", function.name));
+        Some((function, decoded))
+    }
+}
+
+/// Integers become decimal strings (they overflow JSON numbers), addresses
+/// and bytes 0x-prefixed lowercase hex
+pub(crate) fn token_to_json(token: Token) -> Value {
+    match token {
+        Token::Address(address) => Value::String(format!("0x{:x}", address)),
+        Token::Uint(value) => Value::String(value.to_string()),
+        Token::Int(value) => Value::String(I256::from_raw(value).to_string()),
+        Token::Bool(value) => Value::Bool(value),
+        Token::String(value) => Value::String(value),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => {
+            Value::String(format!("0x{}", hex::encode(bytes)))
+        }
+        Token::Array(items) | Token::FixedArray(items) | Token::Tuple(items) => {
+            Value::Array(items.into_iter().map(token_to_json).collect())
+        }
+    }
+}
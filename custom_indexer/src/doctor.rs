@@ -0,0 +1,594 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::types::{Filter, H256};
+use ethers::utils::keccak256;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::batch::is_range_too_large;
+use crate::config::{Config, SinkKind};
+use crate::postgres;
+use crate::rpc_pool::RpcPool;
+use crate::views::ContractViews;
+
+/// How far back `recentLogs` looks
+const RECENT_LOG_BLOCKS: u64 = 1000;
+/// `eth_getLogs` ranges tried, doubling from the first up to the last
+const FIRST_PROBED_RANGE: u64 = 1000;
+const MAX_PROBED_RANGE: u64 = 128_000;
+/// `eth_blockNumber` requests sent to each endpoint to find rate limits, and
+/// how many are in flight at once
+const RATE_PROBE_REQUESTS: usize = 50;
+const RATE_PROBE_CONCURRENCY: usize = 10;
+const RATE_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Works, but slower or with fewer features than it could
+    Warning,
+    /// The indexer will not run correctly until this is fixed
+    Failed,
+    /// Not run, because it is not configured or an earlier check failed
+    Skipped,
+}
+
+/// Outcome of one diagnostic
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Check {
+    pub name: &'static str,
+    /// The RPC endpoint checked, for per-endpoint checks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    pub status: Status,
+    pub message: String,
+    /// What was measured, for scripts
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            endpoint: None,
+            status,
+            message: message.into(),
+            details: Value::Null,
+        }
+    }
+
+    fn endpoint(mut self, url: &str) -> Self {
+        self.endpoint = Some(url.to_string());
+        self
+    }
+
+    fn details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+/// Outcome of a `doctor` run
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub checked_at: DateTime<Utc>,
+    pub contract: String,
+    pub chain_id: u64,
+    /// Head block as seen through the pool, if any endpoint answered
+    pub head_block: Option<u64>,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn count(&self, status: Status) -> usize {
+        self.checks.iter().filter(|c| c.status == status).count()
+    }
+
+    /// One line per check, then a summary
+    pub fn print(&self) {
+        for check in &self.checks {
+            let symbol = match check.status {
+                Status::Ok => "✅",
+                Status::Warning => "⚠️ ",
+                Status::Failed => "❌",
+                Status::Skipped => "➖",
+            };
+            match &check.endpoint {
+                Some(endpoint) => {
+                    println!("{} {} {}: {}", symbol, check.name, endpoint, check.message)
+                }
+                None => println!("{} {}: {}", symbol, check.name, check.message),
+            }
+        }
+        println!(
+            "\n{} checks: {} failed, {} warnings, {} skipped",
+            self.checks.len(),
+            self.count(Status::Failed),
+            self.count(Status::Warning),
+            self.count(Status::Skipped)
+        );
+    }
+}
+
+/// Checks that the configured endpoints, contract and storage are fit to
+/// index from. Every check runs even if others fail.
+pub struct Doctor {
+    config: Config,
+    pool: RpcPool,
+    client: reqwest::Client,
+}
+
+impl Doctor {
+    pub fn new(config: Config) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(RATE_PROBE_TIMEOUT)
+            .build()
+            .context("building HTTP client")?;
+        Ok(Self {
+            pool: RpcPool::new(&config.rpc_urls)?,
+            config,
+            client,
+        })
+    }
+
+    pub async fn run(&self) -> Report {
+        let mut checks = Vec::new();
+        for url in &self.config.rpc_urls {
+            checks.extend(self.check_endpoint(url).await);
+        }
+
+        let provider = self.pool.provider();
+        let head_block = match provider.get_block_number().await {
+            Ok(head) => Some(head.as_u64()),
+            Err(e) => {
+                checks.push(Check::new(
+                    "head",
+                    Status::Failed,
+                    format!("no endpoint returned the head block: {}", e),
+                ));
+                None
+            }
+        };
+        match head_block {
+            Some(head) => {
+                let (code, hash) = self.check_code(head).await;
+                checks.push(code);
+                checks.push(self.check_code_hash(hash));
+                checks.push(self.check_owner(head).await);
+                checks.push(self.check_recent_logs(head).await);
+            }
+            None => {
+                for name in ["contractCode", "codeHash", "owner", "recentLogs"] {
+                    checks.push(Check::new(name, Status::Skipped, "no head block"));
+                }
+            }
+        }
+        checks.push(self.check_archive().await);
+        checks.push(self.check_storage().await);
+
+        Report {
+            checked_at: Utc::now(),
+            contract: format!("0x{:x}", self.config.contract_address),
+            chain_id: self.config.chain_id,
+            head_block,
+            checks,
+        }
+    }
+
+    /// Chain ID and head of one endpoint on its own, then its `eth_getLogs`
+    /// range limit and rate limit
+    async fn check_endpoint(&self, url: &str) -> Vec<Check> {
+        let provider = match Provider::<Http>::try_from(url) {
+            Ok(provider) => provider,
+            Err(e) => {
+                return vec![Check::new("endpoint", Status::Failed, e.to_string()).endpoint(url)]
+            }
+        };
+
+        let started = Instant::now();
+        let (chain_id, head) = match (
+            provider.get_chainid().await,
+            provider.get_block_number().await,
+        ) {
+            (Ok(chain_id), Ok(head)) => (chain_id.as_u64(), head.as_u64()),
+            (Err(e), _) | (_, Err(e)) => {
+                let mut checks =
+                    vec![Check::new("endpoint", Status::Failed, e.to_string()).endpoint(url)];
+                for name in ["logRange", "rateLimit"] {
+                    checks.push(
+                        Check::new(name, Status::Skipped, "endpoint unreachable").endpoint(url),
+                    );
+                }
+                return checks;
+            }
+        };
+        let latency = started.elapsed();
+
+        let details = json!({
+            "chainId": chain_id,
+            "headBlock": head,
+            "latencyMs": latency.as_millis() as u64,
+        });
+        let endpoint = if chain_id == self.config.chain_id {
+            Check::new(
+                "endpoint",
+                Status::Ok,
+                format!(
+                    "chain ID {}, block {} ({}ms)",
+                    chain_id,
+                    head,
+                    latency.as_millis()
+                ),
+            )
+        } else {
+            Check::new(
+                "endpoint",
+                Status::Failed,
+                format!(
+                    "chain ID {}, expected {}; set chain_id if this is the right network",
+                    chain_id, self.config.chain_id
+                ),
+            )
+        };
+
+        vec![
+            endpoint.endpoint(url).details(details),
+            self.check_log_range(&provider, head).await.endpoint(url),
+            self.check_rate_limit(url).await.endpoint(url),
+        ]
+    }
+
+    /// Widest `eth_getLogs` range ending at `head` the endpoint accepts, up
+    /// to `MAX_PROBED_RANGE`
+    async fn check_log_range(&self, provider: &Provider<Http>, head: u64) -> Check {
+        let mut accepted = None;
+        let mut range = FIRST_PROBED_RANGE;
+        let rejection = loop {
+            if range > MAX_PROBED_RANGE || range > head + 1 {
+                break None;
+            }
+            let filter = Filter::new()
+                .address(self.config.contract_address)
+                .from_block(head + 1 - range)
+                .to_block(head);
+            match provider.get_logs(&filter).await {
+                Ok(_) => accepted = Some(range),
+                Err(e) => break Some(e.to_string()),
+            }
+            range *= 2;
+        };
+
+        let details = json!({
+            "maxBlocks": accepted,
+            "rejectedBlocks": rejection.as_ref().map(|_| range),
+            "error": rejection,
+        });
+        let check = match (accepted, rejection) {
+            (_, Some(error)) if !is_range_too_large(&error) => Check::new(
+                "logRange",
+                Status::Warning,
+                format!("eth_getLogs over {} blocks failed: {}", range, error),
+            ),
+            (None, Some(error)) => Check::new(
+                "logRange",
+                Status::Warning,
+                format!(
+                    "rejects even {} blocks ({}), ranges will be split until accepted",
+                    range, error
+                ),
+            ),
+            (Some(max), Some(_)) if max < self.config.batch_size => Check::new(
+                "logRange",
+                Status::Warning,
+                format!(
+                    "accepts {} blocks but not {}, below batch_size {}; ranges will be split",
+                    max, range, self.config.batch_size
+                ),
+            ),
+            (Some(max), Some(_)) => Check::new(
+                "logRange",
+                Status::Ok,
+                format!(
+                    "accepts {} blocks but not {}; keep max_batch_size at most {}",
+                    max, range, max
+                ),
+            ),
+            (Some(max), None) => Check::new(
+                "logRange",
+                Status::Ok,
+                format!("accepts at least {} blocks", max),
+            ),
+            (None, None) => Check::new(
+                "logRange",
+                Status::Skipped,
+                format!("chain is shorter than {} blocks", FIRST_PROBED_RANGE),
+            ),
+        };
+        check.details(details)
+    }
+
+    /// Send a burst of cheap requests and count the rate limited ones
+    async fn check_rate_limit(&self, url: &str) -> Check {
+        let started = Instant::now();
+        let outcomes: Vec<std::result::Result<(), (bool, String)>> =
+            stream::iter(0..RATE_PROBE_REQUESTS)
+                .map(|id| self.probe(url, id))
+                .buffer_unordered(RATE_PROBE_CONCURRENCY)
+                .collect()
+                .await;
+        let elapsed = started.elapsed();
+
+        let limited = outcomes
+            .iter()
+            .filter(|o| matches!(o, Err((true, _))))
+            .count();
+        let errors: Vec<&String> = outcomes
+            .iter()
+            .filter_map(|o| match o {
+                Err((false, error)) => Some(error),
+                _ => None,
+            })
+            .collect();
+        let per_second = RATE_PROBE_REQUESTS as f64 / elapsed.as_secs_f64().max(0.001);
+        let details = json!({
+            "requests": RATE_PROBE_REQUESTS,
+            "concurrency": RATE_PROBE_CONCURRENCY,
+            "rateLimited": limited,
+            "failed": errors.len(),
+            "seconds": elapsed.as_secs_f64(),
+            "perSecond": per_second,
+        });
+
+        let check = if limited > 0 {
+            Check::new(
+                "rateLimit",
+                Status::Warning,
+                format!(
+                    "{} of {} requests rate limited at {:.0} requests/s; lower concurrency",
+                    limited, RATE_PROBE_REQUESTS, per_second
+                ),
+            )
+        } else if let Some(error) = errors.first() {
+            Check::new(
+                "rateLimit",
+                Status::Warning,
+                format!(
+                    "{} of {} requests failed, e.g. {}",
+                    errors.len(),
+                    RATE_PROBE_REQUESTS,
+                    error
+                ),
+            )
+        } else {
+            Check::new(
+                "rateLimit",
+                Status::Ok,
+                format!(
+                    "{} requests at {:.0}/s, none rate limited",
+                    RATE_PROBE_REQUESTS, per_second
+                ),
+            )
+        };
+        check.details(details)
+    }
+
+    /// One `eth_blockNumber`. The error says whether it was rate limited.
+    async fn probe(&self, url: &str, id: usize) -> std::result::Result<(), (bool, String)> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "eth_blockNumber",
+            "params": [],
+        });
+        let response = self
+            .client
+            .post(url)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| (false, e.to_string()))?;
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err((true, "HTTP 429".to_string()));
+        }
+        let status = response.status();
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| (false, format!("HTTP {}: {}", status, e)))?;
+        match body.get("error") {
+            Some(error) => {
+                let message = error["message"].as_str().unwrap_or_default().to_lowercase();
+                let limited = ["rate", "limit", "too many requests", "capacity"]
+                    .iter()
+                    .any(|p| message.contains(p));
+                Err((limited, error.to_string()))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The contract is deployed. Returns the hash of its bytecode.
+    async fn check_code(&self, head: u64) -> (Check, Option<H256>) {
+        let contract = self.config.contract_address;
+        match self
+            .pool
+            .provider()
+            .get_code(contract, Some(head.into()))
+            .await
+        {
+            Ok(code) if code.is_empty() => (
+                Check::new(
+                    "contractCode",
+                    Status::Failed,
+                    format!("no contract at 0x{:x} at block {}", contract, head),
+                ),
+                None,
+            ),
+            Ok(code) => {
+                let hash = H256(keccak256(&code));
+                let check = Check::new(
+                    "contractCode",
+                    Status::Ok,
+                    format!("{} bytes at 0x{:x}", code.len(), contract),
+                )
+                .details(json!({
+                    "size": code.len(),
+                    "codeHash": format!("0x{:x}", hash),
+                }));
+                (check, Some(hash))
+            }
+            Err(e) => (
+                Check::new("contractCode", Status::Failed, e.to_string()),
+                None,
+            ),
+        }
+    }
+
+    /// The bytecode is the build `code_hash` pins
+    fn check_code_hash(&self, hash: Option<H256>) -> Check {
+        match (hash, self.config.code_hash) {
+            (None, _) => Check::new("codeHash", Status::Skipped, "no contract code"),
+            (Some(hash), None) => Check::new(
+                "codeHash",
+                Status::Skipped,
+                format!("0x{:x}, set code_hash to pin it", hash),
+            ),
+            (Some(hash), Some(expected)) if hash == expected => {
+                Check::new("codeHash", Status::Ok, format!("0x{:x}", hash))
+            }
+            (Some(hash), Some(expected)) => Check::new(
+                "codeHash",
+                Status::Failed,
+                format!(
+                    "0x{:x}, expected 0x{:x}; the ABI may not match this build",
+                    hash, expected
+                ),
+            ),
+        }
+    }
+
+    /// `owner()` answers, which the views the indexer calls depend on too
+    async fn check_owner(&self, head: u64) -> Check {
+        let views = ContractViews::new(self.pool.clone(), self.config.contract_address);
+        let owner = views.call("owner", vec![], head).await.and_then(|tokens| {
+            tokens
+                .into_iter()
+                .next()
+                .and_then(|token| token.into_address())
+                .ok_or_else(|| anyhow!("owner() did not return an address"))
+        });
+        match owner {
+            Ok(owner) => Check::new("owner", Status::Ok, format!("0x{:x}", owner))
+                .details(json!({ "owner": format!("0x{:x}", owner) })),
+            Err(e) => Check::new("owner", Status::Failed, format!("{:#}", e)),
+        }
+    }
+
+    async fn check_recent_logs(&self, head: u64) -> Check {
+        let filter = Filter::new()
+            .address(self.config.contract_address)
+            .from_block(head.saturating_sub(RECENT_LOG_BLOCKS))
+            .to_block(head);
+        match self.pool.provider().get_logs(&filter).await {
+            Ok(logs) => {
+                let latest = logs
+                    .last()
+                    .and_then(|log| log.block_number)
+                    .map(|n| n.as_u64());
+                let message = match latest {
+                    Some(block) => format!(
+                        "{} logs in the last {} blocks, latest in block {}",
+                        logs.len(),
+                        RECENT_LOG_BLOCKS,
+                        block
+                    ),
+                    None => format!("no logs in the last {} blocks", RECENT_LOG_BLOCKS),
+                };
+                Check::new("recentLogs", Status::Ok, message)
+                    .details(json!({ "logs": logs.len(), "latestBlock": latest }))
+            }
+            Err(e) => Check::new("recentLogs", Status::Failed, e.to_string()),
+        }
+    }
+
+    /// The starting block and its state are still served. Snapshots call
+    /// views at old blocks, which pruned nodes cannot answer.
+    async fn check_archive(&self) -> Check {
+        let block = self.config.starting_block;
+        let provider = self.pool.provider();
+        match provider.get_block(block).await {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return Check::new(
+                    "archive",
+                    Status::Failed,
+                    format!("no block {}, the starting block", block),
+                )
+            }
+            Err(e) => return Check::new("archive", Status::Failed, e.to_string()),
+        }
+
+        match provider
+            .get_balance(self.config.contract_address, Some(block.into()))
+            .await
+        {
+            Ok(_) => Check::new(
+                "archive",
+                Status::Ok,
+                format!("state at starting block {} is available", block),
+            ),
+            Err(e) => {
+                let status = if self.config.snapshot_interval > 0 {
+                    Status::Failed
+                } else {
+                    Status::Warning
+                };
+                Check::new(
+                    "archive",
+                    status,
+                    format!(
+                        "no state at starting block {} ({}); snapshots of old blocks need an archive node",
+                        block, e
+                    ),
+                )
+            }
+        }
+    }
+
+    /// The configured sink can be written to
+    async fn check_storage(&self) -> Check {
+        match (self.config.sink, &self.config.database_url) {
+            (SinkKind::Postgres, Some(url)) => match postgres::check_writable(url).await {
+                Ok(()) => Check::new("storage", Status::Ok, "Postgres accepts writes"),
+                Err(e) => Check::new("storage", Status::Failed, format!("{:#}", e)),
+            },
+            _ => {
+                let dir = &self.config.output_dir;
+                let probe = dir.join(".doctor_probe");
+                let written = fs::create_dir_all(dir)
+                    .and_then(|()| fs::write(&probe, b"ok"))
+                    .and_then(|()| fs::remove_file(&probe));
+                match written {
+                    Ok(()) => Check::new(
+                        "storage",
+                        Status::Ok,
+                        format!("{} is writable", dir.display()),
+                    ),
+                    Err(e) => Check::new(
+                        "storage",
+                        Status::Failed,
+                        format!("{} is not writable: {}", dir.display(), e),
+                    ),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use ethers::prelude::*;
+use futures::future::BoxFuture;
+use futures::stream::{FuturesOrdered, StreamExt};
+use futures::FutureExt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::batch::is_range_too_large;
+use crate::rpc_pool::PoolProvider;
+
+/// Attempts per `eth_getLogs` call before the range is given up on
+pub const GET_LOGS_ATTEMPTS: u32 = 4;
+
+/// Outcome of fetching the logs for `from..=to`
+#[derive(Debug)]
+pub struct FetchedRange {
+    pub from: u64,
+    pub to: u64,
+    /// How many times the range had to be halved before the node accepted it
+    pub splits: u32,
+    /// Failed `eth_getLogs` attempts that were retried, over all its parts
+    pub retries: u32,
+    pub logs: Result<Vec<Log>, ProviderError>,
+    /// Hash of block `to` on the chain the logs were read from, if known
+    pub hash: Option<H256>,
+}
+
+impl FetchedRange {
+    pub fn blocks(&self) -> u64 {
+        self.to - self.from + 1
+    }
+}
+
+/// `eth_getLogs` for the contract over `from..=to`. Transient errors are
+/// retried with exponential backoff; a range the node rejects as too large
+/// is split in half until it is accepted. Owns everything it needs, so many
+/// can be in flight at once.
+///
+/// The hash of block `to` is read before and after the logs. If it changed,
+/// the chain reorganized under the request and the logs are fetched again,
+/// so the returned hash always belongs to the fork the logs came from.
+pub async fn fetch_range(
+    provider: Arc<PoolProvider>,
+    contract: Address,
+    from: u64,
+    to: u64,
+) -> FetchedRange {
+    fetch_pinned(provider, Filter::new().address(contract), from, to).await
+}
+
+async fn fetch_pinned(
+    provider: Arc<PoolProvider>,
+    filter: Filter,
+    from: u64,
+    to: u64,
+) -> FetchedRange {
+    let mut attempt = 1;
+    loop {
+        let before = match block_hash(&provider, to).await {
+            Ok(hash) => hash,
+            Err(e) => return failed(from, to, e),
+        };
+        let mut fetched = fetch_filtered(provider.clone(), filter.clone(), from, to).await;
+        if fetched.logs.is_err() {
+            return fetched;
+        }
+        let after = match block_hash(&provider, to).await {
+            Ok(hash) => hash,
+            Err(e) => return failed(from, to, e),
+        };
+        let (Some(before), Some(after)) = (before, after) else {
+            // A node that is behind may not have the block yet
+            return fetched;
+        };
+        if before == after {
+            fetched.hash = Some(after);
+            return fetched;
+        }
+
+        let changed = format!(
+            "block {} changed from 0x{:x} to 0x{:x} while fetching its logs",
+            to, before, after
+        );
+        if attempt == GET_LOGS_ATTEMPTS {
+            fetched.logs = Err(ProviderError::CustomError(changed));
+            return fetched;
+        }
+        warn!(
+            "{} (attempt {}/{}), fetching again",
+            changed, attempt, GET_LOGS_ATTEMPTS
+        );
+        attempt += 1;
+    }
+}
+
+async fn block_hash(provider: &PoolProvider, number: u64) -> Result<Option<H256>, ProviderError> {
+    Ok(provider
+        .get_block(number)
+        .await?
+        .and_then(|block| block.hash))
+}
+
+fn failed(from: u64, to: u64, e: ProviderError) -> FetchedRange {
+    FetchedRange {
+        from,
+        to,
+        splits: 0,
+        retries: 0,
+        logs: Err(e),
+        hash: None,
+    }
+}
+
+/// `fetch_range` with any filter, e.g. one narrowed by topic. The filter's
+/// own block range is replaced by `from..=to`.
+pub async fn fetch_filtered(
+    provider: Arc<PoolProvider>,
+    filter: Filter,
+    from: u64,
+    to: u64,
+) -> FetchedRange {
+    let (splits, retries, logs) = fetch_split(provider, filter, from, to).await;
+    FetchedRange {
+        from,
+        to,
+        splits,
+        retries,
+        logs,
+        hash: None,
+    }
+}
+
+/// Consecutive ranges of `from..=to` fetched like `fetch_range`, with up to
+/// `concurrency` requests in flight and handed back in block order, so they
+/// can be committed in order
+pub struct RangeStream {
+    provider: Arc<PoolProvider>,
+    filter: Filter,
+    next_block: u64,
+    end_block: u64,
+    concurrency: usize,
+    in_flight: FuturesOrdered<BoxFuture<'static, FetchedRange>>,
+}
+
+impl RangeStream {
+    pub fn new(
+        provider: Arc<PoolProvider>,
+        filter: Filter,
+        from: u64,
+        to: u64,
+        concurrency: usize,
+    ) -> Self {
+        Self {
+            provider,
+            filter,
+            next_block: from,
+            end_block: to,
+            concurrency: concurrency.max(1),
+            in_flight: FuturesOrdered::new(),
+        }
+    }
+
+    /// The next range in order. Ranges started from here on are
+    /// `batch_size` blocks long.
+    pub async fn next(&mut self, batch_size: u64) -> Option<FetchedRange> {
+        while self.in_flight.len() < self.concurrency && self.next_block <= self.end_block {
+            let batch_end = (self.next_block + batch_size.max(1) - 1).min(self.end_block);
+            self.in_flight.push_back(
+                fetch_pinned(
+                    self.provider.clone(),
+                    self.filter.clone(),
+                    self.next_block,
+                    batch_end,
+                )
+                .boxed(),
+            );
+            self.next_block = batch_end + 1;
+        }
+        self.in_flight.next().await
+    }
+}
+
+/// Returns how many times the range was split, how many attempts were
+/// retried and the logs
+fn fetch_split(
+    provider: Arc<PoolProvider>,
+    filter: Filter,
+    from: u64,
+    to: u64,
+) -> BoxFuture<'static, (u32, u32, Result<Vec<Log>, ProviderError>)> {
+    async move {
+        let ranged = filter.clone().from_block(from).to_block(to);
+
+        let mut delay = Duration::from_secs(1);
+        let mut attempt = 1;
+        loop {
+            let retries = attempt - 1;
+            match provider.get_logs(&ranged).await {
+                Ok(logs) => return (0, retries, Ok(logs)),
+                Err(e) if to > from && is_range_too_large(&e.to_string()) => {
+                    let mid = from + (to - from) / 2;
+                    let (left_splits, left_retries, left) =
+                        fetch_split(provider.clone(), filter.clone(), from, mid).await;
+                    let mut logs = match left {
+                        Ok(logs) => logs,
+                        Err(e) => return (left_splits + 1, retries + left_retries, Err(e)),
+                    };
+                    let (right_splits, right_retries, right) =
+                        fetch_split(provider, filter, mid + 1, to).await;
+                    let splits = 1 + left_splits.max(right_splits);
+                    let retries = retries + left_retries + right_retries;
+                    return match right {
+                        Ok(right) => {
+                            logs.extend(right);
+                            (splits, retries, Ok(logs))
+                        }
+                        Err(e) => (splits, retries, Err(e)),
+                    };
+                }
+                Err(e) if attempt < GET_LOGS_ATTEMPTS => {
+                    warn!(
+                        "Error getting logs for blocks {} to {} (attempt {}/{}): {}",
+                        from, to, attempt, GET_LOGS_ATTEMPTS, e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return (0, retries, Err(e)),
+            }
+        }
+    }
+    .boxed()
+}
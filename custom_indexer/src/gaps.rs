@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::checkpoint::write_json_atomic;
+
+pub const GAPS_FILE: &str = "indexer_gaps.json";
+
+/// First retry delay; doubled after every failed attempt
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// A block range whose logs could not be fetched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Gap {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub attempts: u32,
+    pub last_error: String,
+    pub next_retry: DateTime<Utc>,
+}
+
+/// Failed ranges still to be re-fetched, persisted next to the checkpoint
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct GapList {
+    gaps: Vec<Gap>,
+}
+
+impl GapList {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(GAPS_FILE)
+    }
+
+    pub fn load(output_dir: &Path) -> Result<Self> {
+        let path = Self::path(output_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        serde_json::from_str(&contents).with_context(|| format!("parsing {}", path.display()))
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        write_json_atomic(&Self::path(output_dir), self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gaps.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.gaps.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Gap> {
+        self.gaps.iter()
+    }
+
+    /// Total number of blocks not yet indexed
+    pub fn missing_blocks(&self) -> u64 {
+        self.gaps.iter().map(|g| g.to_block - g.from_block + 1).sum()
+    }
+
+    /// Record a failed attempt for `from..=to`, adding the range if it is new
+    pub fn record_failure(&mut self, from: u64, to: u64, error: String) {
+        let now = Utc::now();
+        match self
+            .gaps
+            .iter_mut()
+            .find(|g| g.from_block == from && g.to_block == to)
+        {
+            Some(gap) => {
+                gap.attempts += 1;
+                gap.last_error = error;
+                gap.next_retry = now + retry_delay(gap.attempts);
+            }
+            None => {
+                self.gaps.push(Gap {
+                    from_block: from,
+                    to_block: to,
+                    attempts: 1,
+                    last_error: error,
+                    next_retry: now + retry_delay(1),
+                });
+                self.gaps.sort_by_key(|g| g.from_block);
+            }
+        }
+    }
+
+    pub fn resolve(&mut self, from: u64, to: u64) {
+        self.gaps
+            .retain(|g| !(g.from_block == from && g.to_block == to));
+    }
+
+    /// Ranges whose next retry is due, oldest first
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<(u64, u64)> {
+        self.gaps
+            .iter()
+            .filter(|g| g.next_retry <= now)
+            .map(|g| (g.from_block, g.to_block))
+            .collect()
+    }
+
+    /// Drop everything above `block`, e.g. after a reorg rolled the
+    /// checkpoint back below it and the range will be indexed again anyway
+    pub fn truncate_after(&mut self, block: u64) {
+        self.gaps.retain(|g| g.from_block <= block);
+        for gap in &mut self.gaps {
+            gap.to_block = gap.to_block.min(block);
+        }
+    }
+}
+
+/// Exponential backoff for the given number of failed attempts
+fn retry_delay(attempts: u32) -> chrono::Duration {
+    let delay = BASE_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY);
+    chrono::Duration::seconds(delay.as_secs() as i64)
+}
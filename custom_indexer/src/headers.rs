@@ -0,0 +1,187 @@
+use anyhow::Result;
+use ethers::types::{H256, U64};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::rpc_pool::RpcPool;
+
+/// Headers kept in memory; comfortably more than one backfill range's worth
+/// of blocks with events
+pub const HEADER_CACHE_SIZE: usize = 4096;
+
+/// Block numbers per batched JSON-RPC request
+const HEADER_BATCH_SIZE: usize = 100;
+
+/// The parts of a block header the indexer uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    pub number: u64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub timestamp: u64,
+}
+
+/// Least recently used cache of block headers keyed by block number
+#[derive(Debug)]
+pub struct HeaderCache {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<u64, (BlockHeader, u64)>,
+    /// Last use tick -> block number, oldest first
+    by_use: BTreeMap<u64, u64>,
+}
+
+impl Default for HeaderCache {
+    fn default() -> Self {
+        Self::new(HEADER_CACHE_SIZE)
+    }
+}
+
+impl HeaderCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            tick: 0,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, number: u64) -> bool {
+        self.entries.contains_key(&number)
+    }
+
+    /// Look up a header and mark it as recently used
+    pub fn get(&mut self, number: u64) -> Option<BlockHeader> {
+        let tick = self.next_tick();
+        let (header, last_used) = self.entries.get_mut(&number)?;
+        self.by_use.remove(last_used);
+        *last_used = tick;
+        self.by_use.insert(tick, number);
+        Some(*header)
+    }
+
+    /// Look up a header without touching its recency
+    pub fn peek(&self, number: u64) -> Option<BlockHeader> {
+        self.entries.get(&number).map(|(header, _)| *header)
+    }
+
+    pub fn insert(&mut self, header: BlockHeader) {
+        let tick = self.next_tick();
+        if let Some((_, last_used)) = self.entries.insert(header.number, (header, tick)) {
+            self.by_use.remove(&last_used);
+        }
+        self.by_use.insert(tick, header.number);
+
+        while self.entries.len() > self.capacity {
+            let Some((_, oldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    /// Drop every header above `number`, e.g. blocks orphaned by a reorg
+    pub fn invalidate_after(&mut self, number: u64) {
+        let stale: Vec<u64> = self
+            .entries
+            .keys()
+            .copied()
+            .filter(|n| *n > number)
+            .collect();
+        for n in stale {
+            if let Some((_, last_used)) = self.entries.remove(&n) {
+                self.by_use.remove(&last_used);
+            }
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+/// Fetches headers with batched `eth_getBlockByNumber` calls (one HTTP
+/// request per `HEADER_BATCH_SIZE` blocks) and keeps them in a `HeaderCache`
+pub struct HeaderFetcher {
+    pool: RpcPool,
+    pub cache: HeaderCache,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcHeader {
+    number: U64,
+    hash: H256,
+    parent_hash: H256,
+    timestamp: U64,
+}
+
+impl HeaderFetcher {
+    pub fn new(pool: RpcPool) -> Self {
+        Self {
+            pool,
+            cache: HeaderCache::default(),
+        }
+    }
+
+    /// Make sure every block in `numbers` is cached, fetching the missing
+    /// ones in batches
+    pub async fn prefetch(&mut self, numbers: impl IntoIterator<Item = u64>) -> Result<()> {
+        let mut missing: Vec<u64> = numbers
+            .into_iter()
+            .filter(|n| !self.cache.contains(*n))
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+
+        for chunk in missing.chunks(HEADER_BATCH_SIZE) {
+            for header in self.fetch_batch(chunk).await? {
+                self.cache.insert(header);
+            }
+        }
+        Ok(())
+    }
+
+    /// Cached header for `number`, fetching it if needed
+    pub async fn get(&mut self, number: u64) -> Result<Option<BlockHeader>> {
+        if let Some(header) = self.cache.get(number) {
+            return Ok(Some(header));
+        }
+        self.prefetch([number]).await?;
+        Ok(self.cache.get(number))
+    }
+
+    async fn fetch_batch(&self, numbers: &[u64]) -> Result<Vec<BlockHeader>> {
+        let params: Vec<serde_json::Value> = numbers
+            .iter()
+            .map(|number| json!([format!("0x{:x}", number), false]))
+            .collect();
+
+        // A block the node does not have yet comes back as null
+        let headers = self
+            .pool
+            .batch_call::<RpcHeader>("eth_getBlockByNumber", &params)
+            .await?
+            .into_iter()
+            .flatten()
+            .map(|header| BlockHeader {
+                number: header.number.as_u64(),
+                hash: header.hash,
+                parent_hash: header.parent_hash,
+                timestamp: header.timestamp.as_u64(),
+            })
+            .collect();
+        Ok(headers)
+    }
+}
@@ -0,0 +1,25 @@
+//! Shared pieces of the NeuroLend indexer binaries.
+
+pub mod batch;
+pub mod calls;
+pub mod checkpoint;
+pub mod config;
+pub mod decoder;
+pub mod doctor;
+pub mod event_signatures;
+pub mod fetch;
+pub mod gaps;
+pub mod headers;
+pub mod metrics;
+pub mod postgres;
+pub mod protobuf;
+pub mod reorg;
+pub mod rpc_pool;
+pub mod segments;
+pub mod shutdown;
+pub mod sink;
+pub mod snapshots;
+pub mod subscription;
+pub mod transfers;
+pub mod verify;
+pub mod views;
@@ -1,203 +1,1312 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
 use ethers::prelude::*;
-use serde_json::json;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 use tracing::{error, info, warn};
 
-mod event_signatures;
-
-// Reuse your contract address
-const NEUROLEND_CONTRACT: &str = "0x064c3e0a900743d9ac87c778d2f6d3d5819d4f23";
-const STARTING_BLOCK: u64 = 6914309;
-const RPC_URL: &str = "https://evmrpc.0g.ai";
+use neurolend_indexer::batch::AdaptiveBatchSize;
+use neurolend_indexer::calls::{CallFetcher, IndexedCall};
+use neurolend_indexer::checkpoint::Checkpoint;
+use neurolend_indexer::config::{Command, Config, SinkKind};
+use neurolend_indexer::decoder::EventDecoder;
+use neurolend_indexer::doctor::{Doctor, Status};
+use neurolend_indexer::event_signatures::{self, id_keyed_events};
+use neurolend_indexer::fetch::{fetch_range, FetchedRange, RangeStream};
+use neurolend_indexer::gaps::GapList;
+use neurolend_indexer::headers::{BlockHeader, HeaderFetcher};
+use neurolend_indexer::metrics::{self, Metrics};
+use neurolend_indexer::postgres::PostgresSink;
+use neurolend_indexer::reorg::BlockWindow;
+use neurolend_indexer::rpc_pool::{PoolProvider, RpcPool};
+use neurolend_indexer::segments::Manifest;
+use neurolend_indexer::shutdown::Shutdown;
+use neurolend_indexer::sink::{
+    FileSink, IndexedEvent, Replace, Sink, CALLS_DIR, SNAPSHOTS_DIR, TRANSFERS_DIR,
+};
+use neurolend_indexer::snapshots::{Snapshot, Snapshotter};
+use neurolend_indexer::subscription::{LogBuffer, Notification, Subscription};
+use neurolend_indexer::transfers::{self, IndexedTransfer, TransferFetcher};
+use neurolend_indexer::verify::{IndexedState, Verifier};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize tracing
     tracing_subscriber::fmt::init();
 
+    let (command, config) = Config::load()?;
+    if command == Command::Status {
+        return print_status(&config).await;
+    }
+    // Runs its own checks, and should report an unreachable node rather
+    // than fail on it
+    if let Command::Doctor { export } = &command {
+        return doctor(config, export.as_deref()).await;
+    }
+
     info!("Starting NeuroLend indexer for 0G network");
+    info!(
+        "RPC {}, contract 0x{:x}, batch size {} (max {}, {} concurrent), confirmations {}, output {:?}",
+        config.rpc_urls.join(", "),
+        config.contract_address,
+        config.batch_size,
+        config.max_batch_size,
+        config.concurrency,
+        config.confirmations,
+        config.output_dir
+    );
 
     // Connect to 0G network
-    let provider = Provider::<Http>::try_from(RPC_URL)?;
-    let provider = Arc::new(provider);
+    let pool = RpcPool::new(&config.rpc_urls)?;
+    let provider = Arc::new(pool.provider());
 
     // Verify connection
     let chain_id = provider.get_chainid().await?;
     info!("Connected to 0G network, Chain ID: {}", chain_id);
+    if chain_id.as_u64() != config.chain_id {
+        warn!(
+            "Expected chain ID {}, set chain_id if this is the right network",
+            config.chain_id
+        );
+    }
 
     // Get current block
     let current_block = provider.get_block_number().await?;
     info!("Current block: {}", current_block);
 
-    // Start indexing
-    let mut indexer = NeuroLendIndexer::new(provider).await?;
-    indexer.start_indexing(STARTING_BLOCK).await?;
+    if let Command::Verify { export } = &command {
+        return verify(&config, pool, export.as_deref()).await;
+    }
+
+    let start_block = config.starting_block;
+    let mut indexer = NeuroLendIndexer::new(pool, provider, config).await?;
+    let result = match command {
+        Command::Backfill { from, to } => indexer.backfill(from, to).await,
+        Command::Reindex { loan } => indexer.reindex_loan(loan, start_block).await,
+        _ => {
+            indexer
+                .check_abi_coverage(start_block, current_block.as_u64())
+                .await;
+            indexer.start_indexing(start_block).await
+        }
+    };
+    indexer.close().await;
+    result
+}
+
+async fn open_sink(config: &Config) -> Result<Sink> {
+    match (config.sink, &config.database_url) {
+        (SinkKind::Postgres, Some(url)) => Ok(Sink::Postgres(
+            PostgresSink::connect(
+                url,
+                config.contract_address,
+                config.index_calls,
+                config.index_transfers,
+            )
+            .await?,
+        )),
+        _ => Ok(Sink::Files(FileSink::open(
+            &config.output_dir,
+            config.segment_blocks,
+            config.compression,
+            config.index_calls,
+            config.index_transfers,
+            config.snapshot_interval > 0,
+            config.protobuf,
+        )?)),
+    }
+}
+
+/// Rebuild loan state from the stored events and diff it against the
+/// contract's views at the last indexed block
+async fn verify(config: &Config, pool: RpcPool, export: Option<&Path>) -> Result<()> {
+    let sink = open_sink(config).await?;
+    let block = match sink.load_checkpoint().await? {
+        Some(checkpoint) if checkpoint.current_block > 0 => checkpoint.current_block - 1,
+        _ => bail!("nothing has been indexed yet, run `follow` first"),
+    };
+    let events = sink.load_events().await?;
+    let state = IndexedState::rebuild(&events);
+    info!(
+        "Rebuilt {} loans and {} requests from {} events, verifying at block {}",
+        state.loans.len(),
+        state.requests.len(),
+        events.len(),
+        block
+    );
+
+    let report = Verifier::new(pool, config.contract_address, block)
+        .verify(&state)
+        .await?;
+    if let Some(path) = export {
+        fs::write(path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("writing {}", path.display()))?;
+        info!("Wrote verification report to {}", path.display());
+    }
+
+    println!(
+        "Verified {} loans, {} requests and {} borrowers at block {}",
+        report.loans_checked, report.requests_checked, report.borrowers_checked, report.block
+    );
+    for m in &report.mismatches {
+        println!(
+            "  {} {} {}: indexed {}, on chain {}",
+            m.kind, m.id, m.field, m.indexed, m.onchain
+        );
+    }
+    if !report.mismatches.is_empty() {
+        bail!(
+            "{} mismatches between the index and the contract",
+            report.mismatches.len()
+        );
+    }
+    println!("No mismatches");
+    Ok(())
+}
+
+/// Check the endpoints, contract and storage, and print what is wrong
+async fn doctor(config: Config, export: Option<&Path>) -> Result<()> {
+    let report = Doctor::new(config)?.run().await;
+    if let Some(path) = export {
+        fs::write(path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("writing {}", path.display()))?;
+        info!("Wrote doctor report to {}", path.display());
+    }
 
+    report.print();
+    let failed = report.count(Status::Failed);
+    if failed > 0 {
+        bail!("{} checks failed", failed);
+    }
     Ok(())
 }
 
+async fn print_status(config: &Config) -> Result<()> {
+    let output_dir = &config.output_dir;
+    let gaps = GapList::load(output_dir)?;
+
+    match open_sink(config).await?.load_checkpoint().await? {
+        Some(checkpoint) => {
+            println!("Next block:   {}", checkpoint.current_block);
+            println!("Last updated: {}", checkpoint.last_updated);
+            println!(
+                "Caught up:    {}",
+                if checkpoint.caught_up && gaps.is_empty() {
+                    "yes"
+                } else {
+                    "no"
+                }
+            );
+        }
+        None => println!("No checkpoint yet"),
+    }
+
+    if let Some(manifest) = Manifest::load(output_dir)? {
+        println!(
+            "Segments:     {} ({} events)",
+            manifest.segments.len(),
+            manifest.total_events()
+        );
+    }
+    if let Some(manifest) = Manifest::load(&output_dir.join(CALLS_DIR))? {
+        println!(
+            "Call segments: {} ({} calls)",
+            manifest.segments.len(),
+            manifest.total_events()
+        );
+    }
+    if let Some(manifest) = Manifest::load(&output_dir.join(TRANSFERS_DIR))? {
+        println!(
+            "Transfer segments: {} ({} transfers)",
+            manifest.segments.len(),
+            manifest.total_events()
+        );
+    }
+    if let Some(manifest) = Manifest::load(&output_dir.join(SNAPSHOTS_DIR))? {
+        println!(
+            "Snapshot segments: {} ({} snapshots)",
+            manifest.segments.len(),
+            manifest.total_events()
+        );
+    }
+
+    if gaps.is_empty() {
+        println!("Gaps:         none");
+    } else {
+        println!(
+            "Gaps:         {} ranges, {} blocks",
+            gaps.len(),
+            gaps.missing_blocks()
+        );
+        for gap in gaps.iter() {
+            println!(
+                "  {}-{}  attempts {}  next retry {}  last error: {}",
+                gap.from_block, gap.to_block, gap.attempts, gap.next_retry, gap.last_error
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// How far back the startup self-check looks for event types
+const SELF_CHECK_BLOCKS: u64 = 10_000;
+
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+/// Polling rounds between attempts to re-establish a dropped subscription
+const POLLS_BEFORE_RECONNECT: u32 = 12;
+/// A subscription with no new head for this long is treated as dropped
+const HEAD_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
 struct NeuroLendIndexer {
-    provider: Arc<Provider<Http>>,
+    provider: Arc<PoolProvider>,
     contract_address: Address,
-    event_signatures: HashMap<H256, &'static str>,
-    output_dir: String,
+    event_signatures: HashMap<H256, String>,
+    output_dir: PathBuf,
+    batch_size: AdaptiveBatchSize,
+    concurrency: usize,
+    confirmations: u64,
+    ws_url: Option<String>,
+    recent_blocks: BlockWindow,
+    gaps: GapList,
+    caught_up: bool,
+    headers: HeaderFetcher,
+    decoder: EventDecoder,
+    /// Set when calls are indexed as well as events
+    calls: Option<CallFetcher>,
+    /// Set when ERC20 transfers to and from the contract are indexed
+    transfers: Option<TransferFetcher>,
+    /// Set when active loans are snapshotted
+    snapshots: Option<Snapshotter>,
+    /// Loan state as of the last committed range, kept to know which loans
+    /// are active. Only maintained when snapshotting.
+    loan_state: IndexedState,
+    /// topic0s seen on chain that the ABI does not know, with counts
+    unknown_topics: HashMap<H256, u64>,
+    sink: Sink,
+    shutdown: Shutdown,
+    metrics: Metrics,
+    /// Set when a round of the follow loop failed part way. Its block
+    /// hashes and loan state may be ahead of what was stored.
+    needs_resync: bool,
 }
 
 impl NeuroLendIndexer {
-    async fn new(provider: Arc<Provider<Http>>) -> Result<Self> {
-        let contract_address = NEUROLEND_CONTRACT.parse()?;
+    async fn new(pool: RpcPool, provider: Arc<PoolProvider>, config: Config) -> Result<Self> {
+        let sink = open_sink(&config).await?;
+        let contract_address = config.contract_address;
         let event_signatures = event_signatures::get_event_signatures();
-        let output_dir = "output".to_string();
+        let output_dir = config.output_dir;
 
         // Create output directory if it doesn't exist
-        if !Path::new(&output_dir).exists() {
+        if !output_dir.exists() {
             fs::create_dir_all(&output_dir)?;
-            info!("Created output directory: {}", output_dir);
+            info!("Created output directory: {:?}", output_dir);
         }
 
         info!("Loaded {} event signatures", event_signatures.len());
 
+        let calls = if config.index_calls {
+            info!("Indexing calls: every block in range will be fetched with its receipts");
+            Some(CallFetcher::new(pool.clone(), contract_address)?)
+        } else {
+            None
+        };
+
+        let transfers = config.index_transfers.then(|| {
+            info!("Indexing ERC20 transfers to and from the contract");
+            TransferFetcher::new(provider.clone(), contract_address)
+        });
+
+        let snapshots = if config.snapshot_interval > 0 {
+            info!(
+                "Snapshotting active loans every {} blocks",
+                config.snapshot_interval
+            );
+            Some(Snapshotter::new(
+                pool.clone(),
+                contract_address,
+                config.snapshot_interval,
+            )?)
+        } else {
+            None
+        };
+
+        let gaps = GapList::load(&output_dir)?;
+        if !gaps.is_empty() {
+            warn!(
+                "{} gaps ({} blocks) left from a previous run",
+                gaps.len(),
+                gaps.missing_blocks()
+            );
+        }
+
+        let metrics = Metrics::default();
+        metrics.set_gaps(&gaps);
+        if let Some(addr) = config.metrics_addr {
+            metrics::serve(addr, metrics.clone(), pool.clone()).await?;
+        }
+
         Ok(Self {
             provider,
             contract_address,
             event_signatures,
             output_dir,
+            batch_size: AdaptiveBatchSize::new(config.batch_size, config.max_batch_size),
+            concurrency: config.concurrency,
+            confirmations: config.confirmations,
+            ws_url: config.ws_url,
+            recent_blocks: BlockWindow::default(),
+            gaps,
+            caught_up: false,
+            headers: HeaderFetcher::new(pool),
+            decoder: EventDecoder::neurolend()?,
+            calls,
+            transfers,
+            snapshots,
+            loan_state: IndexedState::default(),
+            unknown_topics: HashMap::new(),
+            sink,
+            shutdown: Shutdown::on_signals(),
+            metrics,
+            needs_resync: false,
         })
     }
 
+    async fn close(self) {
+        if self.shutdown.requested() {
+            info!("Stopped, run the same command again to carry on");
+        }
+        self.sink.close().await;
+    }
+
+    /// Fetch the contract's recent logs and flag every topic0 the ABI has no
+    /// event for, which means the deployed contract and the ABI disagree
+    async fn check_abi_coverage(&self, start_block: u64, head: u64) {
+        let from = head.saturating_sub(SELF_CHECK_BLOCKS).max(start_block);
+        if from > head {
+            return;
+        }
+
+        let fetched = fetch_range(self.provider.clone(), self.contract_address, from, head).await;
+        let logs = match fetched.logs {
+            Ok(logs) => logs,
+            Err(e) => {
+                warn!("Skipping ABI self-check, could not fetch logs: {}", e);
+                return;
+            }
+        };
+
+        let mut unknown: HashMap<H256, (u64, Option<H256>)> = HashMap::new();
+        let mut known = HashMap::new();
+        for log in &logs {
+            let Some(topic0) = log.topics.first() else {
+                continue;
+            };
+            match self.identify_event(topic0) {
+                Some(name) => *known.entry(name).or_insert(0u64) += 1,
+                None => {
+                    let entry = unknown.entry(*topic0).or_insert((0, log.transaction_hash));
+                    entry.0 += 1;
+                }
+            }
+        }
+
+        if unknown.is_empty() {
+            info!(
+                "ABI self-check: all {} logs in blocks {} to {} match the ABI ({} event types)",
+                logs.len(),
+                from,
+                head,
+                known.len()
+            );
+        }
+        for (topic0, (count, tx)) in unknown {
+            error!(
+                "ABI self-check: topic0 0x{:x} seen {} times in blocks {} to {} (e.g. tx 0x{:x}) is not in the ABI",
+                topic0,
+                count,
+                from,
+                head,
+                tx.unwrap_or_default()
+            );
+        }
+    }
+
     async fn start_indexing(&mut self, start_block: u64) -> Result<()> {
+        // Resume after the last fully processed block if we have a checkpoint
+        let start_block = match self.sink.load_checkpoint().await? {
+            Some(checkpoint) if checkpoint.current_block > start_block => {
+                info!(
+                    "Resuming from checkpoint at block {} (saved {})",
+                    checkpoint.current_block, checkpoint.last_updated
+                );
+                checkpoint.current_block
+            }
+            _ => start_block,
+        };
         info!("Starting indexing from block {}", start_block);
+        if start_block > 0 {
+            self.metrics.set_last_indexed_block(start_block - 1);
+        }
+        if self.snapshots.is_some() {
+            self.loan_state = IndexedState::rebuild(&self.sink.load_events().await?);
+        }
 
-        let current_block = self.provider.get_block_number().await?.as_u64();
+        let current_block = self.confirmed_head().await?;
 
         // Process historical blocks first
         self.process_historical_blocks(start_block, current_block)
             .await?;
+        if self.shutdown.requested() {
+            return Ok(());
+        }
+
+        // Give every failed range one more go before deciding whether we
+        // are caught up
+        self.retry_gaps(true).await?;
+        if self.gaps.is_empty() {
+            info!(
+                "Historical sync complete, caught up to block {}",
+                current_block
+            );
+            self.caught_up = true;
+            self.sink
+                .save_checkpoint(&Checkpoint::new((current_block + 1).max(start_block), true))
+                .await?;
+            self.metrics
+                .set_last_indexed_block(current_block.max(start_block.saturating_sub(1)));
+        } else {
+            warn!(
+                "Historical sync reached block {} but {} gaps ({} blocks) remain, not caught up",
+                current_block,
+                self.gaps.len(),
+                self.gaps.missing_blocks()
+            );
+        }
 
         // Then start listening for new blocks
-        self.listen_for_new_blocks().await?;
+        self.listen_for_new_blocks(current_block.max(start_block.saturating_sub(1)))
+            .await?;
 
         Ok(())
     }
 
-    async fn process_historical_blocks(&self, start_block: u64, end_block: u64) -> Result<()> {
+    async fn process_historical_blocks(&mut self, start_block: u64, end_block: u64) -> Result<()> {
         info!(
             "Processing historical blocks {} to {}",
             start_block, end_block
         );
 
-        const BATCH_SIZE: u64 = 1000; // Process in batches to avoid RPC limits
+        // Ranges come back in block order, so they are committed and
+        // checkpointed in block order
+        let mut ranges = RangeStream::new(
+            self.provider.clone(),
+            Filter::new().address(self.contract_address),
+            start_block,
+            end_block,
+            self.concurrency,
+        );
+        // Ranges still being fetched are dropped on shutdown, nothing of
+        // theirs has been written
+        let shutdown = self.shutdown.clone();
+        while let Some(Some(fetched)) = shutdown
+            .interruptible(ranges.next(self.batch_size.get()))
+            .await
+        {
+            info!(
+                "Processing batch: {} to {} (next batch size {})",
+                fetched.from,
+                fetched.to,
+                self.batch_size.get()
+            );
+            self.commit_range(fetched).await?;
+        }
 
-        for batch_start in (start_block..=end_block).step_by(BATCH_SIZE as usize) {
-            let batch_end = std::cmp::min(batch_start + BATCH_SIZE - 1, end_block);
+        Ok(())
+    }
 
-            info!("Processing batch: {} to {}", batch_start, batch_end);
+    /// Next block the live indexer will index. Backfill and reindex only
+    /// touch blocks before it.
+    async fn live_checkpoint(&self) -> Result<u64> {
+        match self.sink.load_checkpoint().await? {
+            Some(checkpoint) if checkpoint.current_block > 0 => Ok(checkpoint.current_block),
+            _ => bail!("nothing has been indexed yet, run `follow` first"),
+        }
+    }
 
-            // Get logs for this batch
-            let filter = Filter::new()
-                .address(self.contract_address)
-                .from_block(batch_start)
-                .to_block(batch_end);
+    /// Re-index `from..=to` and replace what is stored for it. Each range is
+    /// replaced in one go, so an interrupted backfill can simply be re-run.
+    async fn backfill(&mut self, from: u64, to: u64) -> Result<()> {
+        let next_block = self.live_checkpoint().await?;
+        if to >= next_block {
+            bail!(
+                "block {} has not been indexed yet; the checkpoint is at block {} and backfill only covers blocks behind it",
+                to,
+                next_block
+            );
+        }
+        info!("Backfilling blocks {} to {}", from, to);
 
-            match self.provider.get_logs(&filter).await {
-                Ok(logs) => {
-                    info!(
-                        "Found {} logs in batch {} to {}",
-                        logs.len(),
-                        batch_start,
-                        batch_end
-                    );
-                    self.process_logs(logs).await?;
-                }
-                Err(e) => {
-                    error!(
-                        "Error getting logs for batch {} to {}: {}",
-                        batch_start, batch_end, e
-                    );
-                    // Continue with next batch
-                }
-            }
+        let mut ranges = RangeStream::new(
+            self.provider.clone(),
+            Filter::new().address(self.contract_address),
+            from,
+            to,
+            self.concurrency,
+        );
+        let (mut removed, mut written) = (0, 0);
+        let mut next_block = from;
+        let shutdown = self.shutdown.clone();
+        while let Some(Some(fetched)) = shutdown
+            .interruptible(ranges.next(self.batch_size.get()))
+            .await
+        {
+            let (range_from, range_to) = (fetched.from, fetched.to);
+            let blocks = fetched.blocks();
+            let logs = fetched.logs.with_context(|| {
+                format!(
+                    "fetching blocks {} to {}, re-run the backfill to retry",
+                    range_from, range_to
+                )
+            })?;
+            self.batch_size.observe(blocks, fetched.splits, logs.len());
 
-            // Small delay to avoid overwhelming the RPC
-            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            let (calls, transfer_logs) = self.fetch_extras(range_from, range_to).await?;
+            let events = self.process_logs(logs).await?;
+            let transfers = self.process_transfers(transfer_logs, &events).await?;
+            let headers = self.batch_headers(&events, &calls, &transfers, None);
+            let scope = Replace::Blocks {
+                from: range_from,
+                to: range_to,
+            };
+            removed += self
+                .sink
+                .replace(&scope, &events, &calls, &transfers, &headers)
+                .await?;
+            written += events.len();
+            next_block = range_to + 1;
+            info!(
+                "Backfilled blocks {} to {} ({} events)",
+                range_from,
+                range_to,
+                events.len()
+            );
+        }
+        if self.shutdown.requested() && next_block <= to {
+            warn!(
+                "Backfill stopped before block {}, run `backfill --from {} --to {}` to finish it",
+                next_block, next_block, to
+            );
+            return Ok(());
         }
 
+        info!(
+            "Backfill of blocks {} to {} done: {} stored events replaced by {}",
+            from, to, removed, written
+        );
         Ok(())
     }
 
-    async fn listen_for_new_blocks(&self) -> Result<()> {
-        info!("Starting to listen for new blocks...");
+    /// Re-fetch every event of `loan` up to the checkpoint and replace the
+    /// stored ones
+    async fn reindex_loan(&mut self, loan: U256, start_block: u64) -> Result<()> {
+        let to = self.live_checkpoint().await? - 1;
+        info!(
+            "Re-indexing loan {} in blocks {} to {}",
+            loan, start_block, to
+        );
+
+        // Every loan event has the loan id as its first indexed parameter
+        let mut topic = [0u8; 32];
+        loan.to_big_endian(&mut topic);
+        let filter = Filter::new()
+            .address(self.contract_address)
+            .topic1(H256(topic));
 
-        let mut last_processed_block = self.provider.get_block_number().await?.as_u64();
+        let mut logs = Vec::new();
+        let mut ranges = RangeStream::new(
+            self.provider.clone(),
+            filter,
+            start_block,
+            to,
+            self.concurrency,
+        );
+        let shutdown = self.shutdown.clone();
+        while let Some(Some(fetched)) = shutdown
+            .interruptible(ranges.next(self.batch_size.get()))
+            .await
+        {
+            let blocks = fetched.blocks();
+            let found = fetched
+                .logs
+                .with_context(|| format!("fetching blocks {} to {}", fetched.from, fetched.to))?;
+            self.batch_size.observe(blocks, fetched.splits, found.len());
+            logs.extend(found);
+        }
+        if self.shutdown.requested() {
+            warn!("Stopped before anything was replaced for loan {}", loan);
+            return Ok(());
+        }
+
+        // The filter matches the id in any event's topic1, e.g. a token
+        // address in `PriceFeedSet`. Keep the events filed under a loan or
+        // request id, decoded or not, which is what the replacement removes.
+        let keyed = id_keyed_events();
+        let events: Vec<IndexedEvent> = self
+            .process_logs(logs)
+            .await?
+            .into_iter()
+            .filter(|e| keyed.contains_key(&e.event_name))
+            .collect();
+        let headers = self.batch_headers(&events, &[], &[], None);
+        let scope = Replace::Loan {
+            loan_id: loan.to_string(),
+            topic: format!("0x{:x}", H256(topic)),
+            to,
+        };
+        let removed = self
+            .sink
+            .replace(&scope, &events, &[], &[], &headers)
+            .await?;
+
+        info!(
+            "Re-indexed loan {}: {} stored events replaced by {}",
+            loan,
+            removed,
+            events.len()
+        );
+        Ok(())
+    }
+
+    async fn listen_for_new_blocks(&mut self, mut last_processed_block: u64) -> Result<()> {
+        info!("Starting to listen for new blocks...");
         info!(
             "Starting real-time monitoring from block {}",
             last_processed_block
         );
 
+        let Some(ws_url) = self.ws_url.clone() else {
+            while self.shutdown.sleep(POLL_INTERVAL).await {
+                last_processed_block = self.poll_or_retry(last_processed_block).await;
+            }
+            return Ok(());
+        };
+
+        let shutdown = self.shutdown.clone();
+        while let Some(connected) = shutdown
+            .interruptible(Subscription::connect(&ws_url, self.contract_address))
+            .await
+        {
+            match connected {
+                Ok(subscription) => {
+                    info!("Subscribed to new heads and logs at {}", ws_url);
+                    match self
+                        .follow_subscription(subscription, last_processed_block)
+                        .await
+                    {
+                        Ok(block) => last_processed_block = block,
+                        Err(e) => {
+                            warn!("Following the subscription failed: {:#}", e);
+                            self.needs_resync = true;
+                        }
+                    }
+                    if self.shutdown.requested() {
+                        break;
+                    }
+                    warn!("WebSocket subscription dropped, polling until it reconnects");
+                }
+                Err(e) => warn!(
+                    "Could not subscribe at {}, polling instead: {:#}",
+                    ws_url, e
+                ),
+            }
+
+            for _ in 0..POLLS_BEFORE_RECONNECT {
+                if !self.shutdown.sleep(POLL_INTERVAL).await {
+                    return Ok(());
+                }
+                last_processed_block = self.poll_or_retry(last_processed_block).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// `poll`, except that a failure is logged and left to the next round
+    /// to retry instead of stopping the indexer
+    async fn poll_or_retry(&mut self, last_processed_block: u64) -> u64 {
+        match self.poll(last_processed_block).await {
+            Ok(block) => block,
+            Err(e) => {
+                warn!(
+                    "Polling failed, retrying in {}s: {:#}",
+                    POLL_INTERVAL.as_secs(),
+                    e
+                );
+                self.needs_resync = true;
+                last_processed_block
+            }
+        }
+    }
+
+    /// One polling round: index every block confirmed since
+    /// `last_processed_block`. Returns the new last processed block.
+    async fn poll(&mut self, last_processed_block: u64) -> Result<u64> {
+        let last_processed_block = self.before_new_blocks(last_processed_block).await?;
+        let current_block = self.confirmed_head().await?;
+
+        if current_block > last_processed_block {
+            info!(
+                "New blocks detected: {} to {}",
+                last_processed_block + 1,
+                current_block
+            );
+
+            self.process_range(last_processed_block + 1, current_block)
+                .await?;
+            return Ok(current_block);
+        }
+        Ok(last_processed_block)
+    }
+
+    /// Index blocks as the node announces them, from the logs it pushes,
+    /// until the socket drops or goes quiet. Returns the last processed
+    /// block.
+    async fn follow_subscription(
+        &mut self,
+        mut subscription: Subscription,
+        mut last_processed_block: u64,
+    ) -> Result<u64> {
+        let mut buffer = LogBuffer::default();
+        // First block whose logs are known to be in the buffer. Anything
+        // below it (blocks produced while we were polling or not yet
+        // subscribed) is fetched with eth_getLogs.
+        let mut live_from: Option<u64> = None;
+
+        let shutdown = self.shutdown.clone();
         loop {
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await; // Poll every 5 seconds
+            let next = tokio::time::timeout(HEAD_TIMEOUT, subscription.next());
+            let Some(next) = shutdown.interruptible(next).await else {
+                return Ok(last_processed_block);
+            };
+            let head = match next {
+                Ok(Some(Notification::Log(log))) => {
+                    buffer.insert(*log);
+                    continue;
+                }
+                Ok(Some(Notification::Head(head))) => head,
+                Ok(None) => return Ok(last_processed_block),
+                Err(_) => {
+                    warn!("No new head for {}s", HEAD_TIMEOUT.as_secs());
+                    return Ok(last_processed_block);
+                }
+            };
 
-            let current_block = self.provider.get_block_number().await?.as_u64();
+            let before = last_processed_block;
+            last_processed_block = self.before_new_blocks(last_processed_block).await?;
+            if last_processed_block < before {
+                // Rolled back: re-fetch the orphaned range, this head
+                // included, rather than trust what was pushed for it
+                buffer.clear();
+                live_from = Some(head + 1);
+            }
+            let live_from = *live_from.get_or_insert(head);
 
-            if current_block > last_processed_block {
-                info!(
-                    "New blocks detected: {} to {}",
-                    last_processed_block + 1,
-                    current_block
+            // Logs for a block can arrive after its header, so a block is
+            // only committed once the next head has been announced
+            let target = head.saturating_sub(self.confirmations.max(1));
+            if target <= last_processed_block {
+                continue;
+            }
+
+            let from = last_processed_block + 1;
+            if from < live_from {
+                let to = target.min(live_from - 1);
+                info!("Catching up on blocks {} to {} with eth_getLogs", from, to);
+                self.process_range(from, to).await?;
+                last_processed_block = to;
+            }
+
+            // Drops anything already indexed as well
+            let logs: Vec<Log> = buffer
+                .take_through(target)
+                .into_iter()
+                .filter(|log| {
+                    log.block_number
+                        .is_some_and(|b| b.as_u64() > last_processed_block)
+                })
+                .collect();
+            if target > last_processed_block {
+                let fetched = FetchedRange {
+                    from: last_processed_block + 1,
+                    to: target,
+                    splits: 0,
+                    retries: 0,
+                    logs: Ok(logs),
+                    hash: None,
+                };
+                self.commit_range(fetched).await?;
+                last_processed_block = target;
+            }
+        }
+    }
+
+    /// Housekeeping before indexing new blocks: roll back a reorg and retry
+    /// due gaps. Returns the last processed block, which moves back on a
+    /// rollback.
+    async fn before_new_blocks(&mut self, mut last_processed_block: u64) -> Result<u64> {
+        if self.needs_resync {
+            last_processed_block = self.resync(last_processed_block).await?;
+            self.needs_resync = false;
+        }
+
+        // Make sure the block we last indexed is still on the canonical chain
+        if let Some(ancestor) = self.check_for_reorg(last_processed_block).await? {
+            self.rollback(ancestor).await?;
+            last_processed_block = ancestor;
+        }
+
+        self.retry_gaps(false).await?;
+        if !self.caught_up && self.gaps.is_empty() {
+            info!("All gaps filled, indexer is caught up");
+            self.caught_up = true;
+        }
+        Ok(last_processed_block)
+    }
+
+    /// Go back to what the sink has stored after a failed round, which may
+    /// have recorded block hashes or applied loan events for a range it
+    /// never wrote. Returns the last stored block.
+    async fn resync(&mut self, last_processed_block: u64) -> Result<u64> {
+        let last_stored = match self.sink.load_checkpoint().await? {
+            Some(checkpoint) if checkpoint.current_block > 0 => checkpoint.current_block - 1,
+            _ => last_processed_block,
+        };
+        self.recent_blocks.truncate_from(last_stored + 1);
+        if self.snapshots.is_some() {
+            self.loan_state = IndexedState::rebuild(&self.sink.load_events().await?);
+        }
+        if last_stored != last_processed_block {
+            info!("Resuming from block {}, the last one stored", last_stored);
+        }
+        Ok(last_stored)
+    }
+
+    /// Fetch and process every log in `from..=to`, then checkpoint `to + 1`
+    async fn process_range(&mut self, from: u64, to: u64) -> Result<()> {
+        let fetched = fetch_range(self.provider.clone(), self.contract_address, from, to).await;
+        self.commit_range(fetched).await
+    }
+
+    /// Process a fetched range and checkpoint past it. If the logs (or the
+    /// calls, when indexed) could not be fetched the range is recorded as a
+    /// gap and retried later.
+    async fn commit_range(&mut self, fetched: FetchedRange) -> Result<()> {
+        let started = Instant::now();
+        let FetchedRange {
+            from,
+            to,
+            splits,
+            retries,
+            mut hash,
+            ..
+        } = fetched;
+        if let Ok(logs) = &fetched.logs {
+            self.batch_size
+                .observe(fetched.blocks(), fetched.splits, logs.len());
+        }
+
+        let fetched = match fetched.logs {
+            Ok(logs) => self
+                .fetch_extras(from, to)
+                .await
+                .map(|(calls, transfers)| (logs, calls, transfers)),
+            Err(e) => Err(e.into()),
+        };
+        let mut events = Vec::new();
+        let mut calls = Vec::new();
+        let mut transfers = Vec::new();
+        match fetched {
+            Ok((logs, found, transfer_logs)) => {
+                // Pushed logs come without a pinned hash, but carry their
+                // own block's
+                if hash.is_none() {
+                    hash = logs
+                        .iter()
+                        .rfind(|log| log.block_number.is_some_and(|b| b.as_u64() == to))
+                        .and_then(|log| log.block_hash);
+                }
+                if !logs.is_empty() {
+                    info!("Found {} logs in blocks {} to {}", logs.len(), from, to);
+                    events = self.process_logs(logs).await?;
+                }
+                calls = found;
+                transfers = self.process_transfers(transfer_logs, &events).await?;
+            }
+            Err(e) => {
+                error!(
+                    "Giving up on blocks {} to {} for now, recording a gap: {:#}",
+                    from, to, e
                 );
+                self.gaps.record_failure(from, to, format!("{:#}", e));
+                self.gaps.save(&self.output_dir)?;
+                self.caught_up = false;
+                self.metrics.count_gap_failure();
+                self.metrics.set_gaps(&self.gaps);
+            }
+        }
+
+        // Remember the hash of the last block so the next range can be
+        // checked against it. It has to be the one the logs were read from:
+        // a header fetched now may already be on another fork.
+        let last_header = match self.headers.get(to).await {
+            Ok(header) => header,
+            Err(e) => {
+                warn!("Could not fetch header for block {}: {}", to, e);
+                None
+            }
+        };
+        if let Some(hash) = hash.or(last_header.map(|header| header.hash)) {
+            self.recent_blocks.push(to, hash);
+        }
+
+        let snapshots = self.take_snapshots(from, to, &events).await;
+        if !snapshots.is_empty() {
+            self.sink.write_snapshots(&snapshots).await?;
+        }
+
+        let blocks = self.batch_headers(&events, &calls, &transfers, last_header);
+        let checkpoint = Checkpoint::new(to + 1, self.caught_up);
+        self.sink
+            .write_batch(&events, &calls, &transfers, &blocks, Some(&checkpoint))
+            .await?;
+
+        self.metrics.observe_batch(started.elapsed(), retries, splits);
+        self.metrics.set_last_indexed_block(to);
+        Ok(())
+    }
 
-                // Process new blocks
-                let filter = Filter::new()
-                    .address(self.contract_address)
-                    .from_block(last_processed_block + 1)
-                    .to_block(current_block);
-
-                match self.provider.get_logs(&filter).await {
-                    Ok(logs) => {
-                        if !logs.is_empty() {
-                            info!(
-                                "Found {} new logs in blocks {} to {}",
-                                logs.len(),
-                                last_processed_block + 1,
-                                current_block
-                            );
-                            self.process_logs(logs).await?;
+    /// Apply a range's events to the loan state, snapshotting the active
+    /// loans at every snapshot block along the way. A snapshot that cannot
+    /// be taken, for example because the node has pruned that block's
+    /// state, is skipped.
+    async fn take_snapshots(
+        &mut self,
+        from: u64,
+        to: u64,
+        events: &[IndexedEvent],
+    ) -> Vec<Snapshot> {
+        let Some(snapshotter) = &self.snapshots else {
+            return Vec::new();
+        };
+
+        let mut taken = Vec::new();
+        let mut pending = events.iter().peekable();
+        for block in snapshotter.blocks_in(from, to) {
+            while let Some(event) = pending.next_if(|e| e.block_number <= block) {
+                self.loan_state.apply(event);
+            }
+            let loans = self.loan_state.active_loans();
+            if loans.is_empty() {
+                continue;
+            }
+
+            let timestamp = match self.headers.get(block).await {
+                Ok(Some(header)) => header.timestamp,
+                Ok(None) => {
+                    warn!("Skipping the snapshot at block {}: no such block", block);
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Skipping the snapshot at block {}: {:#}", block, e);
+                    continue;
+                }
+            };
+            match snapshotter.take(block, timestamp, &loans).await {
+                Ok(snapshot) => {
+                    info!(
+                        "Snapshotted {} active loans at block {}",
+                        loans.len(),
+                        block
+                    );
+                    taken.push(snapshot);
+                }
+                Err(e) => warn!("Skipping the snapshot at block {}: {:#}", block, e),
+            }
+        }
+        for event in pending {
+            self.loan_state.apply(event);
+        }
+        taken
+    }
+
+    /// Calls and ERC20 transfer logs in `from..=to`, whichever are indexed.
+    /// A range is only stored once all of them have been fetched.
+    async fn fetch_extras(&mut self, from: u64, to: u64) -> Result<(Vec<IndexedCall>, Vec<Log>)> {
+        let calls = self.fetch_calls(from, to).await?;
+        let transfers = match &self.transfers {
+            Some(fetcher) => fetcher.fetch(from, to).await?,
+            None => Vec::new(),
+        };
+        Ok((calls, transfers))
+    }
+
+    /// Decode transfer logs, linking each to the range's event from the
+    /// same transaction
+    async fn process_transfers(
+        &mut self,
+        logs: Vec<Log>,
+        events: &[IndexedEvent],
+    ) -> Result<Vec<IndexedTransfer>> {
+        if logs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let blocks = logs.iter().filter_map(|log| log.block_number);
+        if let Err(e) = self.headers.prefetch(blocks.map(|n| n.as_u64())).await {
+            warn!("Batched header fetch failed, fetching per block: {}", e);
+        }
+
+        let mut transfers = Vec::with_capacity(logs.len());
+        for log in &logs {
+            let timestamp = match log.block_number {
+                Some(number) => self.block_timestamp(number.as_u64()).await?,
+                None => 0,
+            };
+            transfers.extend(transfers::decode(
+                self.contract_address,
+                log,
+                timestamp,
+                events,
+            ));
+        }
+        let linked = transfers.iter().filter(|t| t.action.is_some()).count();
+        info!(
+            "Found {} token transfers ({} linked to a loan action)",
+            transfers.len(),
+            linked
+        );
+        Ok(transfers)
+    }
+
+    /// Calls to the contract in `from..=to` if calls are indexed. Their
+    /// block headers come with them and go into the header cache.
+    async fn fetch_calls(&mut self, from: u64, to: u64) -> Result<Vec<IndexedCall>> {
+        let Some(fetcher) = &self.calls else {
+            return Ok(Vec::new());
+        };
+        let (calls, headers) = fetcher.fetch(from, to).await?;
+        for header in headers {
+            self.headers.cache.insert(header);
+        }
+        if !calls.is_empty() {
+            let reverted = calls.iter().filter(|c| !c.success).count();
+            info!(
+                "Found {} calls ({} reverted) in blocks {} to {}",
+                calls.len(),
+                reverted,
+                from,
+                to
+            );
+        }
+        Ok(calls)
+    }
+
+    /// Cached headers of the blocks `events` and `calls` are in, plus
+    /// `extra`
+    fn batch_headers(
+        &self,
+        events: &[IndexedEvent],
+        calls: &[IndexedCall],
+        transfers: &[IndexedTransfer],
+        extra: Option<BlockHeader>,
+    ) -> Vec<BlockHeader> {
+        let mut numbers: Vec<u64> = events
+            .iter()
+            .map(|e| e.block_number)
+            .chain(calls.iter().map(|c| c.block_number))
+            .chain(transfers.iter().map(|t| t.block_number))
+            .collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+
+        let mut headers: Vec<BlockHeader> = numbers
+            .into_iter()
+            .filter_map(|n| self.headers.cache.peek(n))
+            .collect();
+        if let Some(header) = extra {
+            if !headers.iter().any(|h| h.number == header.number) {
+                headers.push(header);
+            }
+        }
+        headers
+    }
+
+    /// Re-fetch recorded gaps whose backoff has expired, or all of them if
+    /// `all` is set
+    async fn retry_gaps(&mut self, all: bool) -> Result<()> {
+        let ranges: Vec<(u64, u64)> = if all {
+            self.gaps
+                .iter()
+                .map(|g| (g.from_block, g.to_block))
+                .collect()
+        } else {
+            self.gaps.due(Utc::now())
+        };
+        if ranges.is_empty() {
+            return Ok(());
+        }
+
+        for (from, to) in ranges {
+            if self.shutdown.requested() {
+                break;
+            }
+            info!("Retrying gap {} to {}", from, to);
+            let fetched = fetch_range(self.provider.clone(), self.contract_address, from, to).await;
+            let fetched = match fetched.logs {
+                Ok(logs) => self
+                    .fetch_extras(from, to)
+                    .await
+                    .map(|(calls, transfers)| (logs, calls, transfers)),
+                Err(e) => Err(e.into()),
+            };
+            match fetched {
+                Ok((logs, calls, transfer_logs)) => {
+                    let events = self.process_logs(logs).await?;
+                    let transfers = self.process_transfers(transfer_logs, &events).await?;
+                    let blocks = self.batch_headers(&events, &calls, &transfers, None);
+                    self.sink
+                        .write_batch(&events, &calls, &transfers, &blocks, None)
+                        .await?;
+                    self.gaps.resolve(from, to);
+                    self.metrics.count_gap_filled();
+                    info!("Filled gap {} to {}", from, to);
+                    // Applied late, so snapshots taken while the gap was
+                    // open did not see these events
+                    if self.snapshots.is_some() {
+                        for event in &events {
+                            self.loan_state.apply(event);
                         }
                     }
-                    Err(e) => {
-                        error!("Error getting new logs: {}", e);
-                    }
                 }
+                Err(e) => {
+                    warn!("Gap {} to {} still failing: {:#}", from, to, e);
+                    self.gaps.record_failure(from, to, format!("{:#}", e));
+                    self.caught_up = false;
+                    self.metrics.count_gap_failure();
+                }
+            }
+        }
+        self.gaps.save(&self.output_dir)?;
+        self.metrics.set_gaps(&self.gaps);
+
+        Ok(())
+    }
+
+    /// Latest block that has at least `confirmations` blocks on top of it
+    async fn confirmed_head(&self) -> Result<u64> {
+        let head = self.provider.get_block_number().await?.as_u64();
+        self.metrics.set_head_block(head);
+        Ok(head.saturating_sub(self.confirmations))
+    }
 
-                last_processed_block = current_block;
+    async fn block_hash(&self, number: u64) -> Result<Option<H256>> {
+        Ok(self
+            .provider
+            .get_block(number)
+            .await?
+            .and_then(|block| block.hash))
+    }
+
+    /// Compare the hash we recorded for `last_processed_block` with the
+    /// chain. On a mismatch, walk back through the window to the newest
+    /// block both agree on and return it as the common ancestor.
+    async fn check_for_reorg(&self, last_processed_block: u64) -> Result<Option<u64>> {
+        let Some(expected) = self.recent_blocks.get(last_processed_block) else {
+            return Ok(None);
+        };
+        if self.block_hash(last_processed_block).await? == Some(expected) {
+            return Ok(None);
+        }
+
+        warn!(
+            "Reorg detected: block {} is no longer 0x{:x}",
+            last_processed_block, expected
+        );
+
+        for (number, hash) in self.recent_blocks.iter_rev() {
+            if self.block_hash(number).await? == Some(hash) {
+                info!("Common ancestor found at block {}", number);
+                return Ok(Some(number));
             }
         }
+
+        // Deeper than anything we remember, re-index from before the window
+        let ancestor = self
+            .recent_blocks
+            .oldest()
+            .unwrap_or(last_processed_block)
+            .saturating_sub(1);
+        error!(
+            "Reorg is deeper than the {} tracked blocks, re-indexing from block {}",
+            neurolend_indexer::reorg::REORG_WINDOW,
+            ancestor + 1
+        );
+        Ok(Some(ancestor))
     }
 
-    async fn process_logs(&self, logs: Vec<Log>) -> Result<()> {
-        for log in logs {
-            self.process_single_log(&log).await?;
+    /// Retract every event above `ancestor` and move the checkpoint back so
+    /// the orphaned range is indexed again from the canonical chain.
+    async fn rollback(&mut self, ancestor: u64) -> Result<()> {
+        let checkpoint = Checkpoint::new(ancestor + 1, self.caught_up);
+        let removed = self.sink.rollback(ancestor, &checkpoint).await?;
+
+        self.recent_blocks.truncate_from(ancestor + 1);
+        self.headers.cache.invalidate_after(ancestor);
+        self.gaps.truncate_after(ancestor);
+        self.gaps.save(&self.output_dir)?;
+        self.metrics.count_reorg();
+        self.metrics.set_gaps(&self.gaps);
+        self.metrics.set_last_indexed_block(ancestor);
+        if self.snapshots.is_some() {
+            self.loan_state = IndexedState::rebuild(&self.sink.load_events().await?);
         }
+
+        warn!(
+            "Rolled back to block {}: removed {} events from orphaned blocks",
+            ancestor, removed
+        );
         Ok(())
     }
 
-    async fn process_single_log(&self, log: &Log) -> Result<()> {
+    async fn process_logs(&mut self, logs: Vec<Log>) -> Result<Vec<IndexedEvent>> {
+        // One batched request for the headers of every block these logs are in
+        let blocks = logs.iter().filter_map(|log| log.block_number);
+        if let Err(e) = self.headers.prefetch(blocks.map(|n| n.as_u64())).await {
+            warn!("Batched header fetch failed, fetching per block: {}", e);
+        }
+
+        let mut events = Vec::with_capacity(logs.len());
+        for log in logs {
+            events.extend(self.process_single_log(&log).await?);
+        }
+        Ok(events)
+    }
+
+    async fn process_single_log(&mut self, log: &Log) -> Result<Option<IndexedEvent>> {
         // Get the transaction to extract more details
         let tx_hash = log.transaction_hash.unwrap_or_default();
         let block_number = log.block_number.unwrap_or_default().as_u64();
 
         // Get block timestamp
-        let block = self.provider.get_block(block_number).await?;
-        let timestamp = block.map(|b| b.timestamp).unwrap_or_default();
+        let timestamp = self.block_timestamp(block_number).await?;
 
         // Extract event signature (first topic)
         if log.topics.is_empty() {
             warn!("Log has no topics, skipping");
-            return Ok(());
+            return Ok(None);
         }
 
         let event_signature = log.topics[0];
@@ -206,6 +1315,18 @@ impl NeuroLendIndexer {
         // You can add your existing event processing logic here
         match self.identify_event(&event_signature) {
             Some(event_name) => {
+                let decoded_data = match self.decoder.decode(log) {
+                    Some(Ok(params)) => Some(params),
+                    Some(Err(e)) => {
+                        warn!(
+                            "Could not decode {} log in tx 0x{:x}: {:#}",
+                            event_name, tx_hash, e
+                        );
+                        None
+                    }
+                    None => None,
+                };
+
                 info!(
                     "Processing {} event in tx {} (block {})",
                     event_name,
@@ -214,61 +1335,52 @@ impl NeuroLendIndexer {
                 );
 
                 // Create event data structure
-                let event_data = json!({
-                    "event_name": event_name,
-                    "transaction_hash": format!("0x{:x}", tx_hash),
-                    "block_number": block_number,
-                    "block_timestamp": timestamp.as_u64(),
-                    "log_index": log.log_index.unwrap_or_default().as_u64(),
-                    "contract_address": format!("0x{:x}", self.contract_address),
-                    "topics": log.topics.iter().map(|t| format!("0x{:x}", t)).collect::<Vec<_>>(),
-                    "data": format!("0x{}", hex::encode(&log.data))
-                });
-
-                // Save event to JSON file
-                self.save_event_to_file(&event_data, event_name, block_number)
-                    .await?;
-
-                // Also print to console for debugging
-                println!("{}", serde_json::to_string_pretty(&event_data)?);
+                let event = IndexedEvent {
+                    event_name,
+                    transaction_hash: format!("0x{:x}", tx_hash),
+                    block_number,
+                    block_timestamp: timestamp,
+                    log_index: log.log_index.unwrap_or_default().as_u64(),
+                    contract_address: format!("0x{:x}", self.contract_address),
+                    topics: log.topics.iter().map(|t| format!("0x{:x}", t)).collect(),
+                    data: format!("0x{}", hex::encode(&log.data)),
+                    decoded_data,
+                };
+
+                self.metrics.count_log(&event.event_name);
+                return Ok(Some(event));
             }
             None => {
-                warn!("Unknown event signature: 0x{:x}", event_signature);
+                // Warn once per topic; the self-check explains what it means
+                self.metrics.count_log("unknown");
+                let count = self.unknown_topics.entry(event_signature).or_insert(0);
+                *count += 1;
+                if *count == 1 {
+                    warn!(
+                        "Unknown event signature: 0x{:x} (not in the ABI, further occurrences are not logged)",
+                        event_signature
+                    );
+                }
             }
         }
 
-        Ok(())
+        Ok(None)
     }
 
-    fn identify_event(&self, signature: &H256) -> Option<&'static str> {
-        self.event_signatures.get(signature).copied()
+    /// Timestamp from the header cache, falling back to a plain
+    /// `eth_getBlockByNumber` if the node rejects batched requests
+    async fn block_timestamp(&mut self, block_number: u64) -> Result<u64> {
+        match self.headers.get(block_number).await {
+            Ok(header) => Ok(header.map(|h| h.timestamp).unwrap_or_default()),
+            Err(e) => {
+                warn!("Header cache miss for block {}: {}", block_number, e);
+                let block = self.provider.get_block(block_number).await?;
+                Ok(block.map(|b| b.timestamp.as_u64()).unwrap_or_default())
+            }
+        }
     }
 
-    async fn save_event_to_file(
-        &self,
-        event_data: &serde_json::Value,
-        event_name: &str,
-        block_number: u64,
-    ) -> Result<()> {
-        // Create filename with timestamp and block number for uniqueness
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_millis();
-
-        let filename = format!(
-            "{}_{}_{}_{}.json",
-            event_name,
-            block_number,
-            timestamp,
-            rand::random::<u32>()
-        );
-        let filepath = Path::new(&self.output_dir).join(filename);
-
-        // Save the event data to file
-        let json_string = serde_json::to_string_pretty(event_data)?;
-        fs::write(&filepath, json_string)?;
-
-        info!("Saved {} event to file: {:?}", event_name, filepath);
-        Ok(())
+    fn identify_event(&self, signature: &H256) -> Option<String> {
+        self.event_signatures.get(signature).cloned()
     }
 }
@@ -0,0 +1,374 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{info, warn};
+
+use crate::gaps::GapList;
+use crate::rpc_pool::{EndpointHealth, RpcPool};
+
+/// Prefix of every metric name
+const PREFIX: &str = "neurolend_indexer";
+/// Upper bounds of the batch duration buckets, in seconds
+const BATCH_BUCKETS: [f64; 10] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0];
+/// Most a scrape's request line and headers may take up; the rest of a
+/// longer request is not read
+const MAX_REQUEST_BYTES: u64 = 8 * 1024;
+/// How long a client gets to send its request before it is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the indexer reports on `/metrics`. Clones share the same values.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    values: Arc<Mutex<Values>>,
+}
+
+#[derive(Debug, Default)]
+struct Values {
+    head_block: Option<u64>,
+    last_indexed_block: Option<u64>,
+    /// Logs found per event name, `unknown` for topics not in the ABI
+    logs: BTreeMap<String, u64>,
+    batch_seconds: Histogram,
+    batch_retries: u64,
+    batch_splits: u64,
+    gap_failures: u64,
+    gaps_filled: u64,
+    open_gaps: u64,
+    missing_blocks: u64,
+    reorgs: u64,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations at or below each of `BATCH_BUCKETS`
+    buckets: [u64; BATCH_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(BATCH_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+impl Metrics {
+    /// Latest block the node reported
+    pub fn set_head_block(&self, block: u64) {
+        self.values().head_block = Some(block);
+    }
+
+    /// Last block stored and checkpointed
+    pub fn set_last_indexed_block(&self, block: u64) {
+        self.values().last_indexed_block = Some(block);
+    }
+
+    pub fn count_log(&self, event_name: &str) {
+        *self
+            .values()
+            .logs
+            .entry(event_name.to_string())
+            .or_default() += 1;
+    }
+
+    /// A range was processed and stored
+    pub fn observe_batch(&self, duration: Duration, retries: u32, splits: u32) {
+        let mut values = self.values();
+        values.batch_seconds.observe(duration.as_secs_f64());
+        values.batch_retries += u64::from(retries);
+        values.batch_splits += u64::from(splits);
+    }
+
+    /// A range, new or a retried gap, could not be fetched
+    pub fn count_gap_failure(&self) {
+        self.values().gap_failures += 1;
+    }
+
+    pub fn count_gap_filled(&self) {
+        self.values().gaps_filled += 1;
+    }
+
+    pub fn set_gaps(&self, gaps: &GapList) {
+        let mut values = self.values();
+        values.open_gaps = gaps.len() as u64;
+        values.missing_blocks = gaps.missing_blocks();
+    }
+
+    pub fn count_reorg(&self) {
+        self.values().reorgs += 1;
+    }
+
+    /// Everything in the Prometheus text format, with the health of
+    /// `endpoints` as seen by the RPC pool
+    pub fn render(&self, endpoints: &[EndpointHealth]) -> String {
+        let values = self.values();
+        let mut out = String::new();
+
+        let blocks = [
+            (
+                "head_block",
+                "Latest block the node reported",
+                values.head_block,
+            ),
+            (
+                "last_indexed_block",
+                "Last block stored and checkpointed",
+                values.last_indexed_block,
+            ),
+        ];
+        for (name, help, value) in blocks {
+            if let Some(value) = value {
+                family(&mut out, name, "gauge", help);
+                sample(&mut out, name, &[], value);
+            }
+        }
+        if let (Some(head), Some(last)) = (values.head_block, values.last_indexed_block) {
+            family(&mut out, "lag_blocks", "gauge", "Blocks behind the head");
+            sample(&mut out, "lag_blocks", &[], head.saturating_sub(last));
+        }
+
+        family(
+            &mut out,
+            "logs_total",
+            "counter",
+            "Contract logs found, by event",
+        );
+        for (event, count) in &values.logs {
+            sample(&mut out, "logs_total", &[("event", event)], count);
+        }
+
+        let histogram = &values.batch_seconds;
+        family(
+            &mut out,
+            "batch_duration_seconds",
+            "histogram",
+            "Time to process and store a block range",
+        );
+        for (bound, count) in BATCH_BUCKETS.iter().zip(histogram.buckets) {
+            let bound = bound.to_string();
+            sample(
+                &mut out,
+                "batch_duration_seconds_bucket",
+                &[("le", &bound)],
+                count,
+            );
+        }
+        sample(
+            &mut out,
+            "batch_duration_seconds_bucket",
+            &[("le", "+Inf")],
+            histogram.count,
+        );
+        sample(&mut out, "batch_duration_seconds_sum", &[], histogram.sum);
+        sample(
+            &mut out,
+            "batch_duration_seconds_count",
+            &[],
+            histogram.count,
+        );
+
+        let counters = [
+            (
+                "get_logs_retries_total",
+                "eth_getLogs attempts that failed and were retried",
+                values.batch_retries,
+            ),
+            (
+                "range_splits_total",
+                "Times a range was halved because the node rejected it as too large",
+                values.batch_splits,
+            ),
+            (
+                "gap_failures_total",
+                "Ranges that could not be fetched, new or retried gaps",
+                values.gap_failures,
+            ),
+            (
+                "gaps_filled_total",
+                "Gaps fetched on retry",
+                values.gaps_filled,
+            ),
+            ("reorgs_total", "Reorgs rolled back", values.reorgs),
+        ];
+        for (name, help, value) in counters {
+            family(&mut out, name, "counter", help);
+            sample(&mut out, name, &[], value);
+        }
+        family(
+            &mut out,
+            "open_gaps",
+            "gauge",
+            "Ranges still to be re-fetched",
+        );
+        sample(&mut out, "open_gaps", &[], values.open_gaps);
+        family(&mut out, "missing_blocks", "gauge", "Blocks in open gaps");
+        sample(&mut out, "missing_blocks", &[], values.missing_blocks);
+        drop(values);
+
+        render_endpoints(&mut out, endpoints);
+        out
+    }
+
+    fn values(&self) -> std::sync::MutexGuard<'_, Values> {
+        self.values.lock().unwrap()
+    }
+}
+
+/// Reads one metric's value off an endpoint
+type EndpointValue = fn(&EndpointHealth) -> f64;
+
+fn render_endpoints(out: &mut String, endpoints: &[EndpointHealth]) {
+    let labels: Vec<String> = endpoints.iter().map(|e| redact(&e.url)).collect();
+    let families: [(&str, &str, &str, EndpointValue); 5] = [
+        (
+            "rpc_latency_seconds",
+            "gauge",
+            "Moving average of the endpoint's response time",
+            |e| e.latency_ms / 1000.0,
+        ),
+        (
+            "rpc_error_rate",
+            "gauge",
+            "Moving average of the endpoint's failures, from 0 to 1",
+            |e| e.error_rate,
+        ),
+        (
+            "rpc_requests_total",
+            "counter",
+            "Requests sent to the endpoint",
+            |e| e.requests as f64,
+        ),
+        (
+            "rpc_errors_total",
+            "counter",
+            "Requests to the endpoint that failed",
+            |e| e.failures as f64,
+        ),
+        (
+            "rpc_benched",
+            "gauge",
+            "1 while the endpoint is benched after repeated failures",
+            |e| u8::from(e.benched).into(),
+        ),
+    ];
+    for (name, kind, help, value) in families {
+        family(out, name, kind, help);
+        for (endpoint, label) in endpoints.iter().zip(&labels) {
+            sample(out, name, &[("endpoint", label)], value(endpoint));
+        }
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    let _ = write!(out, "{}_{}", PREFIX, name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+            .collect();
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Scheme, host and port of an endpoint URL. Paths and query strings often
+/// carry API keys, and `/metrics` is usually reachable by more people than
+/// the config is.
+fn redact(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => match (parsed.host_str(), parsed.port()) {
+            (Some(host), Some(port)) => format!("{}://{}:{}", parsed.scheme(), host, port),
+            (Some(host), None) => format!("{}://{}", parsed.scheme(), host),
+            _ => parsed.scheme().to_string(),
+        },
+        Err(_) => "invalid".to_string(),
+    }
+}
+
+/// Bind `addr` and serve `/metrics` from it in the background
+pub async fn serve(addr: SocketAddr, metrics: Metrics, pool: RpcPool) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding the metrics server to {}", addr))?;
+    info!("Serving metrics at http://{}/metrics", addr);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let (metrics, pool) = (metrics.clone(), pool.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream, &metrics, &pool).await {
+                            warn!("Metrics request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Could not accept a metrics connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Answer one HTTP request and close the connection
+async fn respond(stream: TcpStream, metrics: &Metrics, pool: &RpcPool) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader.take(MAX_REQUEST_BYTES));
+    let mut request_line = String::new();
+    let read = async {
+        reader.read_line(&mut request_line).await?;
+        // Skip the headers; a scrape has no body
+        let mut header = String::new();
+        while reader.read_line(&mut header).await? > 0 && header.trim_end() != "" {
+            header.clear();
+        }
+        Ok::<_, std::io::Error>(())
+    };
+    tokio::time::timeout(REQUEST_TIMEOUT, read)
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "request timed out"))??;
+
+    let target = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let path = target.split('?').next().unwrap_or_default();
+    let (status, content_type, body) = if path == "/metrics" {
+        (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            metrics.render(&pool.health()),
+        )
+    } else {
+        ("404 Not Found", "text/plain", "Not found\n".to_string())
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await
+}
@@ -0,0 +1,571 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use ethers::abi::ParamType;
+use ethers::types::Address;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::Row;
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+use crate::calls::IndexedCall;
+use crate::checkpoint::Checkpoint;
+use crate::event_signatures::{id_keyed_events, neurolend_abi};
+use crate::headers::BlockHeader;
+use crate::sink::{IndexedEvent, Replace};
+use crate::snapshots::Snapshot;
+use crate::transfers::IndexedTransfer;
+
+/// Statements for one event table
+struct EventTable {
+    name: String,
+    insert: String,
+    /// Reads the table back, every parameter column as text
+    select: String,
+    /// ABI parameter names and types, in the order they are bound after the
+    /// four common columns
+    params: Vec<String>,
+    kinds: Vec<ParamType>,
+}
+
+/// Writes events into one table per event type (see `migrations/`), with a
+/// transaction per batch. Events that did not decode go to
+/// `undecoded_events` as raw topics and data.
+pub struct PostgresSink {
+    pool: PgPool,
+    contract: String,
+    tables: HashMap<String, EventTable>,
+    index_calls: bool,
+    index_transfers: bool,
+}
+
+impl PostgresSink {
+    /// Connect and bring the schema up to date
+    pub async fn connect(
+        database_url: &str,
+        contract: Address,
+        index_calls: bool,
+        index_transfers: bool,
+    ) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(database_url)
+            .await
+            .context("connecting to Postgres")?;
+
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await
+            .context("running database migrations")?;
+        info!("Connected to Postgres, schema up to date");
+
+        Ok(Self {
+            pool,
+            contract: format!("0x{:x}", contract),
+            tables: event_tables(),
+            index_calls,
+            index_transfers,
+        })
+    }
+
+    pub async fn write_batch(
+        &self,
+        events: &[IndexedEvent],
+        calls: &[IndexedCall],
+        transfers: &[IndexedTransfer],
+        blocks: &[BlockHeader],
+        checkpoint: Option<&Checkpoint>,
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        self.insert(&mut tx, events, calls, transfers, blocks).await?;
+        if let Some(checkpoint) = checkpoint {
+            upsert_checkpoint(&mut tx, &self.contract, checkpoint).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Delete what `scope` covers and insert the replacements in one
+    /// transaction. Calls and transfers are only replaced if they are
+    /// indexed, so a backfill without them keeps the stored ones.
+    pub async fn replace(
+        &self,
+        scope: &Replace,
+        events: &[IndexedEvent],
+        calls: &[IndexedCall],
+        transfers: &[IndexedTransfer],
+        blocks: &[BlockHeader],
+    ) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut removed = 0;
+        match scope {
+            Replace::Blocks { from, to } => {
+                for table in self.tables.values() {
+                    removed += sqlx::query(&format!(
+                        "DELETE FROM {} WHERE block_number BETWEEN $1 AND $2",
+                        table.name
+                    ))
+                    .bind(*from as i64)
+                    .bind(*to as i64)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                }
+                removed += sqlx::query(
+                    "DELETE FROM undecoded_events WHERE block_number BETWEEN $1 AND $2",
+                )
+                .bind(*from as i64)
+                .bind(*to as i64)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+                if self.index_calls {
+                    sqlx::query("DELETE FROM calls WHERE block_number BETWEEN $1 AND $2")
+                        .bind(*from as i64)
+                        .bind(*to as i64)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                if self.index_transfers {
+                    sqlx::query("DELETE FROM erc20_transfers WHERE block_number BETWEEN $1 AND $2")
+                        .bind(*from as i64)
+                        .bind(*to as i64)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            }
+            Replace::Loan { loan_id, topic, to } => {
+                let keyed = id_keyed_events();
+                // The id is the first indexed parameter, so topics[2] in
+                // SQL's one-based arrays
+                removed += sqlx::query(
+                    "DELETE FROM undecoded_events
+                     WHERE event_name = ANY($1) AND topics[2] = $2 AND block_number <= $3",
+                )
+                .bind(keyed.keys().cloned().collect::<Vec<_>>())
+                .bind(topic)
+                .bind(*to as i64)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+                for (event_name, param) in keyed {
+                    let Some(table) = self.tables.get(&event_name) else {
+                        continue;
+                    };
+                    removed += sqlx::query(&format!(
+                        "DELETE FROM {} WHERE {} = $1::numeric AND block_number <= $2",
+                        table.name,
+                        snake_case(&param)
+                    ))
+                    .bind(loan_id)
+                    .bind(*to as i64)
+                    .execute(&mut *tx)
+                    .await?
+                    .rows_affected();
+                }
+            }
+        }
+
+        let calls = if self.index_calls { calls } else { &[] };
+        let transfers = if self.index_transfers { transfers } else { &[] };
+        self.insert(&mut tx, events, calls, transfers, blocks).await?;
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    async fn insert(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        events: &[IndexedEvent],
+        calls: &[IndexedCall],
+        transfers: &[IndexedTransfer],
+        blocks: &[BlockHeader],
+    ) -> Result<()> {
+        for block in blocks {
+            sqlx::query(
+                "INSERT INTO blocks (number, hash, parent_hash, timestamp) VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (number) DO UPDATE
+                 SET hash = EXCLUDED.hash, parent_hash = EXCLUDED.parent_hash, timestamp = EXCLUDED.timestamp",
+            )
+            .bind(block.number as i64)
+            .bind(format!("0x{:x}", block.hash))
+            .bind(format!("0x{:x}", block.parent_hash))
+            .bind(block.timestamp as i64)
+            .execute(&mut **tx)
+            .await?;
+        }
+
+        for event in events {
+            let (Some(table), Some(decoded)) =
+                (self.tables.get(&event.event_name), &event.decoded_data)
+            else {
+                warn!(
+                    "{} event in tx {} was not decoded, storing it raw",
+                    event.event_name, event.transaction_hash
+                );
+                sqlx::query(
+                    "INSERT INTO undecoded_events (block_number, log_index, transaction_hash,
+                                                   block_timestamp, event_name, topics, data)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7)
+                     ON CONFLICT (block_number, log_index) DO NOTHING",
+                )
+                .bind(event.block_number as i64)
+                .bind(event.log_index as i32)
+                .bind(&event.transaction_hash)
+                .bind(event.block_timestamp as i64)
+                .bind(&event.event_name)
+                .bind(&event.topics)
+                .bind(&event.data)
+                .execute(&mut **tx)
+                .await
+                .context("inserting into undecoded_events")?;
+                continue;
+            };
+
+            let mut query = sqlx::query(&table.insert)
+                .bind(event.block_number as i64)
+                .bind(event.log_index as i32)
+                .bind(&event.transaction_hash)
+                .bind(event.block_timestamp as i64);
+            for param in &table.params {
+                // Every decoded value is a string (numbers in decimal); the
+                // statement casts it to the column type
+                let value = decoded.get(param).map(|v| match v {
+                    serde_json::Value::String(s) => s.clone(),
+                    other => other.to_string(),
+                });
+                query = query.bind(value);
+            }
+            query
+                .execute(&mut **tx)
+                .await
+                .with_context(|| format!("inserting into {}", table.name))?;
+        }
+
+        for call in calls {
+            let decoded_input = call
+                .decoded_input
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?;
+            sqlx::query(
+                "INSERT INTO calls (block_number, transaction_index, transaction_hash, block_timestamp,
+                                    function_name, function_signature, from_address, value, success,
+                                    gas_used, input, decoded_input)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8::numeric, $9, $10, $11, $12::jsonb)
+                 ON CONFLICT (block_number, transaction_index) DO NOTHING",
+            )
+            .bind(call.block_number as i64)
+            .bind(call.transaction_index as i32)
+            .bind(&call.transaction_hash)
+            .bind(call.block_timestamp as i64)
+            .bind(&call.function_name)
+            .bind(&call.function_signature)
+            .bind(&call.from_address)
+            .bind(&call.value)
+            .bind(call.success)
+            .bind(call.gas_used as i64)
+            .bind(&call.input)
+            .bind(decoded_input)
+            .execute(&mut **tx)
+            .await
+            .context("inserting into calls")?;
+        }
+
+        for transfer in transfers {
+            sqlx::query(
+                "INSERT INTO erc20_transfers (block_number, log_index, transaction_hash, block_timestamp,
+                                              token_address, from_address, to_address, value, direction,
+                                              action, loan_id, request_id)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8::numeric, $9, $10, $11::numeric, $12::numeric)
+                 ON CONFLICT (block_number, log_index) DO NOTHING",
+            )
+            .bind(transfer.block_number as i64)
+            .bind(transfer.log_index as i32)
+            .bind(&transfer.transaction_hash)
+            .bind(transfer.block_timestamp as i64)
+            .bind(&transfer.token_address)
+            .bind(&transfer.from_address)
+            .bind(&transfer.to_address)
+            .bind(&transfer.value)
+            .bind(&transfer.direction)
+            .bind(&transfer.action)
+            .bind(&transfer.loan_id)
+            .bind(&transfer.request_id)
+            .execute(&mut **tx)
+            .await
+            .context("inserting into erc20_transfers")?;
+        }
+        Ok(())
+    }
+
+    /// Upsert, since snapshots ahead of the checkpoint are taken again
+    /// after a restart
+    pub async fn write_snapshots(&self, snapshots: &[Snapshot]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for snapshot in snapshots {
+            for loan in &snapshot.loans {
+                sqlx::query(
+                    "INSERT INTO loan_snapshots (loan_id, block_number, block_timestamp, health_factor,
+                                                 price_stale, defaulted, current_interest, token_address,
+                                                 token_price, collateral_address, collateral_price)
+                     VALUES ($1::numeric, $2, $3, $4::numeric, $5, $6, $7::numeric, $8, $9::numeric, $10, $11::numeric)
+                     ON CONFLICT (loan_id, block_number) DO UPDATE
+                     SET block_timestamp = EXCLUDED.block_timestamp, health_factor = EXCLUDED.health_factor,
+                         price_stale = EXCLUDED.price_stale, defaulted = EXCLUDED.defaulted,
+                         current_interest = EXCLUDED.current_interest, token_address = EXCLUDED.token_address,
+                         token_price = EXCLUDED.token_price, collateral_address = EXCLUDED.collateral_address,
+                         collateral_price = EXCLUDED.collateral_price",
+                )
+                .bind(&loan.loan_id)
+                .bind(snapshot.block_number as i64)
+                .bind(snapshot.block_timestamp as i64)
+                .bind(&loan.health_factor)
+                .bind(loan.price_stale)
+                .bind(loan.defaulted)
+                .bind(&loan.current_interest)
+                .bind(&loan.token_address)
+                .bind(&loan.token_price)
+                .bind(&loan.collateral_address)
+                .bind(&loan.collateral_price)
+                .execute(&mut *tx)
+                .await
+                .context("inserting into loan_snapshots")?;
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Wait for in-flight queries and close the connections
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    pub async fn load_checkpoint(&self) -> Result<Option<Checkpoint>> {
+        let row = sqlx::query(
+            "SELECT current_block, last_updated, caught_up FROM checkpoints WHERE contract_address = $1",
+        )
+        .bind(&self.contract)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|row| Checkpoint {
+            current_block: row.get::<i64, _>("current_block") as u64,
+            last_updated: row.get::<DateTime<Utc>, _>("last_updated"),
+            caught_up: row.get("caught_up"),
+        }))
+    }
+
+    /// Every stored event, rebuilt from the event tables. Topics and raw
+    /// data are only stored for undecoded events, so decoded ones come back
+    /// without them.
+    pub async fn load_events(&self) -> Result<Vec<IndexedEvent>> {
+        let mut events = Vec::new();
+        for (event_name, table) in &self.tables {
+            let rows = sqlx::query(&table.select)
+                .fetch_all(&self.pool)
+                .await
+                .with_context(|| format!("reading {}", table.name))?;
+            for row in rows {
+                let mut decoded = serde_json::Map::new();
+                for (i, (param, kind)) in table.params.iter().zip(&table.kinds).enumerate() {
+                    let value = match row.get::<Option<String>, _>(i + 4) {
+                        Some(text) if *kind == ParamType::Bool => {
+                            serde_json::Value::Bool(text == "true")
+                        }
+                        Some(text) => serde_json::Value::String(text),
+                        None => serde_json::Value::Null,
+                    };
+                    decoded.insert(param.clone(), value);
+                }
+                events.push(IndexedEvent {
+                    event_name: event_name.clone(),
+                    transaction_hash: row.get("transaction_hash"),
+                    block_number: row.get::<i64, _>("block_number") as u64,
+                    block_timestamp: row.get::<i64, _>("block_timestamp") as u64,
+                    log_index: row.get::<i32, _>("log_index") as u64,
+                    contract_address: self.contract.clone(),
+                    topics: Vec::new(),
+                    data: String::new(),
+                    decoded_data: Some(decoded),
+                });
+            }
+        }
+
+        let rows = sqlx::query(
+            "SELECT block_number, log_index, transaction_hash, block_timestamp, event_name, topics, data
+             FROM undecoded_events",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("reading undecoded_events")?;
+        for row in rows {
+            events.push(IndexedEvent {
+                event_name: row.get("event_name"),
+                transaction_hash: row.get("transaction_hash"),
+                block_number: row.get::<i64, _>("block_number") as u64,
+                block_timestamp: row.get::<i64, _>("block_timestamp") as u64,
+                log_index: row.get::<i32, _>("log_index") as u64,
+                contract_address: self.contract.clone(),
+                topics: row.get("topics"),
+                data: row.get("data"),
+                decoded_data: None,
+            });
+        }
+
+        events.sort_by_key(|e| (e.block_number, e.log_index));
+        Ok(events)
+    }
+
+    pub async fn rollback(&self, ancestor: u64, checkpoint: &Checkpoint) -> Result<u64> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut removed = 0;
+        for table in self.tables.values() {
+            removed += sqlx::query(&format!("DELETE FROM {} WHERE block_number > $1", table.name))
+                .bind(ancestor as i64)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        removed += sqlx::query("DELETE FROM undecoded_events WHERE block_number > $1")
+            .bind(ancestor as i64)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        sqlx::query("DELETE FROM calls WHERE block_number > $1")
+            .bind(ancestor as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM erc20_transfers WHERE block_number > $1")
+            .bind(ancestor as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM loan_snapshots WHERE block_number > $1")
+            .bind(ancestor as i64)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM blocks WHERE number > $1")
+            .bind(ancestor as i64)
+            .execute(&mut *tx)
+            .await?;
+        upsert_checkpoint(&mut tx, &self.contract, checkpoint).await?;
+
+        tx.commit().await?;
+        Ok(removed)
+    }
+}
+
+/// Connect to `database_url` and check that its user can create tables and
+/// insert into them, as the migrations and the sink need. The probe table is
+/// rolled back, and existing tables are not touched.
+pub async fn check_writable(database_url: &str) -> Result<()> {
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(database_url)
+        .await
+        .context("connecting to Postgres")?;
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("CREATE TABLE neurolend_doctor_probe (id INTEGER)")
+        .execute(&mut *tx)
+        .await
+        .context("creating a table")?;
+    sqlx::query("INSERT INTO neurolend_doctor_probe VALUES (1)")
+        .execute(&mut *tx)
+        .await
+        .context("inserting a row")?;
+    tx.rollback().await?;
+
+    pool.close().await;
+    Ok(())
+}
+
+async fn upsert_checkpoint(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    contract: &str,
+    checkpoint: &Checkpoint,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO checkpoints (contract_address, current_block, last_updated, caught_up)
+         VALUES ($1, $2, $3, $4)
+         ON CONFLICT (contract_address) DO UPDATE
+         SET current_block = EXCLUDED.current_block,
+             last_updated = EXCLUDED.last_updated,
+             caught_up = EXCLUDED.caught_up",
+    )
+    .bind(contract)
+    .bind(checkpoint.current_block as i64)
+    .bind(checkpoint.last_updated)
+    .bind(checkpoint.caught_up)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Insert statements for every ABI event, matching the tables created by
+/// the migrations
+fn event_tables() -> HashMap<String, EventTable> {
+    neurolend_abi()
+        .events()
+        .map(|event| {
+            let name = snake_case(&event.name);
+            let mut columns = vec![
+                "block_number".to_string(),
+                "log_index".to_string(),
+                "transaction_hash".to_string(),
+                "block_timestamp".to_string(),
+            ];
+            let mut values: Vec<String> = (1..=4).map(|i| format!("${}", i)).collect();
+            let mut selected = columns.clone();
+            for (i, input) in event.inputs.iter().enumerate() {
+                columns.push(snake_case(&input.name));
+                selected.push(format!("{}::text", snake_case(&input.name)));
+                let cast = match input.kind {
+                    ParamType::Uint(_) | ParamType::Int(_) => "::numeric",
+                    ParamType::Bool => "::boolean",
+                    _ => "",
+                };
+                values.push(format!("${}{}", i + 5, cast));
+            }
+
+            let insert = format!(
+                "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT (block_number, log_index) DO NOTHING",
+                name,
+                columns.join(", "),
+                values.join(", ")
+            );
+            let select = format!(
+                "SELECT {} FROM {} ORDER BY block_number, log_index",
+                selected.join(", "),
+                name
+            );
+            let table = EventTable {
+                name,
+                insert,
+                select,
+                params: event.inputs.iter().map(|i| i.name.clone()).collect(),
+                kinds: event.inputs.iter().map(|i| i.kind.clone()).collect(),
+            };
+            (event.name.clone(), table)
+        })
+        .collect()
+}
+
+/// `minCollateralRatioBPS` -> `min_collateral_ratio_bps`
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::with_capacity(name.len() + 4);
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|n| n.is_lowercase());
+            if prev.is_lowercase() || prev.is_ascii_digit() || (prev.is_uppercase() && next_is_lower) {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
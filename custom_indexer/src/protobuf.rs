@@ -0,0 +1,815 @@
+//! The `contract.v1` messages the Substreams module emits, built from the
+//! indexer's records so consumers can read either pipeline's output with the
+//! same code.
+//!
+//! With `protobuf` on, every segment (see `segments`) is mirrored under
+//! `protobuf/` by `events_<from>_<to>.binpb` and, if calls are indexed,
+//! `calls_<from>_<to>.binpb`. Each holds one length-delimited `Events` or
+//! `Calls` message per block that has records, in block order. The files
+//! are rebuilt from the segments whenever those change, so backfills,
+//! reindexing and rollbacks show up in them too.
+
+use anyhow::{anyhow, bail, Context, Result};
+use prost::Message;
+use prost_types::Timestamp;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::calls::IndexedCall;
+use crate::segments::{write_file_atomic, SegmentStore};
+use crate::sink::IndexedEvent;
+use crate::transfers::IndexedTransfer;
+
+/// Generated by the Substreams build from `proto/contract.proto`
+#[allow(clippy::all)]
+pub mod contract {
+    include!("../../src/pb/contract.v1.rs");
+}
+
+/// The segment stores the protobuf files are built from
+pub struct Stores<'a> {
+    pub events: &'a SegmentStore,
+    pub calls: Option<&'a SegmentStore>,
+    pub transfers: Option<&'a SegmentStore>,
+}
+
+impl Stores<'_> {
+    /// `from_block -> to_block` of every segment in any store
+    fn ranges(&self) -> BTreeMap<u64, u64> {
+        let mut ranges = BTreeMap::new();
+        for store in self.all() {
+            for segment in &store.manifest().segments {
+                let to = ranges.entry(segment.from_block).or_insert(segment.to_block);
+                *to = (*to).max(segment.to_block);
+            }
+        }
+        ranges
+    }
+
+    fn all(&self) -> impl Iterator<Item = &SegmentStore> {
+        std::iter::once(self.events)
+            .chain(self.calls)
+            .chain(self.transfers)
+    }
+}
+
+/// Marks a protobuf rebuild in progress, see `ProtobufOutput::begin`
+const REBUILDING_FILE: &str = "rebuilding";
+
+/// Writes and keeps up to date the `.binpb` files mirroring the segments
+pub struct ProtobufOutput {
+    dir: PathBuf,
+}
+
+impl ProtobufOutput {
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Note that the segments are about to change. The note stays until
+    /// `rebuild` has caught the files up, so if the write fails or the
+    /// process dies in between, `recover` knows to rebuild everything.
+    pub fn begin(&self) -> Result<()> {
+        write_file_atomic(&self.dir.join(REBUILDING_FILE), &[])
+    }
+
+    /// Rebuild every file if a write was interrupted, otherwise only those
+    /// missing, e.g. because protobuf output was just turned on
+    pub fn recover(&self, stores: &Stores) -> Result<()> {
+        if self.dir.join(REBUILDING_FILE).exists() {
+            warn!("Rebuilding protobuf files after an interrupted write");
+            return self.rebuild(stores, 0..=u64::MAX);
+        }
+
+        let has_records = |store: Option<&SegmentStore>, from: u64| {
+            store.is_some_and(|store| {
+                store
+                    .manifest()
+                    .segments
+                    .iter()
+                    .any(|s| s.from_block == from && s.events > 0)
+            })
+        };
+        for (&from, &to) in &stores.ranges() {
+            let events_missing = (has_records(Some(stores.events), from)
+                || has_records(stores.transfers, from))
+                && !self.path("events", from, to).exists();
+            let calls_missing =
+                has_records(stores.calls, from) && !self.path("calls", from, to).exists();
+            if events_missing || calls_missing {
+                self.write_range(stores, from, to)?;
+            }
+        }
+        self.prune(stores)
+    }
+
+    /// Rewrite the files of every segment overlapping `blocks` from what the
+    /// stores hold, delete those of segments that are gone and clear the
+    /// note left by `begin`
+    pub fn rebuild(&self, stores: &Stores, blocks: RangeInclusive<u64>) -> Result<()> {
+        for (&from, &to) in stores.ranges().range(..=*blocks.end()) {
+            if to >= *blocks.start() {
+                self.write_range(stores, from, to)?;
+            }
+        }
+        self.prune(stores)?;
+
+        let marker = self.dir.join(REBUILDING_FILE);
+        if marker.exists() {
+            fs::remove_file(&marker).with_context(|| format!("removing {}", marker.display()))?;
+        }
+        Ok(())
+    }
+
+    fn write_range(&self, stores: &Stores, from: u64, to: u64) -> Result<()> {
+        let events: Vec<IndexedEvent> = stores.events.records_in(from..=to)?;
+        let transfers: Vec<IndexedTransfer> = match stores.transfers {
+            Some(store) => store.records_in(from..=to)?,
+            None => Vec::new(),
+        };
+        let mut blocks: BTreeMap<u64, (Vec<&IndexedEvent>, Vec<&IndexedTransfer>)> =
+            BTreeMap::new();
+        for event in &events {
+            blocks.entry(event.block_number).or_default().0.push(event);
+        }
+        for transfer in &transfers {
+            blocks
+                .entry(transfer.block_number)
+                .or_default()
+                .1
+                .push(transfer);
+        }
+
+        let mut content = Vec::new();
+        for (events, transfers) in blocks.values() {
+            let message = events_message(events, transfers)?;
+            if message != contract::Events::default() {
+                message.encode_length_delimited(&mut content)?;
+            }
+        }
+        self.write_file(&self.path("events", from, to), &content)?;
+
+        if let Some(store) = stores.calls {
+            let calls: Vec<IndexedCall> = store.records_in(from..=to)?;
+            let mut blocks: BTreeMap<u64, Vec<&IndexedCall>> = BTreeMap::new();
+            for call in &calls {
+                blocks.entry(call.block_number).or_default().push(call);
+            }
+
+            let mut content = Vec::new();
+            for calls in blocks.values() {
+                let message = calls_message(calls)?;
+                if message != contract::Calls::default() {
+                    message.encode_length_delimited(&mut content)?;
+                }
+            }
+            self.write_file(&self.path("calls", from, to), &content)?;
+        }
+        Ok(())
+    }
+
+    /// Files of ranges with nothing in them are removed rather than left
+    /// empty
+    fn write_file(&self, path: &Path, content: &[u8]) -> Result<()> {
+        if !content.is_empty() {
+            write_file_atomic(path, content)
+        } else if path.exists() {
+            fs::remove_file(path).with_context(|| format!("removing {}", path.display()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Delete the files of segments no store has any more, e.g. after a
+    /// rollback
+    fn prune(&self, stores: &Stores) -> Result<()> {
+        let ranges = stores.ranges();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let Some(from) = name
+                .strip_suffix(".binpb")
+                .and_then(|stem| stem.split('_').nth(1))
+                .and_then(|from| from.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if !ranges.contains_key(&from) {
+                fs::remove_file(&path).with_context(|| format!("removing {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `protobuf/events_0006900000_0006999999.binpb`
+    fn path(&self, kind: &str, from: u64, to: u64) -> PathBuf {
+        self.dir
+            .join(format!("{}_{:010}_{:010}.binpb", kind, from, to))
+    }
+}
+
+/// The events and transfers of one block as the Substreams `Events`.
+/// Events that could not be decoded are left out, as the Substreams module
+/// skips logs that do not match the ABI. `erc20_approvals` and
+/// `generic_logs` stay empty: the indexer only fetches the contract's own
+/// logs and the transfers it is party to.
+pub fn events_message(
+    events: &[&IndexedEvent],
+    transfers: &[&IndexedTransfer],
+) -> Result<contract::Events> {
+    let mut message = contract::Events::default();
+    for event in events {
+        add_event(&mut message, event).with_context(|| {
+            format!(
+                "converting {} at block {} log {}",
+                event.event_name, event.block_number, event.log_index
+            )
+        })?;
+    }
+    for transfer in transfers {
+        message.erc20_transfers.push(contract::Erc20Transfer {
+            evt_tx_hash: tx_hash(&transfer.transaction_hash),
+            evt_index: transfer.log_index as u32,
+            evt_block_time: Some(timestamp(transfer.block_timestamp)),
+            evt_block_number: transfer.block_number,
+            contract_address: decode_hex(&transfer.token_address)?,
+            from: decode_hex(&transfer.from_address)?,
+            to: decode_hex(&transfer.to_address)?,
+            value: transfer.value.clone(),
+        });
+    }
+    Ok(message)
+}
+
+fn add_event(message: &mut contract::Events, event: &IndexedEvent) -> Result<()> {
+    let Some(decoded) = &event.decoded_data else {
+        return Ok(());
+    };
+    let f = Fields(decoded);
+    let evt_tx_hash = tx_hash(&event.transaction_hash);
+    let evt_index = event.log_index as u32;
+    let evt_block_time = Some(timestamp(event.block_timestamp));
+    let evt_block_number = event.block_number;
+
+    match event.event_name.as_str() {
+        "CollateralAdded" => {
+            message
+                .neurolend_collateral_addeds
+                .push(contract::NeurolendCollateralAdded {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    loan_id: f.string("loanId")?,
+                    borrower: f.bytes("borrower")?,
+                    amount: f.string("amount")?,
+                    new_collateral_ratio: f.string("newCollateralRatio")?,
+                    timestamp: f.string("timestamp")?,
+                })
+        }
+        "CollateralRemoved" => {
+            message
+                .neurolend_collateral_removeds
+                .push(contract::NeurolendCollateralRemoved {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    loan_id: f.string("loanId")?,
+                    borrower: f.bytes("borrower")?,
+                    amount: f.string("amount")?,
+                    new_collateral_ratio: f.string("newCollateralRatio")?,
+                    timestamp: f.string("timestamp")?,
+                })
+        }
+        "LoanAccepted" => message
+            .neurolend_loan_accepteds
+            .push(contract::NeurolendLoanAccepted {
+                evt_tx_hash,
+                evt_index,
+                evt_block_time,
+                evt_block_number,
+                loan_id: f.string("loanId")?,
+                borrower: f.bytes("borrower")?,
+                timestamp: f.string("timestamp")?,
+                initial_collateral_ratio: f.string("initialCollateralRatio")?,
+            }),
+        "LoanCreated" => message
+            .neurolend_loan_createds
+            .push(contract::NeurolendLoanCreated {
+                evt_tx_hash,
+                evt_index,
+                evt_block_time,
+                evt_block_number,
+                loan_id: f.string("loanId")?,
+                lender: f.bytes("lender")?,
+                token_address: f.bytes("tokenAddress")?,
+                amount: f.string("amount")?,
+                interest_rate: f.string("interestRate")?,
+                duration: f.string("duration")?,
+                collateral_address: f.bytes("collateralAddress")?,
+                collateral_amount: f.string("collateralAmount")?,
+                min_collateral_ratio_bps: f.string("minCollateralRatioBPS")?,
+                liquidation_threshold_bps: f.string("liquidationThresholdBPS")?,
+                max_price_staleness: f.string("maxPriceStaleness")?,
+            }),
+        "LoanLiquidated" => {
+            message
+                .neurolend_loan_liquidateds
+                .push(contract::NeurolendLoanLiquidated {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    loan_id: f.string("loanId")?,
+                    liquidator: f.bytes("liquidator")?,
+                    collateral_claimed_by_lender: f.string("collateralClaimedByLender")?,
+                    liquidator_reward: f.string("liquidatorReward")?,
+                    timestamp: f.string("timestamp")?,
+                })
+        }
+        "LoanMatched" => message
+            .neurolend_loan_matcheds
+            .push(contract::NeurolendLoanMatched {
+                evt_tx_hash,
+                evt_index,
+                evt_block_time,
+                evt_block_number,
+                loan_id: f.string("loanId")?,
+                offer_id: f.string("offerId")?,
+                request_id: f.string("requestId")?,
+                lender: f.bytes("lender")?,
+                borrower: f.bytes("borrower")?,
+                amount: f.string("amount")?,
+                interest_rate: f.string("interestRate")?,
+                timestamp: f.string("timestamp")?,
+            }),
+        "LoanOfferCancelled" => {
+            message
+                .neurolend_loan_offer_cancelleds
+                .push(contract::NeurolendLoanOfferCancelled {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    loan_id: f.string("loanId")?,
+                    lender: f.bytes("lender")?,
+                    timestamp: f.string("timestamp")?,
+                })
+        }
+        "LoanOfferRemoved" => {
+            message
+                .neurolend_loan_offer_removeds
+                .push(contract::NeurolendLoanOfferRemoved {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    loan_id: f.string("loanId")?,
+                    reason: f.string("reason")?,
+                })
+        }
+        "LoanRepaid" => message
+            .neurolend_loan_repaids
+            .push(contract::NeurolendLoanRepaid {
+                evt_tx_hash,
+                evt_index,
+                evt_block_time,
+                evt_block_number,
+                loan_id: f.string("loanId")?,
+                borrower: f.bytes("borrower")?,
+                repayment_amount: f.string("repaymentAmount")?,
+                timestamp: f.string("timestamp")?,
+            }),
+        "LoanRequestCancelled" => message.neurolend_loan_request_cancelleds.push(
+            contract::NeurolendLoanRequestCancelled {
+                evt_tx_hash,
+                evt_index,
+                evt_block_time,
+                evt_block_number,
+                request_id: f.string("requestId")?,
+                borrower: f.bytes("borrower")?,
+                timestamp: f.string("timestamp")?,
+            },
+        ),
+        "LoanRequestCreated" => {
+            message
+                .neurolend_loan_request_createds
+                .push(contract::NeurolendLoanRequestCreated {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    request_id: f.string("requestId")?,
+                    borrower: f.bytes("borrower")?,
+                    token_address: f.bytes("tokenAddress")?,
+                    amount: f.string("amount")?,
+                    max_interest_rate: f.string("maxInterestRate")?,
+                    duration: f.string("duration")?,
+                    collateral_address: f.bytes("collateralAddress")?,
+                    collateral_amount: f.string("collateralAmount")?,
+                    min_collateral_ratio_bps: f.string("minCollateralRatioBPS")?,
+                    liquidation_threshold_bps: f.string("liquidationThresholdBPS")?,
+                    max_price_staleness: f.string("maxPriceStaleness")?,
+                })
+        }
+        "LoanRequestRemoved" => {
+            message
+                .neurolend_loan_request_removeds
+                .push(contract::NeurolendLoanRequestRemoved {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    request_id: f.string("requestId")?,
+                    reason: f.string("reason")?,
+                })
+        }
+        "OwnershipTransferred" => {
+            message
+                .neurolend_ownership_transferreds
+                .push(contract::NeurolendOwnershipTransferred {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    previous_owner: f.bytes("previousOwner")?,
+                    new_owner: f.bytes("newOwner")?,
+                })
+        }
+        "PartialRepayment" => {
+            message
+                .neurolend_partial_repayments
+                .push(contract::NeurolendPartialRepayment {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    loan_id: f.string("loanId")?,
+                    borrower: f.bytes("borrower")?,
+                    repayment_amount: f.string("repaymentAmount")?,
+                    total_repaid_amount: f.string("totalRepaidAmount")?,
+                    remaining_amount: f.string("remainingAmount")?,
+                    timestamp: f.string("timestamp")?,
+                })
+        }
+        "PriceFeedSet" => message
+            .neurolend_price_feed_sets
+            .push(contract::NeurolendPriceFeedSet {
+                evt_tx_hash,
+                evt_index,
+                evt_block_time,
+                evt_block_number,
+                token_address: f.bytes("tokenAddress")?,
+                feed_id: f.bytes("feedId")?,
+            }),
+        "PriceUpdatePaid" => {
+            message
+                .neurolend_price_update_paids
+                .push(contract::NeurolendPriceUpdatePaid {
+                    evt_tx_hash,
+                    evt_index,
+                    evt_block_time,
+                    evt_block_number,
+                    loan_id: f.string("loanId")?,
+                    update_fee: f.string("updateFee")?,
+                    timestamp: f.string("timestamp")?,
+                })
+        }
+        other => bail!("contract.v1 has no message for event {}", other),
+    }
+    Ok(())
+}
+
+/// The calls of one block as the Substreams `Calls`. Overloads go to the
+/// `1`/`2` message numbered by their order in the ABI, as in the
+/// Substreams module. `call_ordinal` is the transaction index, the closest
+/// the RPC indexer has to a Firehose ordinal, so it orders calls within the
+/// block but does not match the Substreams value.
+pub fn calls_message(calls: &[&IndexedCall]) -> Result<contract::Calls> {
+    let mut message = contract::Calls::default();
+    for call in calls {
+        add_call(&mut message, call).with_context(|| {
+            format!(
+                "converting {} call at block {} transaction {}",
+                call.function_name, call.block_number, call.transaction_index
+            )
+        })?;
+    }
+    Ok(message)
+}
+
+fn add_call(message: &mut contract::Calls, call: &IndexedCall) -> Result<()> {
+    let Some(decoded) = &call.decoded_input else {
+        return Ok(());
+    };
+    let f = Fields(decoded);
+    let call_tx_hash = tx_hash(&call.transaction_hash);
+    let call_block_time = Some(timestamp(call.block_timestamp));
+    let call_block_number = call.block_number;
+    let call_ordinal = call.transaction_index;
+    let call_success = call.success;
+
+    // Signatures of functions with outputs end in `:(<outputs>)`
+    let signature = call
+        .function_signature
+        .split(':')
+        .next()
+        .unwrap_or_default();
+    match signature {
+        "acceptLoanOffer(uint256)" => message.neurolend_call_accept_loan_offer_1s.push(
+            contract::NeurolendAcceptLoanOffer1call {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                loan_id: f.string("loanId")?,
+            },
+        ),
+        "acceptLoanOffer(uint256,bytes[])" => message.neurolend_call_accept_loan_offer_2s.push(
+            contract::NeurolendAcceptLoanOffer2call {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                loan_id: f.string("loanId")?,
+                price_update: f.bytes_list("priceUpdate")?,
+            },
+        ),
+        "addCollateral(uint256,uint256)" => {
+            message
+                .neurolend_call_add_collaterals
+                .push(contract::NeurolendAddCollateralCall {
+                    call_tx_hash,
+                    call_block_time,
+                    call_block_number,
+                    call_ordinal,
+                    call_success,
+                    loan_id: f.string("loanId")?,
+                    additional_amount: f.string("additionalAmount")?,
+                })
+        }
+        "cancelLoanOffer(uint256)" => message.neurolend_call_cancel_loan_offers.push(
+            contract::NeurolendCancelLoanOfferCall {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                loan_id: f.string("loanId")?,
+            },
+        ),
+        "cancelLoanRequest(uint256)" => message.neurolend_call_cancel_loan_requests.push(
+            contract::NeurolendCancelLoanRequestCall {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                request_id: f.string("requestId")?,
+            },
+        ),
+        "createLoanOffer(address,uint256,uint256,uint256,address,uint256,uint256,uint256,uint256)" => {
+            message.neurolend_call_create_loan_offer_1s.push(
+                contract::NeurolendCreateLoanOffer1call {
+                    call_tx_hash,
+                    call_block_time,
+                    call_block_number,
+                    call_ordinal,
+                    call_success,
+                    u_token_address: f.bytes("tokenAddress")?,
+                    u_amount: f.string("amount")?,
+                    u_interest_rate: f.string("interestRate")?,
+                    u_duration: f.string("duration")?,
+                    u_collateral_address: f.bytes("collateralAddress")?,
+                    u_collateral_amount: f.string("collateralAmount")?,
+                    u_min_collateral_ratio_bps: f.string("minCollateralRatioBPS")?,
+                    u_liquidation_threshold_bps: f.string("liquidationThresholdBPS")?,
+                    u_max_price_staleness: f.string("maxPriceStaleness")?,
+                },
+            )
+        }
+        "createLoanOffer(address,uint256,uint256,uint256,address,uint256)" => message
+            .neurolend_call_create_loan_offer_2s
+            .push(contract::NeurolendCreateLoanOffer2call {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                u_token_address: f.bytes("tokenAddress")?,
+                u_amount: f.string("amount")?,
+                u_interest_rate: f.string("interestRate")?,
+                u_duration: f.string("duration")?,
+                u_collateral_address: f.bytes("collateralAddress")?,
+                u_collateral_amount: f.string("collateralAmount")?,
+            }),
+        "createLoanRequest(address,uint256,uint256,uint256,address,uint256)" => message
+            .neurolend_call_create_loan_request_1s
+            .push(contract::NeurolendCreateLoanRequest1call {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                u_token_address: f.bytes("tokenAddress")?,
+                u_amount: f.string("amount")?,
+                u_max_interest_rate: f.string("maxInterestRate")?,
+                u_duration: f.string("duration")?,
+                u_collateral_address: f.bytes("collateralAddress")?,
+                u_collateral_amount: f.string("collateralAmount")?,
+            }),
+        "createLoanRequest(address,uint256,uint256,uint256,address,uint256,uint256,uint256,uint256)" => {
+            message.neurolend_call_create_loan_request_2s.push(
+                contract::NeurolendCreateLoanRequest2call {
+                    call_tx_hash,
+                    call_block_time,
+                    call_block_number,
+                    call_ordinal,
+                    call_success,
+                    u_token_address: f.bytes("tokenAddress")?,
+                    u_amount: f.string("amount")?,
+                    u_max_interest_rate: f.string("maxInterestRate")?,
+                    u_duration: f.string("duration")?,
+                    u_collateral_address: f.bytes("collateralAddress")?,
+                    u_collateral_amount: f.string("collateralAmount")?,
+                    u_min_collateral_ratio_bps: f.string("minCollateralRatioBPS")?,
+                    u_liquidation_threshold_bps: f.string("liquidationThresholdBPS")?,
+                    u_max_price_staleness: f.string("maxPriceStaleness")?,
+                },
+            )
+        }
+        "fillLoanOffer(uint256)" => message.neurolend_call_fill_loan_offer_1s.push(
+            contract::NeurolendFillLoanOffer1call {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                offer_id: f.string("offerId")?,
+            },
+        ),
+        "fillLoanOffer(uint256,bytes[])" => message.neurolend_call_fill_loan_offer_2s.push(
+            contract::NeurolendFillLoanOffer2call {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                offer_id: f.string("offerId")?,
+                price_update: f.bytes_list("priceUpdate")?,
+            },
+        ),
+        "fillLoanRequest(uint256)" => message.neurolend_call_fill_loan_requests.push(
+            contract::NeurolendFillLoanRequestCall {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                request_id: f.string("requestId")?,
+            },
+        ),
+        "liquidateLoan(uint256,bytes[])" => {
+            message
+                .neurolend_call_liquidate_loans
+                .push(contract::NeurolendLiquidateLoanCall {
+                    call_tx_hash,
+                    call_block_time,
+                    call_block_number,
+                    call_ordinal,
+                    call_success,
+                    loan_id: f.string("loanId")?,
+                    price_update: f.bytes_list("priceUpdate")?,
+                })
+        }
+        "makePartialRepayment(uint256,uint256)" => message
+            .neurolend_call_make_partial_repayments
+            .push(contract::NeurolendMakePartialRepaymentCall {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                loan_id: f.string("loanId")?,
+                repayment_amount: f.string("repaymentAmount")?,
+            }),
+        "removeCollateral(uint256,uint256,bytes[])" => message
+            .neurolend_call_remove_collaterals
+            .push(contract::NeurolendRemoveCollateralCall {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                loan_id: f.string("loanId")?,
+                remove_amount: f.string("removeAmount")?,
+                price_update: f.bytes_list("priceUpdate")?,
+            }),
+        "renounceOwnership()" => message.neurolend_call_renounce_ownerships.push(
+            contract::NeurolendRenounceOwnershipCall {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+            },
+        ),
+        "repayLoan(uint256)" => {
+            message
+                .neurolend_call_repay_loans
+                .push(contract::NeurolendRepayLoanCall {
+                    call_tx_hash,
+                    call_block_time,
+                    call_block_number,
+                    call_ordinal,
+                    call_success,
+                    loan_id: f.string("loanId")?,
+                })
+        }
+        "setTokenPriceFeedId(address,bytes32)" => message
+            .neurolend_call_set_token_price_feed_ids
+            .push(contract::NeurolendSetTokenPriceFeedIdCall {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                u_token_address: f.bytes("tokenAddress")?,
+                u_feed_id: f.bytes("feedId")?,
+            }),
+        "transferOwnership(address)" => message.neurolend_call_transfer_ownerships.push(
+            contract::NeurolendTransferOwnershipCall {
+                call_tx_hash,
+                call_block_time,
+                call_block_number,
+                call_ordinal,
+                call_success,
+                new_owner: f.bytes("newOwner")?,
+            },
+        ),
+        other => bail!("contract.v1 has no message for function {}", other),
+    }
+    Ok(())
+}
+
+/// Decoded event or call arguments, as `decoder::token_to_json` left them
+struct Fields<'a>(&'a Map<String, Value>);
+
+impl Fields<'_> {
+    /// Integers stay decimal strings, as in the Substreams messages
+    fn string(&self, key: &str) -> Result<String> {
+        self.0
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("missing {}", key))
+    }
+
+    /// Addresses and fixed-size bytes as raw bytes
+    fn bytes(&self, key: &str) -> Result<Vec<u8>> {
+        decode_hex(&self.string(key)?).with_context(|| format!("reading {}", key))
+    }
+
+    fn bytes_list(&self, key: &str) -> Result<Vec<Vec<u8>>> {
+        self.0
+            .get(key)
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("missing {}", key))?
+            .iter()
+            .map(|item| {
+                let item = item
+                    .as_str()
+                    .ok_or_else(|| anyhow!("{} holds a non-string item", key))?;
+                decode_hex(item)
+            })
+            .collect()
+    }
+}
+
+/// Substreams hashes are lowercase hex without the `0x`
+fn tx_hash(hash: &str) -> String {
+    hash.trim_start_matches("0x").to_lowercase()
+}
+
+fn timestamp(seconds: u64) -> Timestamp {
+    Timestamp {
+        seconds: seconds as i64,
+        nanos: 0,
+    }
+}
+
+fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x")).with_context(|| format!("invalid hex {:?}", value))
+}
@@ -0,0 +1,63 @@
+use ethers::types::H256;
+use std::collections::VecDeque;
+
+/// How many processed block hashes are remembered for reorg detection
+pub const REORG_WINDOW: usize = 128;
+
+/// Hashes of recently processed blocks, oldest first. Used to check that the
+/// chain we are about to extend is still the one we indexed, and to find the
+/// common ancestor when it is not.
+#[derive(Debug, Clone)]
+pub struct BlockWindow {
+    blocks: VecDeque<(u64, H256)>,
+    capacity: usize,
+}
+
+impl Default for BlockWindow {
+    fn default() -> Self {
+        Self::new(REORG_WINDOW)
+    }
+}
+
+impl BlockWindow {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            blocks: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Remember `hash` for block `number`, forgetting anything at or above
+    /// it and the oldest entries beyond the window capacity.
+    pub fn push(&mut self, number: u64, hash: H256) {
+        self.truncate_from(number);
+        self.blocks.push_back((number, hash));
+        while self.blocks.len() > self.capacity {
+            self.blocks.pop_front();
+        }
+    }
+
+    /// Hash recorded for block `number`, if it is in the window
+    pub fn get(&self, number: u64) -> Option<H256> {
+        self.blocks
+            .iter()
+            .find(|(n, _)| *n == number)
+            .map(|(_, hash)| *hash)
+    }
+
+    /// Entries from newest to oldest
+    pub fn iter_rev(&self) -> impl Iterator<Item = (u64, H256)> + '_ {
+        self.blocks.iter().rev().copied()
+    }
+
+    pub fn oldest(&self) -> Option<u64> {
+        self.blocks.front().map(|(n, _)| *n)
+    }
+
+    /// Forget every block at or above `number`
+    pub fn truncate_from(&mut self, number: u64) {
+        while self.blocks.back().is_some_and(|(n, _)| *n >= number) {
+            self.blocks.pop_back();
+        }
+    }
+}
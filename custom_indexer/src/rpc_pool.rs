@@ -0,0 +1,500 @@
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use ethers::providers::{Http, HttpClientError, JsonRpcClient, JsonRpcError, Provider};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::batch::is_range_too_large;
+
+/// Per-request timeout, after which the next endpoint is tried
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Weight of the newest sample in the latency and error moving averages
+const EWMA_ALPHA: f64 = 0.2;
+/// Consecutive failures before an endpoint is benched
+const BENCH_AFTER_FAILURES: u32 = 3;
+const BASE_BENCH: Duration = Duration::from_secs(30);
+const MAX_BENCH: Duration = Duration::from_secs(5 * 60);
+
+pub type PoolProvider = Provider<RpcPool>;
+
+/// JSON-RPC transport over several HTTP endpoints. Requests go to the
+/// healthiest endpoint and fail over to the next one; `eth_getLogs` is
+/// cross-checked between two endpoints when there are several. Cheap to
+/// clone, clones share health state.
+#[derive(Debug, Clone)]
+pub struct RpcPool {
+    endpoints: Arc<Vec<Endpoint>>,
+    client: reqwest::Client,
+}
+
+#[derive(Debug)]
+struct Endpoint {
+    url: String,
+    http: Http,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug)]
+struct Health {
+    /// Moving average over successful requests
+    latency_ms: f64,
+    /// Moving average of failures, from 0 (none) to 1 (all)
+    error_rate: f64,
+    consecutive_failures: u32,
+    /// Only used when no other endpoint is available until then
+    benched_until: Option<Instant>,
+    requests: u64,
+    failures: u64,
+}
+
+/// Snapshot of one endpoint's health, for status output
+#[derive(Debug, Clone)]
+pub struct EndpointHealth {
+    pub url: String,
+    pub latency_ms: f64,
+    pub error_rate: f64,
+    pub requests: u64,
+    pub failures: u64,
+    pub benched: bool,
+}
+
+#[derive(Deserialize)]
+struct BatchResponse<T> {
+    id: usize,
+    result: Option<T>,
+    error: Option<Value>,
+}
+
+impl Health {
+    fn new() -> Self {
+        Self {
+            // Optimistic start so every endpoint gets tried
+            latency_ms: 100.0,
+            error_rate: 0.0,
+            consecutive_failures: 0,
+            benched_until: None,
+            requests: 0,
+            failures: 0,
+        }
+    }
+
+    fn is_benched(&self, now: Instant) -> bool {
+        self.benched_until.is_some_and(|until| until > now)
+    }
+
+    /// Lower is better
+    fn score(&self) -> f64 {
+        self.latency_ms * (1.0 + 10.0 * self.error_rate)
+    }
+
+    fn record_success(&mut self, latency: Duration) {
+        let ms = latency.as_secs_f64() * 1000.0;
+        self.latency_ms += EWMA_ALPHA * (ms - self.latency_ms);
+        self.error_rate -= EWMA_ALPHA * self.error_rate;
+        self.consecutive_failures = 0;
+        self.benched_until = None;
+        self.requests += 1;
+    }
+
+    fn record_failure(&mut self) {
+        self.error_rate += EWMA_ALPHA * (1.0 - self.error_rate);
+        self.consecutive_failures += 1;
+        self.requests += 1;
+        self.failures += 1;
+        if self.consecutive_failures >= BENCH_AFTER_FAILURES {
+            let doublings = (self.consecutive_failures - BENCH_AFTER_FAILURES).min(16);
+            let bench = (BASE_BENCH * 2u32.pow(doublings)).min(MAX_BENCH);
+            self.benched_until = Some(Instant::now() + bench);
+        }
+    }
+}
+
+impl RpcPool {
+    pub fn new(urls: &[String]) -> Result<Self> {
+        if urls.is_empty() {
+            bail!("at least one RPC URL is needed");
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .context("building HTTP client")?;
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                let parsed: reqwest::Url = url
+                    .parse()
+                    .with_context(|| format!("invalid RPC URL {}", url))?;
+                Ok(Endpoint {
+                    url: url.clone(),
+                    http: Http::new_with_client(parsed, client.clone()),
+                    health: Mutex::new(Health::new()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            endpoints: Arc::new(endpoints),
+            client,
+        })
+    }
+
+    pub fn provider(&self) -> PoolProvider {
+        Provider::new(self.clone())
+    }
+
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        let now = Instant::now();
+        self.endpoints
+            .iter()
+            .map(|endpoint| {
+                let health = endpoint.health.lock().unwrap();
+                EndpointHealth {
+                    url: endpoint.url.clone(),
+                    latency_ms: health.latency_ms,
+                    error_rate: health.error_rate,
+                    requests: health.requests,
+                    failures: health.failures,
+                    benched: health.is_benched(now),
+                }
+            })
+            .collect()
+    }
+
+    /// Send a batch of JSON-RPC requests as one HTTP request, failing over
+    /// like single requests do. Returns the decoded response array.
+    pub async fn batch<R: DeserializeOwned>(&self, requests: &[Value]) -> Result<R> {
+        let mut last_error = None;
+        for index in self.ranked() {
+            let endpoint = &self.endpoints[index];
+            let started = Instant::now();
+            let response = async {
+                self.client
+                    .post(&endpoint.url)
+                    .json(requests)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<R>()
+                    .await
+            }
+            .await;
+
+            match response {
+                Ok(response) => {
+                    endpoint
+                        .health
+                        .lock()
+                        .unwrap()
+                        .record_success(started.elapsed());
+                    return Ok(response);
+                }
+                Err(e) => {
+                    endpoint.health.lock().unwrap().record_failure();
+                    warn!("Batch request to {} failed: {}", endpoint.url, e);
+                    last_error =
+                        Some(anyhow!(e).context(format!("batch request to {}", endpoint.url)));
+                }
+            }
+        }
+        Err(last_error.expect("pool has at least one endpoint"))
+    }
+
+    /// Call `method` once per entry of `params` in a single batch. Results
+    /// come back in the order of `params`; `None` where the node returned
+    /// null (e.g. a block it does not have yet).
+    pub async fn batch_call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[Value],
+    ) -> Result<Vec<Option<T>>> {
+        self.batch_try_call(method, params)
+            .await?
+            .into_iter()
+            .zip(params)
+            .map(|(result, params)| {
+                result.map_err(|error| anyhow!("{}({}) failed: {}", method, params, error))
+            })
+            .collect()
+    }
+
+    /// `batch_call` that keeps going when single calls fail, e.g. an
+    /// `eth_call` that reverts. Each entry is the call's result or the error
+    /// the node returned for it.
+    pub async fn batch_try_call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &[Value],
+    ) -> Result<Vec<std::result::Result<Option<T>, Value>>> {
+        let requests: Vec<Value> = params
+            .iter()
+            .enumerate()
+            .map(|(id, params)| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "method": method,
+                    "params": params,
+                })
+            })
+            .collect();
+
+        let responses: Vec<BatchResponse<T>> = self.batch(&requests).await?;
+        let mut results: Vec<std::result::Result<Option<T>, Value>> =
+            params.iter().map(|_| Ok(None)).collect();
+        for response in responses {
+            if let Some(slot) = results.get_mut(response.id) {
+                *slot = match response.error {
+                    Some(error) => Err(error),
+                    None => Ok(response.result),
+                };
+            }
+        }
+        Ok(results)
+    }
+
+    /// Endpoint indexes, best first. Benched endpoints go last, so they are
+    /// still used when nothing else works.
+    fn ranked(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let mut ranked: Vec<(bool, f64, usize)> = self
+            .endpoints
+            .iter()
+            .enumerate()
+            .map(|(index, endpoint)| {
+                let health = endpoint.health.lock().unwrap();
+                (health.is_benched(now), health.score(), index)
+            })
+            .collect();
+        ranked.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)).then(a.2.cmp(&b.2)));
+        ranked.into_iter().map(|(_, _, index)| index).collect()
+    }
+
+    fn available(&self) -> usize {
+        let now = Instant::now();
+        self.endpoints
+            .iter()
+            .filter(|e| !e.health.lock().unwrap().is_benched(now))
+            .count()
+    }
+
+    /// One request to one endpoint, recording the outcome
+    async fn send<T>(
+        &self,
+        index: usize,
+        method: &str,
+        params: &T,
+    ) -> Result<Value, HttpClientError>
+    where
+        T: Debug + Serialize + Send + Sync,
+    {
+        let endpoint = &self.endpoints[index];
+        let started = Instant::now();
+        let result = endpoint.http.request::<_, Value>(method, params).await;
+
+        let mut health = endpoint.health.lock().unwrap();
+        match &result {
+            // The node answered; the request itself was at fault
+            Err(e) if is_request_error(e) => health.record_success(started.elapsed()),
+            Ok(_) => health.record_success(started.elapsed()),
+            Err(e) => {
+                health.record_failure();
+                warn!("{} to {} failed: {}", method, endpoint.url, e);
+            }
+        }
+        result
+    }
+
+    /// Try endpoints best first until one answers
+    async fn send_with_failover<T>(
+        &self,
+        ranked: &[usize],
+        method: &str,
+        params: &T,
+    ) -> Result<Value, HttpClientError>
+    where
+        T: Debug + Serialize + Send + Sync,
+    {
+        let mut last_error = None;
+        for &index in ranked {
+            match self.send(index, method, params).await {
+                Ok(value) => return Ok(value),
+                Err(e) if is_request_error(&e) => return Err(e),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.expect("pool has at least one endpoint"))
+    }
+
+    /// `eth_getLogs` from the two best endpoints. If they disagree a third
+    /// one decides, and the odd one out is penalised; without a third the
+    /// request fails so the range is retried. An endpoint that is behind
+    /// only returns the logs up to its head, which is not a disagreement.
+    async fn cross_checked_logs<T>(&self, params: &T) -> Result<Value, HttpClientError>
+    where
+        T: Debug + Serialize + Send + Sync,
+    {
+        const METHOD: &str = "eth_getLogs";
+        let ranked = self.ranked();
+        let (first, second) = futures::join!(
+            self.send(ranked[0], METHOD, params),
+            self.send(ranked[1], METHOD, params)
+        );
+
+        let (first, second) = match (first, second) {
+            (Ok(first), Ok(second)) => (first, second),
+            // Nothing to compare against; use whichever answered
+            (Ok(value), Err(_)) | (Err(_), Ok(value)) => return Ok(value),
+            (Err(e), Err(_)) => {
+                if is_request_error(&e) || ranked.len() <= 2 {
+                    return Err(e);
+                }
+                return self.send_with_failover(&ranked[2..], METHOD, params).await;
+            }
+        };
+        if log_keys(&first) == log_keys(&second) || self.is_behind(ranked[1], &second, &first).await
+        {
+            return Ok(first);
+        }
+        if self.is_behind(ranked[0], &first, &second).await {
+            return Ok(second);
+        }
+
+        let Some(&third) = ranked.get(2) else {
+            return Err(disagreement(
+                &self.endpoints[ranked[0]].url,
+                &self.endpoints[ranked[1]].url,
+            ));
+        };
+        warn!(
+            "{} and {} returned different logs, asking {}",
+            self.endpoints[ranked[0]].url, self.endpoints[ranked[1]].url, self.endpoints[third].url
+        );
+        let tiebreak = log_keys(&self.send(third, METHOD, params).await?);
+        let (winner, loser) = if tiebreak == log_keys(&first) {
+            (first, ranked[1])
+        } else if tiebreak == log_keys(&second) {
+            (second, ranked[0])
+        } else {
+            return Err(disagreement(
+                &self.endpoints[ranked[0]].url,
+                &self.endpoints[ranked[1]].url,
+            ));
+        };
+        warn!(
+            "{} returned logs the other endpoints disagree with",
+            self.endpoints[loser].url
+        );
+        self.endpoints[loser]
+            .health
+            .lock()
+            .unwrap()
+            .record_failure();
+        Ok(winner)
+    }
+
+    /// Whether endpoint `index` returned `shorter` only because it is behind
+    /// the one that returned `longer`: the same logs, missing just those
+    /// above its head
+    async fn is_behind(&self, index: usize, shorter: &Value, longer: &Value) -> bool {
+        let (shorter_keys, longer_keys) = (log_keys(shorter), log_keys(longer));
+        if !longer_keys.starts_with(&shorter_keys) {
+            return false;
+        }
+        let head = match self.send(index, "eth_blockNumber", &()).await {
+            Ok(head) => head.as_str().and_then(parse_quantity),
+            Err(_) => None,
+        };
+        let Some(head) = head else {
+            return false;
+        };
+        longer
+            .as_array()
+            .into_iter()
+            .flatten()
+            .skip(shorter_keys.len())
+            .all(|log| {
+                log.get("blockNumber")
+                    .and_then(Value::as_str)
+                    .and_then(parse_quantity)
+                    .is_some_and(|block| block > head)
+            })
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for RpcPool {
+    type Error = HttpClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let value = if method == "eth_getLogs" && self.available() >= 2 {
+            self.cross_checked_logs(&params).await?
+        } else {
+            self.send_with_failover(&self.ranked(), method, &params)
+                .await?
+        };
+
+        serde_json::from_value(value.clone()).map_err(|err| HttpClientError::SerdeJson {
+            err,
+            text: value.to_string(),
+        })
+    }
+}
+
+/// Errors every endpoint would return for the same request, which say
+/// nothing about the endpoint's health
+fn is_request_error(error: &HttpClientError) -> bool {
+    match error {
+        HttpClientError::JsonRpcError(e) => {
+            is_range_too_large(&e.message) || e.message.contains("execution reverted")
+        }
+        _ => false,
+    }
+}
+
+/// What identifies a log, for comparing endpoints. Other fields differ
+/// harmlessly between node implementations.
+fn log_keys(logs: &Value) -> Vec<String> {
+    let field = |log: &Value, name: &str| {
+        log.get(name)
+            .map(|v| v.to_string().to_lowercase())
+            .unwrap_or_default()
+    };
+    logs.as_array()
+        .map(|logs| {
+            logs.iter()
+                .map(|log| {
+                    [
+                        field(log, "blockHash"),
+                        field(log, "logIndex"),
+                        field(log, "transactionHash"),
+                        field(log, "topics"),
+                        field(log, "data"),
+                    ]
+                    .join("|")
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_quantity(hex: &str) -> Option<u64> {
+    u64::from_str_radix(hex.trim_start_matches("0x"), 16).ok()
+}
+
+fn disagreement(a: &str, b: &str) -> HttpClientError {
+    HttpClientError::JsonRpcError(JsonRpcError {
+        code: -32000,
+        message: format!("{} and {} returned different logs", a, b),
+        data: None,
+    })
+}
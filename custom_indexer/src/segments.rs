@@ -0,0 +1,614 @@
+use anyhow::{bail, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::calls::IndexedCall;
+use crate::checkpoint::write_json_atomic;
+use crate::sink::IndexedEvent;
+use crate::snapshots::Snapshot;
+use crate::transfers::IndexedTransfer;
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const SEGMENT_DIR: &str = "segments";
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// zstd level used when sealing; fixed so the same events always compress
+/// to the same bytes
+const ZSTD_LEVEL: i32 = 3;
+
+/// How sealed segments are stored. The segment at the head is always plain
+/// NDJSON so it can be appended to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Zstd,
+}
+
+impl std::str::FromStr for Compression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            other => bail!("unknown compression {:?}, expected none or zstd", other),
+        }
+    }
+}
+
+/// One NDJSON file holding the records of an aligned block range
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Segment {
+    /// Path relative to the output directory
+    pub file: String,
+    pub from_block: u64,
+    pub to_block: u64,
+    /// Number of records: events, calls, transfers or snapshots depending
+    /// on the store
+    pub events: u64,
+    /// Length of the file as of the last commit; anything past it on disk
+    /// was never committed and is discarded on restart
+    pub bytes: u64,
+    /// Sealed segments are complete, sorted by position in the chain and
+    /// deduplicated
+    pub sealed: bool,
+    pub compression: Compression,
+}
+
+/// Index of the segment files, rewritten atomically on every commit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub version: u32,
+    pub segment_blocks: u64,
+    /// Ordered by `from_block`
+    pub segments: Vec<Segment>,
+}
+
+impl Manifest {
+    pub fn path(output_dir: &Path) -> PathBuf {
+        output_dir.join(MANIFEST_FILE)
+    }
+
+    pub fn load(output_dir: &Path) -> Result<Option<Self>> {
+        let path = Self::path(output_dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        let manifest: Manifest = serde_json::from_str(&contents)
+            .with_context(|| format!("parsing {}", path.display()))?;
+        if manifest.version != MANIFEST_VERSION {
+            bail!(
+                "{} has version {}, expected {}",
+                path.display(),
+                manifest.version,
+                MANIFEST_VERSION
+            );
+        }
+        Ok(Some(manifest))
+    }
+
+    pub fn save(&self, output_dir: &Path) -> Result<()> {
+        write_json_atomic(&Self::path(output_dir), self)
+    }
+
+    pub fn total_events(&self) -> u64 {
+        self.segments.iter().map(|s| s.events).sum()
+    }
+}
+
+/// Something stored in segments, one JSON object per line
+pub trait Record: Serialize {
+    fn block_number(&self) -> u64;
+}
+
+impl Record for IndexedEvent {
+    fn block_number(&self) -> u64 {
+        self.block_number
+    }
+}
+
+impl Record for IndexedCall {
+    fn block_number(&self) -> u64 {
+        self.block_number
+    }
+}
+
+impl Record for IndexedTransfer {
+    fn block_number(&self) -> u64 {
+        self.block_number
+    }
+}
+
+impl Record for Snapshot {
+    fn block_number(&self) -> u64 {
+        self.block_number
+    }
+}
+
+/// The part of a line needed to order and deduplicate it. Events and
+/// transfers have no transaction index and calls no log index; either sorts
+/// as zero.
+#[derive(Deserialize)]
+struct RecordKey {
+    block_number: u64,
+    #[serde(default)]
+    transaction_index: u64,
+    #[serde(default)]
+    log_index: u64,
+}
+
+impl RecordKey {
+    fn ordering(&self) -> (u64, u64, u64) {
+        (self.block_number, self.transaction_index, self.log_index)
+    }
+}
+
+/// Append-only record storage split into segments of `segment_blocks`
+/// blocks, aligned to multiples of it so file names only depend on which
+/// blocks they hold.
+pub struct SegmentStore {
+    output_dir: PathBuf,
+    /// File name prefix: `events`, `calls`, `transfers` or `snapshots`
+    name: &'static str,
+    compression: Compression,
+    manifest: Manifest,
+    /// Files written since the last commit, to be synced by it
+    dirty: Vec<PathBuf>,
+    /// Files replaced since the last commit, deleted once the manifest no
+    /// longer points at them
+    obsolete: Vec<PathBuf>,
+}
+
+impl SegmentStore {
+    pub fn open(
+        output_dir: &Path,
+        name: &'static str,
+        segment_blocks: u64,
+        compression: Compression,
+    ) -> Result<Self> {
+        let manifest = match Manifest::load(output_dir)? {
+            Some(manifest) => {
+                if manifest.segment_blocks != segment_blocks {
+                    warn!(
+                        "Output directory uses {}-block segments, ignoring the configured {}",
+                        manifest.segment_blocks, segment_blocks
+                    );
+                }
+                manifest
+            }
+            None => Manifest {
+                version: MANIFEST_VERSION,
+                segment_blocks,
+                segments: Vec::new(),
+            },
+        };
+
+        Ok(Self {
+            output_dir: output_dir.to_path_buf(),
+            name,
+            compression,
+            manifest,
+            dirty: Vec::new(),
+            obsolete: Vec::new(),
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Drop whatever a previous run wrote after its last commit: bytes past
+    /// the committed length of open segments, and records at or above
+    /// `next_block`, which will be indexed again.
+    pub fn recover(&mut self, next_block: Option<u64>) -> Result<()> {
+        for segment in self.manifest.segments.iter().filter(|s| !s.sealed) {
+            let path = self.output_dir.join(&segment.file);
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            if metadata.len() > segment.bytes {
+                warn!(
+                    "Discarding {} uncommitted bytes from {}",
+                    metadata.len() - segment.bytes,
+                    segment.file
+                );
+                fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)?
+                    .set_len(segment.bytes)?;
+            }
+        }
+
+        if let Some(next_block) = next_block.filter(|&n| n > 0) {
+            let removed = self.truncate_after(next_block - 1)?;
+            if removed > 0 {
+                warn!(
+                    "Discarded {} {} past the checkpoint at block {}",
+                    removed, self.name, next_block
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget everything since the last commit and go back to the manifest
+    /// on disk, e.g. after a failed write. `recover` then cleans up the
+    /// files.
+    pub fn discard_uncommitted(&mut self) -> Result<()> {
+        match Manifest::load(&self.output_dir)? {
+            Some(manifest) => self.manifest = manifest,
+            None => self.manifest.segments.clear(),
+        }
+        self.dirty.clear();
+        self.obsolete.clear();
+        Ok(())
+    }
+
+    /// Append records to the segments covering their blocks. Nothing is
+    /// durable until `commit`.
+    pub fn append<R: Record>(&mut self, records: &[R]) -> Result<()> {
+        let mut by_segment: BTreeMap<u64, Vec<&R>> = BTreeMap::new();
+        for record in records {
+            by_segment
+                .entry(self.segment_start(record.block_number()))
+                .or_default()
+                .push(record);
+        }
+
+        for (from_block, records) in by_segment {
+            let index = self.open_segment(from_block)?;
+            let segment = &mut self.manifest.segments[index];
+            let path = self.output_dir.join(&segment.file);
+
+            let mut buf = Vec::new();
+            for record in &records {
+                serde_json::to_writer(&mut buf, record)?;
+                buf.push(b'\n');
+            }
+            fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .with_context(|| format!("opening {}", path.display()))?
+                .write_all(&buf)?;
+
+            segment.events += records.len() as u64;
+            segment.bytes += buf.len() as u64;
+            if !self.dirty.contains(&path) {
+                self.dirty.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Seal every open segment below `next_block`, sync the files written
+    /// since the last commit and save the manifest
+    pub fn commit(&mut self, next_block: u64) -> Result<()> {
+        for index in 0..self.manifest.segments.len() {
+            let segment = &self.manifest.segments[index];
+            if !segment.sealed && segment.to_block < next_block {
+                self.seal(index)?;
+            }
+        }
+
+        for path in std::mem::take(&mut self.dirty) {
+            fs::File::open(&path)?.sync_all()?;
+        }
+        sync_dir(&self.output_dir.join(SEGMENT_DIR))?;
+        self.manifest.save(&self.output_dir)?;
+
+        for path in std::mem::take(&mut self.obsolete) {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove every record above `block`. Returns how many were removed.
+    pub fn truncate_after(&mut self, block: u64) -> Result<u64> {
+        let mut removed = 0;
+        let mut index = 0;
+        while index < self.manifest.segments.len() {
+            let segment = &self.manifest.segments[index];
+            if segment.to_block <= block {
+                index += 1;
+                continue;
+            }
+
+            if segment.from_block > block {
+                removed += segment.events;
+                let segment = self.manifest.segments.remove(index);
+                self.obsolete.push(self.output_dir.join(&segment.file));
+                continue;
+            }
+
+            let lines = self.read_lines(segment)?;
+            let before = lines.len() as u64;
+            let kept: Vec<String> = lines
+                .into_iter()
+                .filter(|line| parse_key(line).is_ok_and(|key| key.block_number <= block))
+                .collect();
+            removed += before - kept.len() as u64;
+            self.rewrite_plain(index, &kept)?;
+            index += 1;
+        }
+        Ok(removed)
+    }
+
+    /// Every committed record, in segment order
+    pub fn records<T: DeserializeOwned>(&self) -> Result<Vec<T>> {
+        let mut records = Vec::with_capacity(self.manifest.total_events() as usize);
+        for segment in &self.manifest.segments {
+            for line in self.read_lines(segment)? {
+                records.push(
+                    serde_json::from_str(&line)
+                        .with_context(|| format!("reading {}", segment.file))?,
+                );
+            }
+        }
+        Ok(records)
+    }
+
+    /// Records in `blocks`, including ones not committed yet, sorted and
+    /// deduplicated as sealing would
+    pub fn records_in<T: DeserializeOwned>(&self, blocks: RangeInclusive<u64>) -> Result<Vec<T>> {
+        let mut lines = Vec::new();
+        for segment in &self.manifest.segments {
+            if segment.to_block < *blocks.start() || segment.from_block > *blocks.end() {
+                continue;
+            }
+            for line in self.read_lines(segment)? {
+                let key = parse_key(&line).with_context(|| format!("reading {}", segment.file))?;
+                if blocks.contains(&key.block_number) {
+                    lines.push((key.ordering(), line));
+                }
+            }
+        }
+        lines.sort_by_key(|(key, _)| *key);
+        lines.dedup_by_key(|(key, _)| *key);
+
+        lines
+            .iter()
+            .map(|(_, line)| serde_json::from_str(line).context("reading a segment record"))
+            .collect()
+    }
+
+    /// Remove the records in `blocks` that `matches` selects. Returns how
+    /// many were removed.
+    pub fn remove_where(
+        &mut self,
+        blocks: RangeInclusive<u64>,
+        matches: impl Fn(&Value) -> bool,
+    ) -> Result<u64> {
+        let mut removed = 0;
+        for index in 0..self.manifest.segments.len() {
+            let segment = &self.manifest.segments[index];
+            if segment.to_block < *blocks.start() || segment.from_block > *blocks.end() {
+                continue;
+            }
+
+            let file = segment.file.clone();
+            let lines = self.read_lines(segment)?;
+            let before = lines.len() as u64;
+            let mut kept = Vec::with_capacity(lines.len());
+            for line in lines {
+                let record: Value =
+                    serde_json::from_str(&line).with_context(|| format!("reading {}", file))?;
+                let in_range = record
+                    .get("block_number")
+                    .and_then(Value::as_u64)
+                    .is_some_and(|b| blocks.contains(&b));
+                if !(in_range && matches(&record)) {
+                    kept.push(line);
+                }
+            }
+
+            if (kept.len() as u64) < before {
+                removed += before - kept.len() as u64;
+                self.rewrite_plain(index, &kept)?;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn segment_start(&self, block: u64) -> u64 {
+        block - block % self.manifest.segment_blocks
+    }
+
+    /// Index of the open segment starting at `from_block`, creating it or
+    /// reopening a sealed one as needed
+    fn open_segment(&mut self, from_block: u64) -> Result<usize> {
+        let position = self
+            .manifest
+            .segments
+            .binary_search_by_key(&from_block, |s| s.from_block);
+
+        match position {
+            Ok(index) if !self.manifest.segments[index].sealed => Ok(index),
+            Ok(index) => {
+                // Late records for a sealed range (a retried gap): go back to
+                // plain NDJSON, it is sorted again when resealed
+                let lines = self.read_lines(&self.manifest.segments[index])?;
+                self.rewrite_plain(index, &lines)?;
+                Ok(index)
+            }
+            Err(index) => {
+                let to_block = from_block + self.manifest.segment_blocks - 1;
+                let segment = Segment {
+                    file: segment_file(self.name, from_block, to_block, Compression::None),
+                    from_block,
+                    to_block,
+                    events: 0,
+                    bytes: 0,
+                    sealed: false,
+                    compression: Compression::None,
+                };
+                fs::create_dir_all(self.output_dir.join(SEGMENT_DIR))?;
+                // Anything already there was never committed
+                fs::File::create(self.output_dir.join(&segment.file))?;
+                self.manifest.segments.insert(index, segment);
+                Ok(index)
+            }
+        }
+    }
+
+    /// Sort and deduplicate a complete segment and store it compressed if
+    /// configured
+    fn seal(&mut self, index: usize) -> Result<()> {
+        let segment = &self.manifest.segments[index];
+        let mut lines = Vec::new();
+        for line in self.read_lines(segment)? {
+            let key = parse_key(&line).with_context(|| format!("reading {}", segment.file))?;
+            lines.push((key.ordering(), line));
+        }
+        lines.sort_by_key(|(key, _)| *key);
+        lines.dedup_by_key(|(key, _)| *key);
+
+        let mut content = Vec::new();
+        for (_, line) in &lines {
+            content.extend_from_slice(line.as_bytes());
+            content.push(b'\n');
+        }
+        if self.compression == Compression::Zstd {
+            content = zstd::stream::encode_all(content.as_slice(), ZSTD_LEVEL)?;
+        }
+
+        let file = segment_file(
+            self.name,
+            segment.from_block,
+            segment.to_block,
+            self.compression,
+        );
+        let old_path = self.output_dir.join(&segment.file);
+        let new_path = self.output_dir.join(&file);
+        write_file_atomic(&new_path, &content)?;
+        if new_path != old_path {
+            self.obsolete.push(old_path);
+        }
+        self.dirty.retain(|p| *p != new_path);
+
+        info!(
+            "Sealed segment {} ({} {}, {} bytes)",
+            file,
+            lines.len(),
+            self.name,
+            content.len()
+        );
+        let segment = &mut self.manifest.segments[index];
+        segment.file = file;
+        segment.events = lines.len() as u64;
+        segment.bytes = content.len() as u64;
+        segment.sealed = true;
+        segment.compression = self.compression;
+        Ok(())
+    }
+
+    /// Replace a segment's content with `lines` as an open, plain segment
+    fn rewrite_plain(&mut self, index: usize, lines: &[String]) -> Result<()> {
+        let segment = &self.manifest.segments[index];
+        let file = segment_file(
+            self.name,
+            segment.from_block,
+            segment.to_block,
+            Compression::None,
+        );
+        let old_path = self.output_dir.join(&segment.file);
+        let new_path = self.output_dir.join(&file);
+
+        let mut content = Vec::new();
+        for line in lines {
+            content.extend_from_slice(line.as_bytes());
+            content.push(b'\n');
+        }
+        write_file_atomic(&new_path, &content)?;
+        if new_path != old_path {
+            self.obsolete.push(old_path);
+        }
+
+        let segment = &mut self.manifest.segments[index];
+        segment.file = file;
+        segment.events = lines.len() as u64;
+        segment.bytes = content.len() as u64;
+        segment.sealed = false;
+        segment.compression = Compression::None;
+        Ok(())
+    }
+
+    /// Committed lines of a segment, decompressed
+    fn read_lines(&self, segment: &Segment) -> Result<Vec<String>> {
+        let path = self.output_dir.join(&segment.file);
+        let mut content = match fs::read(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                warn!("Segment {} is missing, treating it as empty", segment.file);
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+        };
+        if segment.compression == Compression::Zstd {
+            content = zstd::stream::decode_all(content.as_slice())
+                .with_context(|| format!("decompressing {}", path.display()))?;
+        } else if !segment.sealed {
+            // Recovery may not have run yet; ignore the uncommitted tail
+            content.truncate(segment.bytes as usize);
+        }
+
+        let content =
+            String::from_utf8(content).with_context(|| format!("reading {}", path.display()))?;
+        Ok(content
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+/// `segments/events_0006900000_0006999999.ndjson[.zst]`
+fn segment_file(name: &str, from_block: u64, to_block: u64, compression: Compression) -> String {
+    let extension = match compression {
+        Compression::None => "ndjson",
+        Compression::Zstd => "ndjson.zst",
+    };
+    format!(
+        "{}/{}_{:010}_{:010}.{}",
+        SEGMENT_DIR, name, from_block, to_block, extension
+    )
+}
+
+fn parse_key(line: &str) -> Result<RecordKey> {
+    Ok(serde_json::from_str(line)?)
+}
+
+pub(crate) fn write_file_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file =
+        fs::File::create(&tmp_path).with_context(|| format!("creating {}", tmp_path.display()))?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path).with_context(|| format!("replacing {}", path.display()))?;
+    Ok(())
+}
+
+/// Make renames and new files in `dir` durable
+fn sync_dir(dir: &Path) -> Result<()> {
+    if dir.exists() {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
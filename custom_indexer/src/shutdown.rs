@@ -0,0 +1,80 @@
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Exit code after a second signal cut a batch short
+const FORCED_EXIT_CODE: i32 = 130;
+
+/// Set once SIGINT or SIGTERM arrives. The indexer checks it between
+/// batches, so the batch being written is finished and checkpointed before
+/// it exits. A second signal exits straight away; the sink discards
+/// anything uncommitted on the next start.
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Start listening for the signals
+    pub fn on_signals() -> Self {
+        let (sender, requested) = watch::channel(false);
+        tokio::spawn(async move {
+            signal().await;
+            info!("Shutting down after the current batch, signal again to exit now");
+            let _ = sender.send(true);
+
+            signal().await;
+            warn!("Exiting without finishing the current batch");
+            std::process::exit(FORCED_EXIT_CODE);
+        });
+        Self { requested }
+    }
+
+    pub fn requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Run `future` to completion, or drop it and return `None` once a
+    /// shutdown is requested. Only for futures that write nothing.
+    pub async fn interruptible<F: Future>(&self, future: F) -> Option<F::Output> {
+        let mut requested = self.requested.clone();
+        tokio::select! {
+            biased;
+            _ = requested.wait_for(|requested| *requested) => None,
+            output = future => Some(output),
+        }
+    }
+
+    /// Sleep for `duration`. Returns false if a shutdown cut it short.
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        self.interruptible(tokio::time::sleep(duration))
+            .await
+            .is_some()
+    }
+}
+
+/// Wait for SIGINT or SIGTERM. Never returns if neither can be listened
+/// for, rather than shutting down at once.
+async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    Ok(()) = tokio::signal::ctrl_c() => {}
+                    Some(()) = terminate.recv() => {}
+                    else => std::future::pending::<()>().await,
+                }
+                return;
+            }
+            Err(e) => warn!("Cannot listen for SIGTERM: {}", e),
+        }
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Cannot listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,442 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::calls::IndexedCall;
+use crate::checkpoint::Checkpoint;
+use crate::event_signatures::id_keyed_events;
+use crate::headers::BlockHeader;
+use crate::postgres::PostgresSink;
+use crate::protobuf::{ProtobufOutput, Stores};
+use crate::segments::{Compression, Manifest, SegmentStore};
+use crate::snapshots::Snapshot;
+use crate::transfers::IndexedTransfer;
+
+/// Subdirectory of the output dir holding call segments
+pub const CALLS_DIR: &str = "calls";
+/// Subdirectory of the output dir holding token transfer segments
+pub const TRANSFERS_DIR: &str = "transfers";
+/// Subdirectory of the output dir holding loan snapshot segments
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+/// Subdirectory of the output dir holding `contract.v1` protobuf files
+pub const PROTOBUF_DIR: &str = "protobuf";
+/// Held by whichever process is writing to an output dir
+const LOCK_FILE: &str = "indexer.lock";
+
+/// One decoded contract event, in the shape the API's `DataStore` loads
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedEvent {
+    pub event_name: String,
+    pub transaction_hash: String,
+    pub block_number: u64,
+    pub block_timestamp: u64,
+    pub log_index: u64,
+    pub contract_address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub decoded_data: Option<Map<String, Value>>,
+}
+
+/// Stored records a backfill or reindex replaces
+#[derive(Debug, Clone)]
+pub enum Replace {
+    /// Every event, and every call and transfer if those are indexed, in
+    /// `from..=to`
+    Blocks { from: u64, to: u64 },
+    /// Events filed under `loan_id` (see `id_keyed_events`), up to block
+    /// `to`: the loan's, and those of the loan request with the same id.
+    /// `topic` is the id as it appears in topic1, which matches events that
+    /// could not be decoded as well.
+    Loan {
+        loan_id: String,
+        topic: String,
+        to: u64,
+    },
+}
+
+impl Replace {
+    fn blocks(&self) -> RangeInclusive<u64> {
+        match self {
+            Replace::Blocks { from, to } => *from..=*to,
+            Replace::Loan { to, .. } => 0..=*to,
+        }
+    }
+
+    /// Whether a stored event, as JSON, falls under this replacement.
+    /// `keyed` is `id_keyed_events()`.
+    fn matches_event(&self, event: &Value, keyed: &HashMap<String, String>) -> bool {
+        match self {
+            Replace::Blocks { .. } => true,
+            Replace::Loan { topic, .. } => {
+                event
+                    .get("event_name")
+                    .and_then(Value::as_str)
+                    .is_some_and(|name| keyed.contains_key(name))
+                    && event
+                        .get("topics")
+                        .and_then(|topics| topics.get(1))
+                        .and_then(Value::as_str)
+                        == Some(topic.as_str())
+            }
+        }
+    }
+}
+
+/// Where indexed events, calls, transfers and the checkpoint are stored
+pub enum Sink {
+    Files(FileSink),
+    Postgres(PostgresSink),
+}
+
+impl Sink {
+    /// Store a processed range atomically: its events, calls and
+    /// transfers, the headers of the blocks they are in and, if given, the
+    /// checkpoint past it
+    pub async fn write_batch(
+        &mut self,
+        events: &[IndexedEvent],
+        calls: &[IndexedCall],
+        transfers: &[IndexedTransfer],
+        blocks: &[BlockHeader],
+        checkpoint: Option<&Checkpoint>,
+    ) -> Result<()> {
+        match self {
+            Sink::Files(sink) => {
+                sink.atomically(|sink| sink.write_batch(events, calls, transfers, checkpoint))
+            }
+            Sink::Postgres(sink) => {
+                sink.write_batch(events, calls, transfers, blocks, checkpoint)
+                    .await
+            }
+        }
+    }
+
+    pub async fn save_checkpoint(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        self.write_batch(&[], &[], &[], &[], Some(checkpoint)).await
+    }
+
+    pub async fn load_checkpoint(&self) -> Result<Option<Checkpoint>> {
+        match self {
+            Sink::Files(sink) => Checkpoint::load(&sink.output_dir),
+            Sink::Postgres(sink) => sink.load_checkpoint().await,
+        }
+    }
+
+    /// Every stored event
+    pub async fn load_events(&self) -> Result<Vec<IndexedEvent>> {
+        match self {
+            Sink::Files(sink) => sink.segments.records(),
+            Sink::Postgres(sink) => sink.load_events().await,
+        }
+    }
+
+    /// Store loan snapshots. They may be ahead of the checkpoint, in which
+    /// case they are dropped and taken again if the range is indexed again.
+    pub async fn write_snapshots(&mut self, snapshots: &[Snapshot]) -> Result<()> {
+        match self {
+            Sink::Files(sink) => sink.atomically(|sink| sink.write_snapshots(snapshots)),
+            Sink::Postgres(sink) => sink.write_snapshots(snapshots).await,
+        }
+    }
+
+    /// Swap the stored records `scope` covers for `events`, `calls` and
+    /// `transfers` without touching the checkpoint. Returns how many events
+    /// were removed.
+    pub async fn replace(
+        &mut self,
+        scope: &Replace,
+        events: &[IndexedEvent],
+        calls: &[IndexedCall],
+        transfers: &[IndexedTransfer],
+        blocks: &[BlockHeader],
+    ) -> Result<u64> {
+        match self {
+            Sink::Files(sink) => {
+                sink.atomically(|sink| sink.replace(scope, events, calls, transfers))
+            }
+            Sink::Postgres(sink) => sink.replace(scope, events, calls, transfers, blocks).await,
+        }
+    }
+
+    /// Delete everything above `ancestor` and move the checkpoint back.
+    /// Returns how many events were removed.
+    pub async fn rollback(&mut self, ancestor: u64, checkpoint: &Checkpoint) -> Result<u64> {
+        match self {
+            Sink::Files(sink) => sink.atomically(|sink| sink.rollback(ancestor, checkpoint)),
+            Sink::Postgres(sink) => sink.rollback(ancestor, checkpoint).await,
+        }
+    }
+
+    /// Release the output dir or database connections. Every write is
+    /// committed as it is made, so there is nothing left to flush.
+    pub async fn close(self) {
+        match self {
+            Sink::Files(sink) => drop(sink),
+            Sink::Postgres(sink) => sink.close().await,
+        }
+    }
+}
+
+/// Events in block-range NDJSON segments (see `segments`) plus
+/// `indexer_state.json` in the output dir. Calls, transfers and loan
+/// snapshots, when enabled, get their own segments and manifest under
+/// `calls/`, `transfers/` and `snapshots/`. With protobuf output on, the
+/// segments are mirrored as `contract.v1` messages under `protobuf/`.
+pub struct FileSink {
+    output_dir: PathBuf,
+    segments: SegmentStore,
+    calls: Option<Box<SegmentStore>>,
+    transfers: Option<Box<SegmentStore>>,
+    snapshots: Option<Box<SegmentStore>>,
+    protobuf: Option<ProtobufOutput>,
+    /// Exclusive lock on the output dir, taken before the first write and
+    /// held until exit
+    lock: Option<fs::File>,
+    /// Whether the tail left by an earlier run has been cleaned up
+    recovered: bool,
+}
+
+impl FileSink {
+    pub fn open(
+        output_dir: &Path,
+        segment_blocks: u64,
+        compression: Compression,
+        index_calls: bool,
+        index_transfers: bool,
+        snapshots: bool,
+        protobuf: bool,
+    ) -> Result<Self> {
+        fs::create_dir_all(output_dir)?;
+        let substore = |enabled: bool, name: &'static str| -> Result<_> {
+            if !enabled {
+                return Ok(None);
+            }
+            let dir = output_dir.join(name);
+            fs::create_dir_all(&dir)?;
+            let store = SegmentStore::open(&dir, name, segment_blocks, compression)?;
+            Ok(Some(Box::new(store)))
+        };
+
+        Ok(Self {
+            output_dir: output_dir.to_path_buf(),
+            segments: SegmentStore::open(output_dir, "events", segment_blocks, compression)?,
+            calls: substore(index_calls, CALLS_DIR)?,
+            transfers: substore(index_transfers, TRANSFERS_DIR)?,
+            snapshots: substore(snapshots, SNAPSHOTS_DIR)?,
+            protobuf: protobuf
+                .then(|| ProtobufOutput::open(&output_dir.join(PROTOBUF_DIR)))
+                .transpose()?,
+            lock: None,
+            recovered: false,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        self.segments.manifest()
+    }
+
+    /// Run a write. If it fails, whatever it left uncommitted is dropped
+    /// and cleaned up before the next write, as after a crash, so a retry
+    /// cannot duplicate or corrupt records.
+    fn atomically<T>(&mut self, write: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let result = write(self);
+        if result.is_err() {
+            let stores = [&mut self.calls, &mut self.transfers, &mut self.snapshots]
+                .into_iter()
+                .flatten()
+                .map(|store| &mut **store);
+            for store in std::iter::once(&mut self.segments).chain(stores) {
+                if let Err(e) = store.discard_uncommitted() {
+                    warn!("Could not reload a manifest after a failed write: {:#}", e);
+                }
+            }
+            self.recovered = false;
+        }
+        result
+    }
+
+    /// Recovery only runs once something is about to be written, so
+    /// read-only commands leave a running indexer's files alone
+    fn recover(&mut self) -> Result<()> {
+        if self.lock.is_none() {
+            let path = self.output_dir.join(LOCK_FILE);
+            let file =
+                fs::File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+            match file.try_lock() {
+                Ok(()) => self.lock = Some(file),
+                Err(fs::TryLockError::WouldBlock) => bail!(
+                    "another indexer is writing to {}, stop it first",
+                    self.output_dir.display()
+                ),
+                Err(fs::TryLockError::Error(e)) => {
+                    return Err(e).with_context(|| format!("locking {}", path.display()))
+                }
+            }
+        }
+        if !self.recovered {
+            let next_block = Checkpoint::load(&self.output_dir)?.map(|c| c.current_block);
+            self.segments.recover(next_block)?;
+            for store in [&mut self.calls, &mut self.transfers, &mut self.snapshots]
+                .into_iter()
+                .flatten()
+            {
+                store.recover(next_block)?;
+            }
+            if let Some(output) = &self.protobuf {
+                output.recover(&Stores {
+                    events: &self.segments,
+                    calls: self.calls.as_deref(),
+                    transfers: self.transfers.as_deref(),
+                })?;
+            }
+            self.recovered = true;
+        }
+        Ok(())
+    }
+
+    fn write_batch(
+        &mut self,
+        events: &[IndexedEvent],
+        calls: &[IndexedCall],
+        transfers: &[IndexedTransfer],
+        checkpoint: Option<&Checkpoint>,
+    ) -> Result<()> {
+        self.recover()?;
+        let blocks = events
+            .iter()
+            .map(|e| e.block_number)
+            .chain(calls.iter().map(|c| c.block_number))
+            .chain(transfers.iter().map(|t| t.block_number));
+        let blocks = blocks.clone().min().zip(blocks.max());
+        if blocks.is_some() {
+            self.begin_protobuf()?;
+        }
+        self.segments.append(events)?;
+        if !events.is_empty() {
+            info!("Appended {} events to segments", events.len());
+        }
+        if let Some(store) = &mut self.calls {
+            store.append(calls)?;
+            if !calls.is_empty() {
+                info!("Appended {} calls to segments", calls.len());
+            }
+        }
+        if let Some(store) = &mut self.transfers {
+            store.append(transfers)?;
+            if !transfers.is_empty() {
+                info!("Appended {} transfers to segments", transfers.len());
+            }
+        }
+
+        // Segments and manifest are synced before the checkpoint moves past
+        // them
+        let next_block = match checkpoint {
+            Some(checkpoint) => checkpoint.current_block,
+            None => Checkpoint::load(&self.output_dir)?.map_or(0, |c| c.current_block),
+        };
+        self.segments.commit(next_block)?;
+        for store in [&mut self.calls, &mut self.transfers].into_iter().flatten() {
+            store.commit(next_block)?;
+        }
+        if let Some((from, to)) = blocks {
+            self.rebuild_protobuf(from..=to)?;
+        }
+        if let Some(checkpoint) = checkpoint {
+            checkpoint.save(&self.output_dir)?;
+        }
+        Ok(())
+    }
+
+    fn write_snapshots(&mut self, snapshots: &[Snapshot]) -> Result<()> {
+        self.recover()?;
+        let Some(store) = &mut self.snapshots else {
+            return Ok(());
+        };
+        store.append(snapshots)?;
+        let next_block = Checkpoint::load(&self.output_dir)?.map_or(0, |c| c.current_block);
+        store.commit(next_block)
+    }
+
+    fn replace(
+        &mut self,
+        scope: &Replace,
+        events: &[IndexedEvent],
+        calls: &[IndexedCall],
+        transfers: &[IndexedTransfer],
+    ) -> Result<u64> {
+        self.recover()?;
+        self.begin_protobuf()?;
+        let keyed = id_keyed_events();
+        let removed = self
+            .segments
+            .remove_where(scope.blocks(), |e| scope.matches_event(e, &keyed))?;
+        self.segments.append(events)?;
+        if let (Replace::Blocks { .. }, Some(store)) = (scope, &mut self.calls) {
+            store.remove_where(scope.blocks(), |_| true)?;
+            store.append(calls)?;
+        }
+        if let (Replace::Blocks { .. }, Some(store)) = (scope, &mut self.transfers) {
+            store.remove_where(scope.blocks(), |_| true)?;
+            store.append(transfers)?;
+        }
+
+        // Segments behind the checkpoint are sealed again on commit
+        let next_block = Checkpoint::load(&self.output_dir)?.map_or(0, |c| c.current_block);
+        self.segments.commit(next_block)?;
+        for store in [&mut self.calls, &mut self.transfers].into_iter().flatten() {
+            store.commit(next_block)?;
+        }
+        self.rebuild_protobuf(scope.blocks())?;
+        Ok(removed)
+    }
+
+    fn rollback(&mut self, ancestor: u64, checkpoint: &Checkpoint) -> Result<u64> {
+        self.recover()?;
+        // The checkpoint moves back first: if the truncation below never
+        // makes it to disk, `recover` drops the orphaned blocks on restart
+        // and they are indexed again from the new fork
+        checkpoint.save(&self.output_dir)?;
+        self.begin_protobuf()?;
+        let removed = self.segments.truncate_after(ancestor)?;
+        self.segments.commit(checkpoint.current_block)?;
+        for store in [&mut self.calls, &mut self.transfers, &mut self.snapshots]
+            .into_iter()
+            .flatten()
+        {
+            store.truncate_after(ancestor)?;
+            store.commit(checkpoint.current_block)?;
+        }
+        self.rebuild_protobuf(ancestor..=u64::MAX)?;
+        Ok(removed)
+    }
+
+    /// Call before changing the segments: until `rebuild_protobuf` has
+    /// caught up, a restart rebuilds every protobuf file
+    fn begin_protobuf(&self) -> Result<()> {
+        match &self.protobuf {
+            Some(output) => output.begin(),
+            None => Ok(()),
+        }
+    }
+
+    /// Bring the protobuf files of the segments overlapping `blocks` in line
+    /// with the committed segments
+    fn rebuild_protobuf(&self, blocks: RangeInclusive<u64>) -> Result<()> {
+        let Some(output) = &self.protobuf else {
+            return Ok(());
+        };
+        output.rebuild(
+            &Stores {
+                events: &self.segments,
+                calls: self.calls.as_deref(),
+                transfers: self.transfers.as_deref(),
+            },
+            blocks,
+        )
+    }
+}
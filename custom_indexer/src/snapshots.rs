@@ -0,0 +1,174 @@
+use anyhow::{Context, Result};
+use ethers::abi::Token;
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tracing::warn;
+
+use crate::rpc_pool::RpcPool;
+use crate::views::{output_field_names, token_string, CallResult, ContractViews};
+
+/// Per-loan views called at every snapshot block
+const LOAN_VIEWS: [&str; 4] = [
+    "getLoan",
+    "getLoanHealthFactor",
+    "isLoanDefaulted",
+    "calculateCurrentInterest",
+];
+
+/// State of one active loan at a snapshot block. A field is `None` when its
+/// view reverted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoanSnapshot {
+    pub loan_id: String,
+    /// Collateral ratio in basis points, from `getLoanHealthFactor`
+    pub health_factor: Option<String>,
+    pub price_stale: Option<bool>,
+    pub defaulted: Option<bool>,
+    /// Interest accrued so far, from `calculateCurrentInterest`
+    pub current_interest: Option<String>,
+    pub token_address: Option<String>,
+    /// `getCurrentPrice` of the loan token
+    pub token_price: Option<String>,
+    pub collateral_address: Option<String>,
+    /// `getCurrentPrice` of the collateral token
+    pub collateral_price: Option<String>,
+}
+
+/// Every active loan at one block. Stored as one record so a block's
+/// snapshot is written, deduplicated and rolled back as a whole.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub block_number: u64,
+    pub block_timestamp: u64,
+    pub loans: Vec<LoanSnapshot>,
+}
+
+/// Takes a snapshot of the active loans every `interval` blocks
+pub struct Snapshotter {
+    views: ContractViews,
+    interval: u64,
+    /// Positions of the fields of `getLoan`'s struct needed for prices
+    token_field: usize,
+    collateral_field: usize,
+    staleness_field: usize,
+}
+
+impl Snapshotter {
+    pub fn new(pool: RpcPool, contract: Address, interval: u64) -> Result<Self> {
+        let fields = output_field_names("getLoan")?;
+        let position = |name: &str| {
+            fields
+                .iter()
+                .position(|f| f == name)
+                .with_context(|| format!("getLoan does not return {}", name))
+        };
+        Ok(Self {
+            views: ContractViews::new(pool, contract),
+            interval,
+            token_field: position("tokenAddress")?,
+            collateral_field: position("collateralAddress")?,
+            staleness_field: position("maxPriceStaleness")?,
+        })
+    }
+
+    /// Snapshot blocks in `from..=to`: the multiples of the interval
+    pub fn blocks_in(&self, from: u64, to: u64) -> impl Iterator<Item = u64> {
+        let first = from.div_ceil(self.interval) * self.interval;
+        (first..=to).step_by(self.interval as usize)
+    }
+
+    /// Call the views for `loans` at `block`
+    pub async fn take(&self, block: u64, timestamp: u64, loans: &[U256]) -> Result<Snapshot> {
+        let calls: Vec<(&str, Vec<Token>)> = loans
+            .iter()
+            .flat_map(|id| LOAN_VIEWS.map(|name| (name, vec![Token::Uint(*id)])))
+            .collect();
+        let mut results = self.views.call_many(&calls, block).await?.into_iter();
+        let mut failed = 0;
+
+        let mut snapshots = Vec::with_capacity(loans.len());
+        let mut price_keys = Vec::with_capacity(loans.len());
+        for id in loans {
+            let mut next = || match results.next() {
+                Some(Ok(tokens)) => Some(tokens),
+                _ => {
+                    failed += 1;
+                    None
+                }
+            };
+            let (loan, health, defaulted, interest) = (next(), next(), next(), next());
+
+            let field = |i: usize| {
+                loan.as_ref().and_then(|l| match l.first() {
+                    Some(Token::Tuple(fields)) => fields.get(i).cloned(),
+                    _ => None,
+                })
+            };
+            let staleness = field(self.staleness_field).and_then(Token::into_uint);
+            let token = field(self.token_field).and_then(Token::into_address);
+            let collateral = field(self.collateral_field).and_then(Token::into_address);
+            price_keys.push((token.zip(staleness), collateral.zip(staleness)));
+
+            snapshots.push(LoanSnapshot {
+                loan_id: id.to_string(),
+                health_factor: health.as_ref().map(|h| token_string(h.first())),
+                price_stale: health.and_then(|h| h.get(1).cloned()?.into_bool()),
+                defaulted: defaulted.and_then(|d| d.first().cloned()?.into_bool()),
+                current_interest: interest.map(|i| token_string(i.first())),
+                token_address: token.map(|a| format!("0x{:x}", a)),
+                token_price: None,
+                collateral_address: collateral.map(|a| format!("0x{:x}", a)),
+                collateral_price: None,
+            });
+        }
+
+        // Loans mostly share a handful of tokens, so each price is fetched
+        // once
+        let mut prices: HashMap<(Address, U256), Option<String>> = HashMap::new();
+        for (token, collateral) in &price_keys {
+            for key in [token, collateral].into_iter().flatten() {
+                prices.entry(*key).or_default();
+            }
+        }
+        let keys: Vec<(Address, U256)> = prices.keys().copied().collect();
+        let calls: Vec<(&str, Vec<Token>)> = keys
+            .iter()
+            .map(|(token, staleness)| {
+                (
+                    "getCurrentPrice",
+                    vec![Token::Address(*token), Token::Uint(*staleness)],
+                )
+            })
+            .collect();
+        for (key, result) in keys.iter().zip(self.views.call_many(&calls, block).await?) {
+            prices.insert(*key, first_value(result, &mut failed));
+        }
+        for (snapshot, (token, collateral)) in snapshots.iter_mut().zip(&price_keys) {
+            snapshot.token_price = token.and_then(|k| prices[&k].clone());
+            snapshot.collateral_price = collateral.and_then(|k| prices[&k].clone());
+        }
+
+        if failed > 0 {
+            warn!(
+                "{} view calls reverted in the snapshot at block {}",
+                failed, block
+            );
+        }
+        Ok(Snapshot {
+            block_number: block,
+            block_timestamp: timestamp,
+            loans: snapshots,
+        })
+    }
+}
+
+fn first_value(result: CallResult, failed: &mut usize) -> Option<String> {
+    match result {
+        Ok(tokens) => Some(token_string(tokens.first())),
+        Err(_) => {
+            *failed += 1;
+            None
+        }
+    }
+}
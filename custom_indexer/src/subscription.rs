@@ -0,0 +1,134 @@
+use anyhow::{anyhow, Context, Result};
+use ethers::prelude::*;
+use std::collections::BTreeMap;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// Something pushed over the WebSocket
+#[derive(Debug)]
+pub enum Notification {
+    /// A new head's block number
+    Head(u64),
+    Log(Box<Log>),
+}
+
+/// Live `eth_subscribe` streams of new heads and the contract's logs. `next`
+/// returns `None` once the socket drops.
+pub struct Subscription {
+    notifications: mpsc::Receiver<Notification>,
+    task: JoinHandle<()>,
+}
+
+impl Subscription {
+    pub async fn connect(ws_url: &str, contract: Address) -> Result<Self> {
+        // No automatic reconnects: blocks produced while the socket was down
+        // would silently have no logs, so a drop has to reach the caller
+        let ws = Ws::connect_with_reconnects(ws_url, 0)
+            .await
+            .with_context(|| format!("connecting to {}", ws_url))?;
+        let provider = Provider::new(ws);
+
+        let (tx, notifications) = mpsc::channel(1024);
+        let (ready_tx, ready_rx) = oneshot::channel();
+        // The subscription streams borrow the provider, so both live in the
+        // task
+        let task = tokio::spawn(async move {
+            // Logs first: every head announced afterwards is then known to
+            // have its logs pushed too
+            let filter = Filter::new().address(contract);
+            let mut logs = match provider.subscribe_logs(&filter).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(anyhow!("subscribing to logs: {}", e)));
+                    return;
+                }
+            };
+            let mut heads = match provider.subscribe_blocks().await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    let _ = ready_tx.send(Err(anyhow!("subscribing to new heads: {}", e)));
+                    return;
+                }
+            };
+            let _ = ready_tx.send(Ok(()));
+
+            loop {
+                // Logs before heads: both are forwarded in the order the
+                // node sent them, and a head must not overtake the logs
+                // announced before it
+                let notification = tokio::select! {
+                    biased;
+                    log = logs.next() => match log {
+                        Some(log) => Notification::Log(Box::new(log)),
+                        None => break,
+                    },
+                    head = heads.next() => match head {
+                        Some(block) => match block.number {
+                            Some(number) => Notification::Head(number.as_u64()),
+                            None => continue,
+                        },
+                        None => break,
+                    },
+                };
+                if tx.send(notification).await.is_err() {
+                    break;
+                }
+            }
+            warn!("WebSocket subscription stream ended");
+        });
+
+        ready_rx
+            .await
+            .map_err(|_| anyhow!("subscription task exited"))??;
+        Ok(Self {
+            notifications,
+            task,
+        })
+    }
+
+    pub async fn next(&mut self) -> Option<Notification> {
+        self.notifications.recv().await
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Logs pushed by the subscription whose blocks have not been committed yet,
+/// keyed by block number and log index so redelivered logs are not doubled
+#[derive(Debug, Default)]
+pub struct LogBuffer {
+    logs: BTreeMap<(u64, u64), Log>,
+}
+
+impl LogBuffer {
+    /// Add a log, or drop it again if the node reports it as removed by a
+    /// reorg
+    pub fn insert(&mut self, log: Log) {
+        let (Some(block), Some(index)) = (log.block_number, log.log_index) else {
+            return;
+        };
+        let key = (block.as_u64(), index.as_u64());
+        if log.removed == Some(true) {
+            self.logs.remove(&key);
+        } else {
+            self.logs.insert(key, log);
+        }
+    }
+
+    /// Remove and return the logs of every block up to `block`, in order
+    pub fn take_through(&mut self, block: u64) -> Vec<Log> {
+        let rest = self.logs.split_off(&(block + 1, 0));
+        std::mem::replace(&mut self.logs, rest)
+            .into_values()
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.logs.clear();
+    }
+}
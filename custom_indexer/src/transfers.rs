@@ -0,0 +1,125 @@
+use anyhow::{Context, Result};
+use ethers::prelude::*;
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::fetch::fetch_filtered;
+use crate::rpc_pool::PoolProvider;
+use crate::sink::IndexedEvent;
+
+/// topic0 of `Transfer(address,address,uint256)`
+pub fn transfer_topic() -> H256 {
+    H256(keccak256("Transfer(address,address,uint256)"))
+}
+
+/// An ERC20 transfer into or out of the contract, like the Substreams
+/// `Erc20Transfer`, linked to the NeuroLend event emitted in the same
+/// transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedTransfer {
+    pub token_address: String,
+    pub from_address: String,
+    pub to_address: String,
+    /// Amount in the token's smallest unit
+    pub value: String,
+    /// `in` when the contract received the tokens, `out` when it sent them
+    pub direction: String,
+    pub transaction_hash: String,
+    pub block_number: u64,
+    pub block_timestamp: u64,
+    pub log_index: u64,
+    /// Name of the NeuroLend event in the same transaction, if there is one
+    pub action: Option<String>,
+    pub loan_id: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// Finds ERC20 transfers with the contract as sender or recipient, on any
+/// token
+pub struct TransferFetcher {
+    provider: Arc<PoolProvider>,
+    contract: Address,
+}
+
+impl TransferFetcher {
+    pub fn new(provider: Arc<PoolProvider>, contract: Address) -> Self {
+        Self { provider, contract }
+    }
+
+    /// `Transfer` logs in `from..=to` from or to the contract, in chain
+    /// order
+    pub async fn fetch(&self, from: u64, to: u64) -> Result<Vec<Log>> {
+        let base = Filter::new().topic0(transfer_topic());
+        let contract = H256::from(self.contract);
+
+        let mut logs = Vec::new();
+        for filter in [base.clone().topic1(contract), base.topic2(contract)] {
+            let fetched = fetch_filtered(self.provider.clone(), filter, from, to).await;
+            logs.extend(fetched.logs.with_context(|| {
+                format!("fetching token transfers in blocks {} to {}", from, to)
+            })?);
+        }
+
+        // A transfer from the contract to itself matches both filters
+        let position = |log: &Log| {
+            (
+                log.block_number.map(|n| n.as_u64()),
+                log.log_index.map(|i| i.as_u64()),
+            )
+        };
+        logs.sort_by_key(position);
+        logs.dedup_by_key(|log| position(log));
+        Ok(logs)
+    }
+}
+
+/// Decode a `Transfer` log and link it to the first event in `events`
+/// from the same transaction, preferring one about a loan or request.
+/// ERC721 transfers share the signature but index the token id, and are
+/// skipped.
+pub fn decode(
+    contract: Address,
+    log: &Log,
+    block_timestamp: u64,
+    events: &[IndexedEvent],
+) -> Option<IndexedTransfer> {
+    if log.topics.len() != 3 || log.data.len() < 32 {
+        return None;
+    }
+    let from = Address::from(log.topics[1]);
+    let to = Address::from(log.topics[2]);
+    let transaction_hash = format!("0x{:x}", log.transaction_hash?);
+
+    let in_tx: Vec<&IndexedEvent> = events
+        .iter()
+        .filter(|e| e.transaction_hash == transaction_hash)
+        .collect();
+    let param = |event: &IndexedEvent, name: &str| {
+        event
+            .decoded_data
+            .as_ref()?
+            .get(name)?
+            .as_str()
+            .map(str::to_string)
+    };
+    let action = in_tx
+        .iter()
+        .find(|e| param(e, "loanId").is_some() || param(e, "requestId").is_some())
+        .or(in_tx.first());
+
+    Some(IndexedTransfer {
+        token_address: format!("0x{:x}", log.address),
+        from_address: format!("0x{:x}", from),
+        to_address: format!("0x{:x}", to),
+        value: U256::from_big_endian(&log.data[..32]).to_string(),
+        direction: if to == contract { "in" } else { "out" }.to_string(),
+        transaction_hash,
+        block_number: log.block_number?.as_u64(),
+        block_timestamp,
+        log_index: log.log_index?.as_u64(),
+        action: action.map(|e| e.event_name.clone()),
+        loan_id: action.and_then(|e| param(e, "loanId")),
+        request_id: action.and_then(|e| param(e, "requestId")),
+    })
+}
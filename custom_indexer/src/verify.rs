@@ -0,0 +1,684 @@
+use anyhow::{anyhow, Result};
+use ethers::abi::Token;
+use ethers::types::{Address, U256};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::info;
+
+use crate::rpc_pool::RpcPool;
+use crate::sink::IndexedEvent;
+use crate::views::{output_field_names, token_string, CallResult, ContractViews};
+
+/// `DreamLend.LoanStatus` in declaration order, which is how the views
+/// return it. Loans and requests share the enum.
+pub const LOAN_STATUSES: [&str; 5] = ["Pending", "Active", "Repaid", "Defaulted", "Cancelled"];
+
+/// Field values of one loan or request, keyed by the view's field names.
+/// Only fields the events determine are present.
+pub type Fields = BTreeMap<String, String>;
+
+/// Loan and request state rebuilt from indexed events
+#[derive(Debug, Default)]
+pub struct IndexedState {
+    pub loans: BTreeMap<U256, Fields>,
+    pub requests: BTreeMap<U256, Fields>,
+    pub active_offers: BTreeSet<U256>,
+    pub active_requests: BTreeSet<U256>,
+    /// Loans by borrower address
+    pub borrower_loans: BTreeMap<String, BTreeSet<U256>>,
+}
+
+impl IndexedState {
+    /// Replay `events` in chain order
+    pub fn rebuild(events: &[IndexedEvent]) -> Self {
+        let mut events: Vec<&IndexedEvent> = events.iter().collect();
+        events.sort_by_key(|e| (e.block_number, e.log_index));
+
+        let mut state = Self::default();
+        for event in events {
+            state.apply(event);
+        }
+        state
+    }
+
+    /// Apply the next event in chain order
+    pub fn apply(&mut self, event: &IndexedEvent) {
+        if let Some(decoded) = &event.decoded_data {
+            self.apply_decoded(&event.event_name, decoded);
+        }
+    }
+
+    /// Loans the events leave active
+    pub fn active_loans(&self) -> Vec<U256> {
+        self.loans
+            .iter()
+            .filter(|(_, loan)| loan.get("status").map(String::as_str) == Some("Active"))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn apply_decoded(&mut self, event_name: &str, params: &serde_json::Map<String, Value>) {
+        let get = |name: &str| params.get(name).and_then(Value::as_str).map(str::to_string);
+        let id = |name: &str| get(name).and_then(|v| U256::from_dec_str(&v).ok());
+
+        match event_name {
+            "LoanCreated" => {
+                let Some(loan_id) = id("loanId") else { return };
+                let loan = self.loans.entry(loan_id).or_default();
+                loan.insert("id".into(), loan_id.to_string());
+                for field in [
+                    "lender",
+                    "tokenAddress",
+                    "amount",
+                    "interestRate",
+                    "duration",
+                    "collateralAddress",
+                    "collateralAmount",
+                    "minCollateralRatioBPS",
+                    "liquidationThresholdBPS",
+                    "maxPriceStaleness",
+                ] {
+                    if let Some(value) = get(field) {
+                        loan.insert(field.into(), value);
+                    }
+                }
+                loan.insert("status".into(), "Pending".into());
+                self.active_offers.insert(loan_id);
+            }
+            "LoanAccepted" => {
+                let Some(loan_id) = id("loanId") else { return };
+                let loan = self.loans.entry(loan_id).or_default();
+                if let Some(borrower) = get("borrower") {
+                    loan.insert("borrower".into(), borrower.clone());
+                    self.borrower_loans
+                        .entry(borrower)
+                        .or_default()
+                        .insert(loan_id);
+                }
+                if let Some(start) = get("timestamp") {
+                    loan.insert("startTime".into(), start);
+                }
+                loan.insert("status".into(), "Active".into());
+                self.active_offers.remove(&loan_id);
+            }
+            "LoanMatched" => {
+                let Some(loan_id) = id("loanId") else { return };
+                let loan = self.loans.entry(loan_id).or_default();
+                loan.insert("id".into(), loan_id.to_string());
+                for field in ["lender", "borrower", "amount", "interestRate"] {
+                    if let Some(value) = get(field) {
+                        loan.insert(field.into(), value);
+                    }
+                }
+                if let Some(start) = get("timestamp") {
+                    loan.insert("startTime".into(), start);
+                }
+                loan.insert("status".into(), "Active".into());
+                if let Some(borrower) = get("borrower") {
+                    self.borrower_loans
+                        .entry(borrower)
+                        .or_default()
+                        .insert(loan_id);
+                }
+                if let Some(offer_id) = id("offerId") {
+                    self.active_offers.remove(&offer_id);
+                }
+                if let Some(request_id) = id("requestId") {
+                    self.active_requests.remove(&request_id);
+                    // What a filled request's status becomes is not visible
+                    // in its events
+                    if let Some(request) = self.requests.get_mut(&request_id) {
+                        request.remove("status");
+                    }
+                }
+            }
+            "PartialRepayment" => {
+                let Some(loan_id) = id("loanId") else { return };
+                if let Some(total) = get("totalRepaidAmount") {
+                    self.loans
+                        .entry(loan_id)
+                        .or_default()
+                        .insert("repaidAmount".into(), total);
+                }
+            }
+            "LoanRepaid" => {
+                let Some(loan_id) = id("loanId") else { return };
+                let loan = self.loans.entry(loan_id).or_default();
+                loan.insert("status".into(), "Repaid".into());
+                // The final payment's amount does not say what the total
+                // came to
+                loan.remove("repaidAmount");
+            }
+            "LoanLiquidated" => {
+                let Some(loan_id) = id("loanId") else { return };
+                self.loans
+                    .entry(loan_id)
+                    .or_default()
+                    .insert("status".into(), "Defaulted".into());
+            }
+            "LoanOfferCancelled" => {
+                let Some(loan_id) = id("loanId") else { return };
+                self.loans
+                    .entry(loan_id)
+                    .or_default()
+                    .insert("status".into(), "Cancelled".into());
+                self.active_offers.remove(&loan_id);
+            }
+            "LoanOfferRemoved" => {
+                if let Some(loan_id) = id("loanId") {
+                    self.active_offers.remove(&loan_id);
+                }
+            }
+            "CollateralAdded" | "CollateralRemoved" => {
+                let Some(loan_id) = id("loanId") else { return };
+                let Some(amount) = id("amount") else { return };
+                let loan = self.loans.entry(loan_id).or_default();
+                let current = loan
+                    .get("collateralAmount")
+                    .and_then(|v| U256::from_dec_str(v).ok());
+                // Without the starting amount the total is unknown
+                if let Some(current) = current {
+                    let total = if event_name == "CollateralAdded" {
+                        current.saturating_add(amount)
+                    } else {
+                        current.saturating_sub(amount)
+                    };
+                    loan.insert("collateralAmount".into(), total.to_string());
+                }
+            }
+            "LoanRequestCreated" => {
+                let Some(request_id) = id("requestId") else {
+                    return;
+                };
+                let request = self.requests.entry(request_id).or_default();
+                request.insert("id".into(), request_id.to_string());
+                for field in [
+                    "borrower",
+                    "tokenAddress",
+                    "amount",
+                    "maxInterestRate",
+                    "duration",
+                    "collateralAddress",
+                    "collateralAmount",
+                    "minCollateralRatioBPS",
+                    "liquidationThresholdBPS",
+                    "maxPriceStaleness",
+                ] {
+                    if let Some(value) = get(field) {
+                        request.insert(field.into(), value);
+                    }
+                }
+                request.insert("status".into(), "Pending".into());
+                self.active_requests.insert(request_id);
+            }
+            "LoanRequestCancelled" => {
+                let Some(request_id) = id("requestId") else {
+                    return;
+                };
+                self.requests
+                    .entry(request_id)
+                    .or_default()
+                    .insert("status".into(), "Cancelled".into());
+                self.active_requests.remove(&request_id);
+            }
+            "LoanRequestRemoved" => {
+                if let Some(request_id) = id("requestId") {
+                    self.active_requests.remove(&request_id);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// One difference between the index and the contract
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Mismatch {
+    /// `loan` or `request`
+    pub kind: String,
+    pub id: String,
+    /// A struct field, or the list view the id is or is not in
+    pub field: String,
+    pub indexed: String,
+    pub onchain: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthFactor {
+    pub loan_id: String,
+    pub current_ratio: String,
+    pub price_stale: bool,
+}
+
+/// Outcome of a `verify` run
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    /// Block the views were called at, the last indexed block
+    pub block: u64,
+    pub loans_checked: usize,
+    pub requests_checked: usize,
+    pub borrowers_checked: usize,
+    pub mismatches: Vec<Mismatch>,
+    /// `getLoanHealthFactor` of every loan the index has as active
+    pub health_factors: Vec<HealthFactor>,
+}
+
+/// Calls the contract's view functions at a fixed block
+pub struct Verifier {
+    views: ContractViews,
+    block: u64,
+}
+
+impl Verifier {
+    pub fn new(pool: RpcPool, contract: Address, block: u64) -> Self {
+        Self {
+            views: ContractViews::new(pool, contract),
+            block,
+        }
+    }
+
+    /// Diff `state` against the contract
+    pub async fn verify(&self, state: &IndexedState) -> Result<Report> {
+        let mut mismatches = Vec::new();
+
+        let loan_ids: Vec<U256> = state.loans.keys().copied().collect();
+        info!("Checking {} loans against getLoan", loan_ids.len());
+        let loans = self
+            .views
+            .call_each("getLoan", &loan_ids, self.block)
+            .await?;
+        for ((id, expected), onchain) in state.loans.iter().zip(loans) {
+            diff_fields("loan", *id, expected, onchain, &mut mismatches);
+        }
+
+        let request_ids: Vec<U256> = state.requests.keys().copied().collect();
+        info!(
+            "Checking {} requests against getLoanRequest",
+            request_ids.len()
+        );
+        let requests = self
+            .views
+            .call_each("getLoanRequest", &request_ids, self.block)
+            .await?;
+        for ((id, expected), onchain) in state.requests.iter().zip(requests) {
+            diff_fields("request", *id, expected, onchain, &mut mismatches);
+        }
+
+        let offers = self
+            .views
+            .id_list("getActiveLoanOffers", vec![], self.block)
+            .await?;
+        diff_sets(
+            "loan",
+            "activeLoanOffers",
+            &state.active_offers,
+            &offers,
+            &mut mismatches,
+        );
+        let open_requests = self
+            .views
+            .id_list("getActiveLoanRequests", vec![], self.block)
+            .await?;
+        diff_sets(
+            "request",
+            "activeLoanRequests",
+            &state.active_requests,
+            &open_requests,
+            &mut mismatches,
+        );
+
+        info!(
+            "Checking {} borrowers against getBorrowerLoans",
+            state.borrower_loans.len()
+        );
+        for (borrower, expected) in &state.borrower_loans {
+            let address: Address = borrower
+                .parse()
+                .map_err(|e| anyhow!("invalid borrower address {}: {}", borrower, e))?;
+            let onchain = self
+                .views
+                .id_list(
+                    "getBorrowerLoans",
+                    vec![Token::Address(address)],
+                    self.block,
+                )
+                .await?;
+            diff_sets(
+                "loan",
+                &format!("borrowerLoans({})", borrower),
+                expected,
+                &onchain,
+                &mut mismatches,
+            );
+        }
+
+        let active = state.active_loans();
+        let mut health_factors = Vec::new();
+        for (id, outcome) in active.iter().zip(
+            self.views
+                .call_each("getLoanHealthFactor", &active, self.block)
+                .await?,
+        ) {
+            match outcome {
+                Ok(tokens) => health_factors.push(HealthFactor {
+                    loan_id: id.to_string(),
+                    current_ratio: token_string(tokens.first()),
+                    price_stale: matches!(tokens.get(1), Some(Token::Bool(true))),
+                }),
+                // The index thinks the loan is active, the contract does not
+                // have a health factor for it
+                Err(error) => mismatches.push(Mismatch {
+                    kind: "loan".into(),
+                    id: id.to_string(),
+                    field: "healthFactor".into(),
+                    indexed: "active".into(),
+                    onchain: error,
+                }),
+            }
+        }
+
+        Ok(Report {
+            block: self.block,
+            loans_checked: state.loans.len(),
+            requests_checked: state.requests.len(),
+            borrowers_checked: state.borrower_loans.len(),
+            mismatches,
+            health_factors,
+        })
+    }
+}
+
+/// Compare the fields the index knows with a `getLoan`/`getLoanRequest`
+/// result
+fn diff_fields(
+    kind: &str,
+    id: U256,
+    expected: &Fields,
+    onchain: CallResult,
+    mismatches: &mut Vec<Mismatch>,
+) {
+    let mut mismatch = |field: &str, indexed: &str, onchain: String| {
+        mismatches.push(Mismatch {
+            kind: kind.into(),
+            id: id.to_string(),
+            field: field.into(),
+            indexed: indexed.into(),
+            onchain,
+        })
+    };
+
+    let fields = match onchain {
+        Ok(tokens) => match tokens.into_iter().next() {
+            Some(Token::Tuple(fields)) => fields,
+            _ => return mismatch("exists", "yes", "unexpected output".into()),
+        },
+        Err(error) => return mismatch("exists", "yes", error),
+    };
+    let names = match output_field_names(if kind == "loan" {
+        "getLoan"
+    } else {
+        "getLoanRequest"
+    }) {
+        Ok(names) => names,
+        Err(e) => return mismatch("exists", "yes", format!("{:#}", e)),
+    };
+    let onchain: BTreeMap<String, String> = names
+        .into_iter()
+        .zip(fields)
+        .map(|(name, token)| {
+            let value = if name == "status" {
+                status_name(&token)
+            } else {
+                token_string(Some(&token))
+            };
+            (name, value)
+        })
+        .collect();
+
+    // A struct that was never written reads back as zeroes
+    if onchain.get("id").is_some_and(|v| v == "0") && !id.is_zero() {
+        return mismatch("exists", "yes", "no".into());
+    }
+    for (field, indexed) in expected {
+        let actual = onchain.get(field).cloned().unwrap_or_default();
+        if !actual.eq_ignore_ascii_case(indexed) {
+            mismatch(field, indexed, actual);
+        }
+    }
+}
+
+/// Compare the ids the index has in a list view with the contract's
+fn diff_sets(
+    kind: &str,
+    field: &str,
+    indexed: &BTreeSet<U256>,
+    onchain: &BTreeSet<U256>,
+    mismatches: &mut Vec<Mismatch>,
+) {
+    for missing in onchain.difference(indexed) {
+        mismatches.push(Mismatch {
+            kind: kind.into(),
+            id: missing.to_string(),
+            field: field.into(),
+            indexed: "absent".into(),
+            onchain: "present".into(),
+        });
+    }
+    for extra in indexed.difference(onchain) {
+        mismatches.push(Mismatch {
+            kind: kind.into(),
+            id: extra.to_string(),
+            field: field.into(),
+            indexed: "present".into(),
+            onchain: "absent".into(),
+        });
+    }
+}
+
+fn status_name(token: &Token) -> String {
+    match token.clone().into_uint() {
+        Some(n) if n < U256::from(LOAN_STATUSES.len()) => LOAN_STATUSES[n.as_usize()].to_string(),
+        Some(n) => format!("Unknown({})", n),
+        None => token_string(Some(token)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const LENDER: &str = "0x00000000000000000000000000000000000000aa";
+    const BORROWER: &str = "0x00000000000000000000000000000000000000bb";
+
+    fn apply(state: &mut IndexedState, event_name: &str, params: Value) {
+        let Value::Object(params) = params else {
+            panic!("params must be an object")
+        };
+        state.apply_decoded(event_name, &params);
+    }
+
+    fn loan(state: &IndexedState, id: u64) -> &Fields {
+        &state.loans[&U256::from(id)]
+    }
+
+    /// A `getLoan` result with everything but the given fields zeroed
+    fn get_loan(id: u64, status: u8, collateral: u64) -> CallResult {
+        let address = |s: &str| Token::Address(s.parse().unwrap());
+        let uint = |n: u64| Token::Uint(U256::from(n));
+        Ok(vec![Token::Tuple(vec![
+            uint(id),
+            address(LENDER),
+            address(BORROWER),
+            Token::Address(Address::zero()),
+            uint(1000),
+            uint(0),
+            uint(0),
+            Token::Address(Address::zero()),
+            uint(collateral),
+            uint(0),
+            uint(status as u64),
+            uint(0),
+            uint(0),
+            uint(0),
+            uint(0),
+        ])])
+    }
+
+    #[test]
+    fn tracks_collateral_changes() {
+        let mut state = IndexedState::default();
+        apply(
+            &mut state,
+            "LoanCreated",
+            json!({ "loanId": "1", "lender": LENDER, "collateralAmount": "1000" }),
+        );
+        apply(
+            &mut state,
+            "CollateralAdded",
+            json!({ "loanId": "1", "amount": "250" }),
+        );
+        assert_eq!(loan(&state, 1)["collateralAmount"], "1250");
+
+        apply(
+            &mut state,
+            "CollateralRemoved",
+            json!({ "loanId": "1", "amount": "2000" }),
+        );
+        assert_eq!(loan(&state, 1)["collateralAmount"], "0");
+
+        // No LoanCreated for loan 2, so there is nothing to add to
+        apply(
+            &mut state,
+            "CollateralAdded",
+            json!({ "loanId": "2", "amount": "250" }),
+        );
+        assert!(!loan(&state, 2).contains_key("collateralAmount"));
+    }
+
+    #[test]
+    fn loan_matched_fills_the_offer_and_request() {
+        let mut state = IndexedState::default();
+        apply(
+            &mut state,
+            "LoanRequestCreated",
+            json!({ "requestId": "7", "borrower": BORROWER }),
+        );
+        apply(&mut state, "LoanCreated", json!({ "loanId": "3" }));
+        assert_eq!(state.requests[&U256::from(7)]["status"], "Pending");
+        assert!(state.active_requests.contains(&U256::from(7)));
+        assert!(state.active_offers.contains(&U256::from(3)));
+
+        apply(
+            &mut state,
+            "LoanMatched",
+            json!({
+                "loanId": "9",
+                "offerId": "3",
+                "requestId": "7",
+                "lender": LENDER,
+                "borrower": BORROWER,
+                "timestamp": "1700000000",
+            }),
+        );
+        assert!(!state.requests[&U256::from(7)].contains_key("status"));
+        assert!(state.active_requests.is_empty());
+        assert!(state.active_offers.is_empty());
+        assert_eq!(loan(&state, 9)["status"], "Active");
+        assert_eq!(loan(&state, 9)["startTime"], "1700000000");
+        assert_eq!(state.active_loans(), [U256::from(9)]);
+        assert!(state.borrower_loans[BORROWER].contains(&U256::from(9)));
+    }
+
+    #[test]
+    fn diffs_indexed_fields_against_get_loan() {
+        let mut state = IndexedState::default();
+        apply(
+            &mut state,
+            "LoanCreated",
+            json!({ "loanId": "1", "lender": LENDER, "amount": "1000", "collateralAmount": "500" }),
+        );
+        let expected = loan(&state, 1);
+
+        let mut mismatches = Vec::new();
+        diff_fields(
+            "loan",
+            U256::from(1),
+            expected,
+            get_loan(1, 0, 500),
+            &mut mismatches,
+        );
+        assert!(mismatches.is_empty(), "{:?}", mismatches);
+
+        diff_fields(
+            "loan",
+            U256::from(1),
+            expected,
+            get_loan(1, 1, 400),
+            &mut mismatches,
+        );
+        let found: Vec<_> = mismatches
+            .iter()
+            .map(|m| (m.field.as_str(), m.indexed.as_str(), m.onchain.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("collateralAmount", "500", "400"),
+                ("status", "Pending", "Active")
+            ]
+        );
+    }
+
+    #[test]
+    fn a_zeroed_struct_means_the_loan_does_not_exist() {
+        let mut state = IndexedState::default();
+        apply(&mut state, "LoanCreated", json!({ "loanId": "4" }));
+
+        let mut mismatches = Vec::new();
+        diff_fields(
+            "loan",
+            U256::from(4),
+            loan(&state, 4),
+            get_loan(0, 0, 0),
+            &mut mismatches,
+        );
+        assert_eq!(mismatches.len(), 1, "{:?}", mismatches);
+        assert_eq!(mismatches[0].field, "exists");
+        assert_eq!(mismatches[0].onchain, "no");
+
+        mismatches.clear();
+        diff_fields(
+            "loan",
+            U256::from(4),
+            loan(&state, 4),
+            Err("execution reverted".into()),
+            &mut mismatches,
+        );
+        assert_eq!(mismatches[0].field, "exists");
+        assert_eq!(mismatches[0].onchain, "execution reverted");
+    }
+
+    #[test]
+    fn names_loan_statuses() {
+        let names: Vec<_> = (0..6u64)
+            .map(|n| status_name(&Token::Uint(U256::from(n))))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Pending",
+                "Active",
+                "Repaid",
+                "Defaulted",
+                "Cancelled",
+                "Unknown(5)"
+            ]
+        );
+        assert_eq!(status_name(&Token::Bool(true)), "true");
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Context, Result};
+use ethers::abi::{Abi, Function, Token};
+use ethers::types::{Address, Bytes, U256};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+
+use crate::decoder::token_to_json;
+use crate::event_signatures::{neurolend_abi, NEUROLEND_ABI};
+use crate::rpc_pool::RpcPool;
+
+/// `eth_call`s per batched request
+const CALL_BATCH_SIZE: usize = 100;
+
+/// Outcome of one view call: its decoded outputs, or why it failed
+pub type CallResult = std::result::Result<Vec<Token>, String>;
+
+/// Calls the contract's view functions with batched `eth_call`s
+#[derive(Clone)]
+pub struct ContractViews {
+    pool: RpcPool,
+    contract: Address,
+    abi: Abi,
+}
+
+impl ContractViews {
+    pub fn new(pool: RpcPool, contract: Address) -> Self {
+        Self {
+            pool,
+            contract,
+            abi: neurolend_abi(),
+        }
+    }
+
+    pub fn function(&self, name: &str) -> Result<&Function> {
+        self.abi
+            .function(name)
+            .with_context(|| format!("{} is not in the contract ABI", name))
+    }
+
+    /// Make every `(function, args)` call at `block`, in batches. A revert
+    /// is returned as its error message rather than failing the rest.
+    pub async fn call_many(
+        &self,
+        calls: &[(&str, Vec<Token>)],
+        block: u64,
+    ) -> Result<Vec<CallResult>> {
+        let mut results = Vec::with_capacity(calls.len());
+        for chunk in calls.chunks(CALL_BATCH_SIZE) {
+            let functions = chunk
+                .iter()
+                .map(|(name, _)| self.function(name))
+                .collect::<Result<Vec<_>>>()?;
+            let params = functions
+                .iter()
+                .zip(chunk)
+                .map(|(function, (_, args))| self.call_params(function, args, block))
+                .collect::<Result<Vec<Value>>>()?;
+            let outcomes = self
+                .pool
+                .batch_try_call::<Bytes>("eth_call", &params)
+                .await?;
+            for (function, outcome) in functions.into_iter().zip(outcomes) {
+                results.push(match outcome {
+                    Ok(Some(output)) => function
+                        .decode_output(&output)
+                        .map_err(|e| format!("decoding {} output: {}", function.name, e)),
+                    Ok(None) => Err("no result".to_string()),
+                    Err(error) => Err(error
+                        .get("message")
+                        .and_then(Value::as_str)
+                        .map_or_else(|| error.to_string(), str::to_string)),
+                });
+            }
+        }
+        Ok(results)
+    }
+
+    /// Call `name` once per id
+    pub async fn call_each(&self, name: &str, ids: &[U256], block: u64) -> Result<Vec<CallResult>> {
+        let calls: Vec<(&str, Vec<Token>)> = ids
+            .iter()
+            .map(|id| (name, vec![Token::Uint(*id)]))
+            .collect();
+        self.call_many(&calls, block).await
+    }
+
+    /// A single call, failing on a revert
+    pub async fn call(&self, name: &str, args: Vec<Token>, block: u64) -> Result<Vec<Token>> {
+        self.call_many(&[(name, args)], block)
+            .await?
+            .pop()
+            .with_context(|| format!("{} returned nothing", name))?
+            .map_err(|e| anyhow!("calling {}: {}", name, e))
+    }
+
+    /// A view returning `uint256[]`, as a set
+    pub async fn id_list(
+        &self,
+        name: &str,
+        args: Vec<Token>,
+        block: u64,
+    ) -> Result<BTreeSet<U256>> {
+        match self.call(name, args, block).await?.into_iter().next() {
+            Some(Token::Array(items)) => {
+                Ok(items.into_iter().filter_map(|t| t.into_uint()).collect())
+            }
+            _ => Err(anyhow!("{} did not return a list", name)),
+        }
+    }
+
+    fn call_params(&self, function: &Function, args: &[Token], block: u64) -> Result<Value> {
+        let data = function
+            .encode_input(args)
+            .with_context(|| format!("encoding {} call", function.name))?;
+        Ok(json!([
+            {
+                "to": format!("0x{:x}", self.contract),
+                "data": format!("0x{}", hex::encode(data)),
+            },
+            format!("0x{:x}", block),
+        ]))
+    }
+}
+
+/// Names of the struct fields a view returns, from the ABI's tuple
+/// components (ethabi drops them when parsing)
+pub fn output_field_names(function: &str) -> Result<Vec<String>> {
+    let abi: Value = serde_json::from_str(NEUROLEND_ABI)?;
+    abi.as_array()
+        .into_iter()
+        .flatten()
+        .find(|item| item["type"] == "function" && item["name"] == function)
+        .and_then(|item| item["outputs"][0]["components"].as_array())
+        .map(|components| {
+            components
+                .iter()
+                .map(|c| c["name"].as_str().unwrap_or_default().to_string())
+                .collect()
+        })
+        .with_context(|| format!("{} has no struct output in the ABI", function))
+}
+
+/// A token in the format events are decoded to
+pub fn token_string(token: Option<&Token>) -> String {
+    match token.map(|t| token_to_json(t.clone())) {
+        Some(Value::String(s)) => s,
+        Some(other) => other.to_string(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,1267 @@
+//! End-to-end tests running the indexer binary against the mock node
+
+use ethers::abi::{self, Token};
+use ethers::providers::Middleware;
+use ethers::types::{Address, Bytes, Filter, H256, U256};
+use ethers::utils::keccak256;
+use neurolend_indexer::calls::IndexedCall;
+use neurolend_indexer::checkpoint::Checkpoint;
+use neurolend_indexer::config::{DEFAULT_CONTRACT, DEFAULT_SEGMENT_BLOCKS};
+use neurolend_indexer::event_signatures::neurolend_abi;
+use neurolend_indexer::fetch::GET_LOGS_ATTEMPTS;
+use neurolend_indexer::gaps::GapList;
+use neurolend_indexer::postgres::PostgresSink;
+use neurolend_indexer::protobuf::contract;
+use neurolend_indexer::rpc_pool::RpcPool;
+use neurolend_indexer::segments::{Compression, SegmentStore};
+use neurolend_indexer::sink::{IndexedEvent, CALLS_DIR, SNAPSHOTS_DIR, TRANSFERS_DIR};
+use neurolend_indexer::snapshots::Snapshot;
+use neurolend_indexer::transfers::{transfer_topic, IndexedTransfer};
+use neurolend_mock_rpc::{Chain, MockRpc, GENESIS_TIMESTAMP};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use sqlx::PgPool;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tempfile::TempDir;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const START: u64 = 100;
+/// Enough for every attempt at a range to fail and for a few polls
+const TIMEOUT: Duration = Duration::from_secs(90);
+
+fn contract() -> Address {
+    DEFAULT_CONTRACT.parse().unwrap()
+}
+
+fn lender() -> Address {
+    Address::repeat_byte(0x11)
+}
+
+fn borrower() -> Address {
+    Address::repeat_byte(0x22)
+}
+
+fn token() -> Address {
+    Address::repeat_byte(0x33)
+}
+
+fn uint(value: u64) -> Token {
+    Token::Uint(U256::from(value))
+}
+
+/// Topics and data of the NeuroLend event `name`. `args` are in ABI order.
+fn event_log(name: &str, args: Vec<Token>) -> (Vec<H256>, Vec<u8>) {
+    let abi = neurolend_abi();
+    let event = abi.event(name).unwrap();
+    let mut topics = vec![event.signature()];
+    let mut data = Vec::new();
+    for (param, arg) in event.inputs.iter().zip(args) {
+        if param.indexed {
+            topics.push(H256::from_slice(&abi::encode(&[arg])));
+        } else {
+            data.push(arg);
+        }
+    }
+    (topics, abi::encode(&data))
+}
+
+/// Emit the NeuroLend event `name` from the contract at `block`, mining up
+/// to it first. `args` are in ABI order.
+fn emit(chain: &mut Chain, block: u64, name: &str, args: Vec<Token>) {
+    let (topics, data) = event_log(name, args);
+    chain.mine_to(block);
+    chain
+        .block_mut(block)
+        .unwrap()
+        .log(contract(), topics, data);
+}
+
+fn loan_created(loan: u64) -> Vec<Token> {
+    vec![
+        uint(loan),
+        Token::Address(lender()),
+        Token::Address(token()),
+        uint(1000),
+        uint(500),
+        uint(86400),
+        Token::Address(token()),
+        uint(2000),
+        uint(15000),
+        uint(12000),
+        uint(3600),
+    ]
+}
+
+fn loan_ids(events: &[IndexedEvent]) -> Vec<String> {
+    events
+        .iter()
+        .map(|e| {
+            e.decoded_data.as_ref().unwrap()["loanId"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect()
+}
+
+/// The indexer binary over a temporary output directory. A running
+/// `follow` is killed when dropped.
+struct Indexer {
+    output_dir: TempDir,
+    rpc_url: String,
+    child: Option<Child>,
+}
+
+impl Indexer {
+    fn new(rpc: &MockRpc) -> Self {
+        Self {
+            output_dir: TempDir::new().unwrap(),
+            rpc_url: rpc.url(),
+            child: None,
+        }
+    }
+
+    fn log_file(&self) -> PathBuf {
+        self.output_dir.path().join("indexer.log")
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let log = File::options()
+            .create(true)
+            .append(true)
+            .open(self.log_file())
+            .unwrap();
+        let mut command = Command::new(env!("CARGO_BIN_EXE_neurolend_indexer"));
+        command
+            .args(args)
+            .args(["--rpc-url", &self.rpc_url])
+            .args(["--start-block", &START.to_string()])
+            .args(["--batch-size", "20"])
+            .arg("--output-dir")
+            .arg(self.output_dir.path())
+            .env_clear()
+            .stdout(log.try_clone().unwrap())
+            .stderr(log);
+        command
+    }
+
+    fn follow(&mut self) {
+        self.follow_with(&[]);
+    }
+
+    fn follow_with(&mut self, args: &[&str]) {
+        let args = [&["follow"], args].concat();
+        self.child = Some(self.command(&args).spawn().unwrap());
+    }
+
+    fn stop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+
+    /// Send SIGTERM to the running `follow` and wait for it to exit
+    async fn terminate(&mut self) -> ExitStatus {
+        let mut child = self.child.take().unwrap();
+        let sent = Command::new("kill")
+            .args(["-TERM", &child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(sent.success());
+
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                return status;
+            }
+            assert!(Instant::now() < deadline, "indexer did not exit");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Run a command that exits on its own. Blocks, so the test runtime
+    /// needs another thread to keep serving the mock node.
+    fn run(&self, args: &[&str]) -> ExitStatus {
+        self.command(args).status().unwrap()
+    }
+
+    fn checkpoint(&self) -> Option<Checkpoint> {
+        Checkpoint::load(self.output_dir.path()).ok().flatten()
+    }
+
+    fn gaps(&self) -> GapList {
+        GapList::load(self.output_dir.path()).unwrap_or_default()
+    }
+
+    fn events(&self) -> Vec<IndexedEvent> {
+        SegmentStore::open(
+            self.output_dir.path(),
+            "events",
+            DEFAULT_SEGMENT_BLOCKS,
+            Compression::None,
+        )
+        .and_then(|store| store.records())
+        .unwrap_or_default()
+    }
+
+    /// Records of the store in subdirectory `name`, such as `calls`
+    fn records<T: DeserializeOwned>(&self, name: &'static str) -> Vec<T> {
+        SegmentStore::open(
+            &self.output_dir.path().join(name),
+            name,
+            DEFAULT_SEGMENT_BLOCKS,
+            Compression::None,
+        )
+        .and_then(|store| store.records())
+        .unwrap_or_default()
+    }
+
+    async fn wait_until(&mut self, what: &str, done: impl Fn(&Self) -> bool) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !done(self) {
+            let exited = match &mut self.child {
+                Some(child) => child.try_wait().unwrap(),
+                None => None,
+            };
+            if exited.is_some() || Instant::now() > deadline {
+                panic!(
+                    "gave up waiting for {} (indexer exited: {:?}), log:\n{}",
+                    what,
+                    exited,
+                    fs::read_to_string(self.log_file()).unwrap_or_default()
+                );
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    }
+
+    /// Wait for a caught-up checkpoint past `block`
+    async fn wait_for_block(&mut self, block: u64) {
+        self.wait_until(&format!("block {}", block), |indexer| {
+            indexer
+                .checkpoint()
+                .is_some_and(|c| c.caught_up && c.current_block > block)
+        })
+        .await;
+    }
+}
+
+impl Drop for Indexer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn decodes_events_end_to_end() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    let created_tx = {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 105, "LoanCreated", loan_created(1));
+        emit(
+            &mut chain,
+            110,
+            "LoanAccepted",
+            vec![
+                uint(1),
+                Token::Address(borrower()),
+                uint(1_700_000_110),
+                uint(20000),
+            ],
+        );
+        // Same signature, different contract
+        let (topics, data) = {
+            let block = chain.block(110).unwrap();
+            let log = &block.transactions[0].logs[0];
+            (log.topics.clone(), log.data.clone())
+        };
+        chain.mine_to(115);
+        chain
+            .block_mut(115)
+            .unwrap()
+            .log(Address::repeat_byte(0x99), topics, data);
+        emit(
+            &mut chain,
+            120,
+            "LoanRepaid",
+            vec![
+                uint(1),
+                Token::Address(borrower()),
+                uint(1050),
+                uint(1_700_000_120),
+            ],
+        );
+        chain.mine_to(130);
+        chain.block(105).unwrap().transactions[0].hash
+    };
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow();
+    indexer.wait_for_block(130).await;
+
+    let events = indexer.events();
+    let names: Vec<&str> = events.iter().map(|e| e.event_name.as_str()).collect();
+    assert_eq!(names, ["LoanCreated", "LoanAccepted", "LoanRepaid"]);
+
+    let created = &events[0];
+    assert_eq!(created.block_number, 105);
+    assert_eq!(created.block_timestamp, GENESIS_TIMESTAMP + 105);
+    assert_eq!(created.transaction_hash, format!("0x{:x}", created_tx));
+    let decoded = created.decoded_data.as_ref().unwrap();
+    assert_eq!(decoded["loanId"], "1");
+    assert_eq!(decoded["lender"], format!("0x{:x}", lender()));
+    assert_eq!(decoded["amount"], "1000");
+    assert_eq!(decoded["maxPriceStaleness"], "3600");
+
+    let repaid = events[2].decoded_data.as_ref().unwrap();
+    assert_eq!(repaid["borrower"], format!("0x{:x}", borrower()));
+    assert_eq!(repaid["repaymentAmount"], "1050");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn splits_ranges_the_node_rejects() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    rpc.limit_log_range(7);
+    {
+        let mut chain = rpc.chain();
+        for (block, loan) in [(103, 1), (117, 2), (149, 3)] {
+            emit(&mut chain, block, "LoanCreated", loan_created(loan));
+        }
+        chain.mine_to(160);
+    }
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow();
+    indexer.wait_for_block(160).await;
+
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2", "3"]);
+    assert!(indexer.gaps().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_and_fills_gaps() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 105, "LoanCreated", loan_created(1));
+        emit(&mut chain, 150, "LoanCreated", loan_created(2));
+        chain.mine_to(160);
+    }
+    // Enough to outlast the startup ABI check, the range itself and the
+    // retry at the end of the historical sync
+    rpc.fail_logs_at(150, 3 * GET_LOGS_ATTEMPTS);
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow();
+    indexer
+        .wait_until("a gap", |indexer| {
+            !indexer.gaps().is_empty() && indexer.checkpoint().is_some_and(|c| !c.caught_up)
+        })
+        .await;
+    let gap = indexer.gaps().iter().next().cloned().unwrap();
+    assert!(gap.from_block <= 150 && 150 <= gap.to_block);
+    assert_eq!(loan_ids(&indexer.events()), ["1"]);
+
+    // A restart retries the gap straight away
+    indexer.stop();
+    indexer.follow();
+    indexer.wait_for_block(160).await;
+
+    assert!(indexer.gaps().is_empty());
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2"]);
+
+    // A gap while following is no longer caught up either
+    rpc.fail_logs_at(170, GET_LOGS_ATTEMPTS);
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 170, "LoanCreated", loan_created(3));
+        chain.mine_to(175);
+    }
+    indexer
+        .wait_until("a gap while following", |indexer| {
+            !indexer.gaps().is_empty() && indexer.checkpoint().unwrap().current_block > 170
+        })
+        .await;
+    assert!(!indexer.checkpoint().unwrap().caught_up);
+
+    indexer.stop();
+    indexer.follow();
+    indexer.wait_for_block(175).await;
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2", "3"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rolls_back_reorged_blocks() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 110, "LoanCreated", loan_created(1));
+        chain.mine_to(140);
+    }
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow();
+    indexer.wait_for_block(140).await;
+
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 143, "LoanCreated", loan_created(2));
+        chain.mine_to(145);
+    }
+    indexer.wait_for_block(145).await;
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2"]);
+
+    // The new fork drops loan 2 and creates loan 3 instead
+    {
+        let mut chain = rpc.chain();
+        chain.reorg(141);
+        emit(&mut chain, 142, "LoanCreated", loan_created(3));
+        chain.mine_to(147);
+    }
+    indexer.wait_for_block(147).await;
+    assert_eq!(loan_ids(&indexer.events()), ["1", "3"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resumes_a_rollback_cut_short() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 110, "LoanCreated", loan_created(1));
+        emit(&mut chain, 143, "LoanCreated", loan_created(2));
+        chain.mine_to(145);
+    }
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow();
+    indexer.wait_for_block(145).await;
+    indexer.stop();
+
+    // A rollback to block 140 that crashed after rewinding the checkpoint,
+    // before truncating the segments
+    Checkpoint::new(141, true)
+        .save(indexer.output_dir.path())
+        .unwrap();
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2"]);
+    {
+        let mut chain = rpc.chain();
+        chain.reorg(141);
+        emit(&mut chain, 142, "LoanCreated", loan_created(3));
+        chain.mine_to(147);
+    }
+
+    indexer.follow();
+    indexer.wait_for_block(147).await;
+    assert_eq!(loan_ids(&indexer.events()), ["1", "3"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn backfill_replaces_a_range() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 105, "LoanCreated", loan_created(1));
+        emit(&mut chain, 112, "LoanCreated", loan_created(3));
+        chain.mine_to(120);
+    }
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow();
+    indexer.wait_for_block(120).await;
+    indexer.stop();
+
+    // A log the node left out the first time round
+    emit(&mut rpc.chain(), 107, "LoanCreated", loan_created(2));
+
+    assert!(indexer
+        .run(&["backfill", "--from", "100", "--to", "115"])
+        .success());
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2", "3"]);
+    assert_eq!(indexer.checkpoint().unwrap().current_block, 121);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reindex_replaces_a_loan() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 105, "LoanCreated", loan_created(1));
+        emit(&mut chain, 108, "LoanCreated", loan_created(2));
+        chain.mine_to(120);
+    }
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow();
+    indexer.wait_for_block(120).await;
+    indexer.stop();
+
+    // Logs the node left out the first time round: a request with the same
+    // id, one that does not decode and a repayment
+    {
+        let mut chain = rpc.chain();
+        emit(
+            &mut chain,
+            106,
+            "LoanRequestCreated",
+            vec![
+                uint(1),
+                Token::Address(borrower()),
+                Token::Address(token()),
+                uint(1000),
+                uint(500),
+                uint(86400),
+                Token::Address(token()),
+                uint(2000),
+                uint(15000),
+                uint(12000),
+                uint(3600),
+            ],
+        );
+        let accepted = neurolend_abi().event("LoanAccepted").unwrap().signature();
+        chain.block_mut(107).unwrap().log(
+            contract(),
+            vec![accepted, H256::from_low_u64_be(1), borrower().into()],
+            vec![0u8; 8],
+        );
+        emit(
+            &mut chain,
+            110,
+            "LoanRepaid",
+            vec![
+                uint(1),
+                Token::Address(borrower()),
+                uint(1050),
+                uint(1_700_000_110),
+            ],
+        );
+    }
+
+    assert!(indexer.run(&["reindex", "--loan", "1"]).success());
+    // The open segment is in the order records were appended
+    let mut events = indexer.events();
+    events.sort_by_key(|e| (e.block_number, e.log_index));
+    let stored: Vec<(&str, u64)> = events
+        .iter()
+        .map(|e| (e.event_name.as_str(), e.block_number))
+        .collect();
+    assert_eq!(
+        stored,
+        [
+            ("LoanCreated", 105),
+            ("LoanRequestCreated", 106),
+            ("LoanAccepted", 107),
+            ("LoanCreated", 108),
+            ("LoanRepaid", 110),
+        ]
+    );
+    assert!(events[2].decoded_data.is_none());
+    assert_eq!(events[2].topics.len(), 3);
+
+    // Running it again replaces rather than duplicates
+    assert!(indexer.run(&["reindex", "--loan", "1"]).success());
+    assert_eq!(indexer.events().len(), 5);
+}
+
+/// A new database on the server `DATABASE_URL` points at, as a URL and the
+/// name to drop it by. Postgres tests are skipped without `DATABASE_URL`.
+async fn test_database() -> Option<(String, String)> {
+    let server = std::env::var("DATABASE_URL").ok()?;
+    let name = format!(
+        "neurolend_test_{}_{}",
+        std::process::id(),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos()
+    );
+    let pool = PgPool::connect(&server).await.unwrap();
+    sqlx::query(&format!("CREATE DATABASE {}", name))
+        .execute(&pool)
+        .await
+        .unwrap();
+    pool.close().await;
+
+    let separator = if server.contains('?') { '&' } else { '?' };
+    Some((format!("{}{}dbname={}", server, separator, name), name))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stores_undecoded_events_in_postgres() {
+    let Some((database_url, name)) = test_database().await else {
+        eprintln!("DATABASE_URL is not set, skipping");
+        return;
+    };
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 105, "LoanCreated", loan_created(1));
+        chain.mine_to(107);
+        let accepted = neurolend_abi().event("LoanAccepted").unwrap().signature();
+        chain.block_mut(107).unwrap().log(
+            contract(),
+            vec![accepted, H256::from_low_u64_be(1), borrower().into()],
+            vec![0u8; 8],
+        );
+        emit(
+            &mut chain,
+            110,
+            "LoanRepaid",
+            vec![
+                uint(1),
+                Token::Address(borrower()),
+                uint(1050),
+                uint(1_700_000_110),
+            ],
+        );
+        chain.mine_to(130);
+    }
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow_with(&["--sink", "postgres", "--database-url", &database_url]);
+    let sink = PostgresSink::connect(&database_url, contract(), false, false)
+        .await
+        .unwrap();
+    let deadline = Instant::now() + TIMEOUT;
+    while !sink
+        .load_checkpoint()
+        .await
+        .unwrap()
+        .is_some_and(|c| c.caught_up && c.current_block > 130)
+    {
+        assert!(Instant::now() < deadline, "indexer did not reach block 130");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+    indexer.stop();
+
+    let check = |events: Vec<IndexedEvent>| {
+        let stored: Vec<(&str, u64)> = events
+            .iter()
+            .map(|e| (e.event_name.as_str(), e.block_number))
+            .collect();
+        assert_eq!(
+            stored,
+            [
+                ("LoanCreated", 105),
+                ("LoanAccepted", 107),
+                ("LoanRepaid", 110)
+            ]
+        );
+        let created = events[0].decoded_data.as_ref().unwrap();
+        assert_eq!(created["lender"], format!("0x{:x}", lender()));
+        assert_eq!(created["amount"], "1000");
+        assert!(events[1].decoded_data.is_none());
+        assert_eq!(events[1].topics.len(), 3);
+        assert_eq!(events[1].data, format!("0x{}", "00".repeat(8)));
+    };
+    check(sink.load_events().await.unwrap());
+
+    // Reindexing the loan replaces the raw row along with the decoded ones
+    let args = ["--sink", "postgres", "--database-url", &database_url];
+    assert!(indexer
+        .run(&[&["reindex", "--loan", "1"], &args[..]].concat())
+        .success());
+    check(sink.load_events().await.unwrap());
+    sink.close().await;
+
+    let pool = PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    sqlx::query(&format!("DROP DATABASE {} WITH (FORCE)", name))
+        .execute(&pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn retries_failed_polls() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 110, "LoanCreated", loan_created(1));
+        chain.mine_to(120);
+    }
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow();
+    indexer.wait_for_block(120).await;
+
+    rpc.fail("eth_blockNumber", 2);
+    emit(&mut rpc.chain(), 123, "LoanCreated", loan_created(2));
+    indexer.wait_for_block(123).await;
+
+    assert_eq!(rpc.chain().head(), 123);
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2"]);
+}
+
+/// Nodes serving the same chain, built by `build`
+async fn nodes(count: usize, build: impl Fn(&mut Chain)) -> Vec<MockRpc> {
+    let mut nodes = Vec::new();
+    for _ in 0..count {
+        let rpc = MockRpc::start(START).await.unwrap();
+        build(&mut rpc.chain());
+        nodes.push(rpc);
+    }
+    nodes
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn fails_over_from_a_broken_endpoint() {
+    let nodes = nodes(2, |chain| {
+        emit(chain, 105, "LoanCreated", loan_created(1));
+        emit(chain, 150, "LoanCreated", loan_created(2));
+        chain.mine_to(160);
+    })
+    .await;
+    let (broken, healthy) = (&nodes[0], &nodes[1]);
+    broken.rate_limit(0);
+
+    let mut indexer = Indexer::new(broken);
+    indexer.follow_with(&["--rpc-url", &healthy.url()]);
+    indexer.wait_for_block(160).await;
+
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2"]);
+    assert!(indexer.gaps().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_a_gap_when_endpoints_disagree() {
+    let nodes = nodes(2, |chain| {
+        emit(chain, 105, "LoanCreated", loan_created(1));
+        chain.mine_to(130);
+    })
+    .await;
+    // Only one of them has loan 2
+    emit(&mut nodes[0].chain(), 110, "LoanCreated", loan_created(2));
+
+    let mut indexer = Indexer::new(&nodes[0]);
+    indexer.follow_with(&["--rpc-url", &nodes[1].url()]);
+    indexer
+        .wait_until("a gap", |indexer| {
+            !indexer.gaps().is_empty()
+                && indexer.checkpoint().is_some_and(|c| c.current_block > 130)
+        })
+        .await;
+    let gap = indexer.gaps().iter().next().cloned().unwrap();
+    assert!(gap.from_block <= 110 && 110 <= gap.to_block);
+    assert!(gap.last_error.contains("returned different logs"));
+    assert!(indexer.events().is_empty());
+
+    // Once they agree the gap is filled
+    indexer.stop();
+    emit(&mut nodes[1].chain(), 110, "LoanCreated", loan_created(2));
+    indexer.follow_with(&["--rpc-url", &nodes[1].url()]);
+    indexer.wait_for_block(130).await;
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2"]);
+    assert!(indexer.gaps().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn accepts_logs_from_an_endpoint_behind() {
+    let nodes = nodes(2, |chain| {
+        emit(chain, 105, "LoanCreated", loan_created(1));
+        chain.mine_to(150);
+    })
+    .await;
+    {
+        let mut chain = nodes[0].chain();
+        emit(&mut chain, 155, "LoanCreated", loan_created(2));
+        chain.mine_to(160);
+    }
+
+    let filter = Filter::new()
+        .address(contract())
+        .from_block(START)
+        .to_block(160);
+    // Either one asked first
+    for urls in [
+        [nodes[0].url(), nodes[1].url()],
+        [nodes[1].url(), nodes[0].url()],
+    ] {
+        let provider = RpcPool::new(&urls).unwrap().provider();
+        let logs = provider.get_logs(&filter).await.unwrap();
+        let blocks: Vec<u64> = logs
+            .iter()
+            .map(|log| log.block_number.unwrap().as_u64())
+            .collect();
+        assert_eq!(blocks, [105, 155]);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn serves_metrics() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 105, "LoanCreated", loan_created(1));
+        emit(&mut chain, 110, "LoanCreated", loan_created(2));
+        chain.mine_to(130);
+    }
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow_with(&["--metrics-addr", &addr]);
+    indexer.wait_for_block(130).await;
+
+    let metrics = reqwest::get(format!("http://{}/metrics", addr))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    for line in [
+        "neurolend_indexer_head_block 130",
+        "neurolend_indexer_last_indexed_block 130",
+        "neurolend_indexer_lag_blocks 0",
+        "neurolend_indexer_logs_total{event=\"LoanCreated\"} 2",
+        "neurolend_indexer_open_gaps 0",
+    ] {
+        assert!(metrics.lines().any(|l| l == line), "{}", metrics);
+    }
+    let endpoint = rpc.url().trim_end_matches('/').to_string();
+    assert!(
+        metrics.contains(&format!(
+            "neurolend_indexer_rpc_requests_total{{endpoint=\"{}\"}}",
+            endpoint
+        )),
+        "{}",
+        metrics
+    );
+
+    let with_query = reqwest::get(format!("http://{}/metrics?x=1", addr))
+        .await
+        .unwrap();
+    assert_eq!(with_query.status(), 200);
+    let missing = reqwest::get(format!("http://{}/", addr)).await.unwrap();
+    assert_eq!(missing.status(), 404);
+
+    // A client that never finishes its request is cut off rather than
+    // held open, and an endless request line is not read into memory
+    let mut stalled = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    stalled
+        .write_all(b"GET /metrics HTTP/1.1\r\n")
+        .await
+        .unwrap();
+    let mut flood = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let junk = vec![b'a'; 64 * 1024];
+    let _ = flood.write_all(&junk).await;
+    let mut response = Vec::new();
+    let read = tokio::time::timeout(TIMEOUT, flood.read_to_end(&mut response)).await;
+    assert!(read.is_ok(), "a flooding client is still connected");
+    let closed = tokio::time::timeout(TIMEOUT, stalled.read_to_end(&mut Vec::new())).await;
+    assert!(closed.is_ok(), "a stalled client is still connected");
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread")]
+async fn stops_cleanly_on_sigterm() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 110, "LoanCreated", loan_created(1));
+        chain.mine_to(140);
+    }
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow();
+    indexer.wait_for_block(140).await;
+
+    assert!(indexer.terminate().await.success());
+    assert_eq!(indexer.checkpoint().unwrap().current_block, 141);
+
+    // Nothing was left for the next start to clean up
+    emit(&mut rpc.chain(), 142, "LoanCreated", loan_created(2));
+    indexer.follow();
+    indexer.wait_for_block(142).await;
+    assert_eq!(loan_ids(&indexer.events()), ["1", "2"]);
+    let log = fs::read_to_string(indexer.log_file()).unwrap();
+    assert!(!log.contains("Discard"), "{}", log);
+}
+
+/// The length-delimited messages in `protobuf/<kind>_<range>.binpb` for
+/// the first segment, none if there is no such file
+fn protobuf<M: prost::Message + Default>(indexer: &Indexer, kind: &str) -> Vec<M> {
+    let file = format!(
+        "{}_{:010}_{:010}.binpb",
+        kind,
+        0,
+        DEFAULT_SEGMENT_BLOCKS - 1
+    );
+    let Ok(content) = fs::read(indexer.output_dir.path().join("protobuf").join(file)) else {
+        return Vec::new();
+    };
+    let mut buf = content.as_slice();
+    let mut messages = Vec::new();
+    while !buf.is_empty() {
+        messages.push(M::decode_length_delimited(&mut buf).unwrap());
+    }
+    messages
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn writes_contract_v1_protobuf() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    let repay = |loan| {
+        neurolend_abi()
+            .function("repayLoan")
+            .unwrap()
+            .encode_input(&[uint(loan)])
+            .unwrap()
+    };
+    let created_tx = {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 105, "LoanCreated", loan_created(1));
+        chain.mine_to(110);
+        chain
+            .block_mut(110)
+            .unwrap()
+            .transaction(borrower(), contract(), repay(1));
+        chain.mine_to(140);
+        chain.block(105).unwrap().transactions[0].hash
+    };
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow_with(&["--protobuf", "--index-calls"]);
+    indexer.wait_for_block(140).await;
+
+    let events: Vec<contract::Events> = protobuf(&indexer, "events");
+    assert_eq!(events.len(), 1);
+    let created = &events[0].neurolend_loan_createds[0];
+    assert_eq!(created.evt_tx_hash, format!("{:x}", created_tx));
+    assert_eq!(created.evt_block_number, 105);
+    assert_eq!(
+        created.evt_block_time.as_ref().unwrap().seconds as u64,
+        GENESIS_TIMESTAMP + 105
+    );
+    assert_eq!(created.loan_id, "1");
+    assert_eq!(created.lender, lender().as_bytes());
+    assert_eq!(created.max_price_staleness, "3600");
+
+    // The transactions the events came in carry no calldata, so only the
+    // repayment decodes
+    let calls: Vec<contract::Calls> = protobuf(&indexer, "calls");
+    assert_eq!(calls.len(), 1);
+    let repaid = &calls[0].neurolend_call_repay_loans[0];
+    assert_eq!(repaid.call_block_number, 110);
+    assert_eq!(repaid.loan_id, "1");
+    assert!(repaid.call_success);
+
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 143, "LoanCreated", loan_created(2));
+        chain.mine_to(145);
+    }
+    indexer.wait_for_block(145).await;
+    assert_eq!(protobuf::<contract::Events>(&indexer, "events").len(), 2);
+
+    // A rollback rewrites the files too
+    {
+        let mut chain = rpc.chain();
+        chain.reorg(141);
+        chain.mine_to(147);
+    }
+    indexer.wait_for_block(147).await;
+    let events: Vec<contract::Events> = protobuf(&indexer, "events");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].neurolend_loan_createds[0].loan_id, "1");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn indexes_reverted_calls() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    let repay = neurolend_abi()
+        .function("repayLoan")
+        .unwrap()
+        .encode_input(&[uint(1)])
+        .unwrap();
+    let reverted_tx = {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 105, "LoanCreated", loan_created(1));
+        chain.mine_to(110);
+        let block = chain.block_mut(110).unwrap();
+        block.transaction(borrower(), Address::repeat_byte(0x99), repay.clone());
+        block
+            .transaction(borrower(), contract(), repay)
+            .revert()
+            .hash
+    };
+    rpc.chain().mine_to(130);
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow_with(&["--index-calls"]);
+    indexer.wait_for_block(130).await;
+
+    // The reverted call left no event, and the call to another contract
+    // is not ours
+    assert_eq!(loan_ids(&indexer.events()), ["1"]);
+    let calls: Vec<IndexedCall> = indexer.records(CALLS_DIR);
+    assert_eq!(calls.len(), 2);
+    let reverted = &calls[1];
+    assert_eq!(reverted.function_name, "repayLoan");
+    assert_eq!(reverted.transaction_hash, format!("0x{:x}", reverted_tx));
+    assert_eq!(reverted.block_number, 110);
+    assert_eq!(reverted.transaction_index, 1);
+    assert_eq!(reverted.from_address, format!("0x{:x}", borrower()));
+    assert!(!reverted.success);
+    assert_eq!(reverted.decoded_input.as_ref().unwrap()["loanId"], "1");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn links_token_transfers_to_loans() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    let transfer = |from: Address, to: Address, value: u64| {
+        (
+            vec![transfer_topic(), H256::from(from), H256::from(to)],
+            abi::encode(&[uint(value)]),
+        )
+    };
+    {
+        let mut chain = rpc.chain();
+        // The lender funds the offer in the transaction creating it
+        chain.mine_to(105);
+        let (topics, data) = transfer(lender(), contract(), 1000);
+        let (event_topics, event_data) = event_log("LoanCreated", loan_created(1));
+        chain
+            .block_mut(105)
+            .unwrap()
+            .transaction(lender(), contract(), Bytes::new())
+            .log(token(), topics, data)
+            .log(contract(), event_topics, event_data);
+
+        // Moving tokens between the contract's own balances matches both
+        // the from and the to filter
+        chain.mine_to(110);
+        let (topics, data) = transfer(contract(), contract(), 50);
+        let (event_topics, event_data) = event_log(
+            "LoanAccepted",
+            vec![
+                uint(1),
+                Token::Address(borrower()),
+                uint(1_700_000_110),
+                uint(20000),
+            ],
+        );
+        chain
+            .block_mut(110)
+            .unwrap()
+            .transaction(borrower(), contract(), Bytes::new())
+            .log(contract(), event_topics, event_data)
+            .log(token(), topics, data);
+
+        // Neither side is the contract
+        chain.mine_to(115);
+        let (topics, data) = transfer(lender(), borrower(), 7);
+        chain.block_mut(115).unwrap().log(token(), topics, data);
+        chain.mine_to(130);
+    }
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow_with(&["--index-transfers"]);
+    indexer.wait_for_block(130).await;
+
+    let transfers: Vec<IndexedTransfer> = indexer.records(TRANSFERS_DIR);
+    let found: Vec<_> = transfers
+        .iter()
+        .map(|t| {
+            (
+                t.block_number,
+                t.value.as_str(),
+                t.direction.as_str(),
+                t.action.as_deref(),
+                t.loan_id.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (105, "1000", "in", Some("LoanCreated"), Some("1")),
+            (110, "50", "in", Some("LoanAccepted"), Some("1")),
+        ]
+    );
+    assert_eq!(transfers[0].token_address, format!("0x{:x}", token()));
+    assert_eq!(transfers[0].from_address, format!("0x{:x}", lender()));
+    assert_eq!(transfers[0].log_index, 0);
+    assert_eq!(transfers[1].log_index, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn snapshots_active_loans() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    {
+        let mut chain = rpc.chain();
+        emit(&mut chain, 105, "LoanCreated", loan_created(1));
+        emit(
+            &mut chain,
+            112,
+            "LoanAccepted",
+            vec![
+                uint(1),
+                Token::Address(borrower()),
+                uint(1_700_000_112),
+                uint(20000),
+            ],
+        );
+        chain.mine_to(130);
+    }
+    let abi = neurolend_abi();
+    let selector = |name: &str| abi.function(name).unwrap().short_signature();
+    let (get_loan, health, defaulted, price) = (
+        selector("getLoan"),
+        selector("getLoanHealthFactor"),
+        selector("isLoanDefaulted"),
+        selector("getCurrentPrice"),
+    );
+    rpc.on_call(move |call| {
+        let output = if call.data.starts_with(&get_loan) {
+            let mut fields = vec![uint(0); 15];
+            fields[0] = uint(1);
+            fields[3] = Token::Address(token());
+            fields[7] = Token::Address(token());
+            fields[10] = uint(1);
+            fields[13] = uint(3600);
+            vec![Token::Tuple(fields)]
+        } else if call.data.starts_with(&health) {
+            vec![uint(15000 + call.block), Token::Bool(false)]
+        } else if call.data.starts_with(&defaulted) {
+            vec![Token::Bool(false)]
+        } else if call.data.starts_with(&price) {
+            vec![uint(2_000_000), Token::Bool(false)]
+        } else {
+            return Err("execution reverted".to_string());
+        };
+        Ok(abi::encode(&output).into())
+    });
+
+    let mut indexer = Indexer::new(&rpc);
+    indexer.follow_with(&["--snapshot-interval", "10"]);
+    indexer.wait_for_block(130).await;
+
+    // Block 110 is a snapshot block too, but the loan was not active yet
+    let snapshots: Vec<Snapshot> = indexer.records(SNAPSHOTS_DIR);
+    let blocks: Vec<u64> = snapshots.iter().map(|s| s.block_number).collect();
+    assert_eq!(blocks, [120, 130]);
+
+    let first = &snapshots[0];
+    assert_eq!(first.block_timestamp, GENESIS_TIMESTAMP + 120);
+    assert_eq!(first.loans.len(), 1);
+    let loan = &first.loans[0];
+    assert_eq!(loan.loan_id, "1");
+    assert_eq!(loan.health_factor.as_deref(), Some("15120"));
+    assert_eq!(loan.price_stale, Some(false));
+    assert_eq!(loan.defaulted, Some(false));
+    assert_eq!(loan.token_address, Some(format!("0x{:x}", token())));
+    assert_eq!(loan.token_price.as_deref(), Some("2000000"));
+    assert_eq!(loan.collateral_price.as_deref(), Some("2000000"));
+    // Views the node does not serve are left out rather than failing the
+    // snapshot
+    assert_eq!(loan.current_interest, None);
+}
+
+/// Run `doctor` with `args`, returning whether it passed and its report by
+/// check name (and endpoint, for per-endpoint checks)
+fn doctor(indexer: &Indexer, args: &[&str]) -> (bool, Value) {
+    let export = indexer.output_dir.path().join("doctor.json");
+    let export = export.to_str().unwrap();
+    let passed = indexer
+        .run(&[&["doctor", "--export", export], args].concat())
+        .success();
+    let report: Value = serde_json::from_str(&fs::read_to_string(export).unwrap()).unwrap();
+    (passed, report)
+}
+
+fn check<'a>(report: &'a Value, name: &str) -> &'a Value {
+    report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|c| c["name"] == name)
+        .unwrap_or_else(|| panic!("no {} check in {}", name, report))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn doctor_checks_the_node() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    let code = Bytes::from(vec![0x60, 0x80, 0x60, 0x40]);
+    {
+        let mut chain = rpc.chain();
+        chain.set_code(contract(), code.clone());
+        emit(&mut chain, 150, "LoanCreated", loan_created(1));
+        chain.mine_to(2200);
+    }
+    let owner_selector = neurolend_abi().function("owner").unwrap().short_signature();
+    rpc.on_call(move |call| {
+        if call.data.starts_with(&owner_selector) {
+            Ok(abi::encode(&[Token::Address(lender())]).into())
+        } else {
+            Err("execution reverted".to_string())
+        }
+    });
+    rpc.limit_log_range(1500);
+    rpc.prune_state_before(1000);
+
+    let indexer = Indexer::new(&rpc);
+    let (passed, report) = doctor(&indexer, &[]);
+    assert!(passed, "{}", report);
+    assert_eq!(report["headBlock"], 2200);
+
+    let endpoint = check(&report, "endpoint");
+    assert_eq!(endpoint["status"], "ok");
+    assert_eq!(endpoint["details"]["chainId"], 16661);
+    let range = check(&report, "logRange");
+    assert_eq!(range["status"], "ok");
+    assert_eq!(range["details"]["maxBlocks"], 1000);
+    assert_eq!(range["details"]["rejectedBlocks"], 2000);
+    assert_eq!(check(&report, "rateLimit")["status"], "ok");
+    let code_hash = format!("0x{:x}", H256(keccak256(&code)));
+    assert_eq!(
+        check(&report, "contractCode")["details"]["codeHash"],
+        code_hash
+    );
+    assert_eq!(check(&report, "codeHash")["status"], "skipped");
+    assert_eq!(
+        check(&report, "owner")["details"]["owner"],
+        format!("0x{:x}", lender())
+    );
+    assert_eq!(check(&report, "recentLogs")["details"]["logs"], 0);
+    assert_eq!(check(&report, "archive")["status"], "warning");
+    assert_eq!(check(&report, "storage")["status"], "ok");
+
+    // A pinned hash of another build fails, as does a wrong network
+    let (passed, report) = doctor(
+        &indexer,
+        &[
+            "--code-hash",
+            &format!("0x{:x}", H256::zero()),
+            "--chain-id",
+            "1",
+        ],
+    );
+    assert!(!passed);
+    assert_eq!(check(&report, "codeHash")["status"], "failed");
+    assert_eq!(check(&report, "endpoint")["status"], "failed");
+
+    let (_, report) = doctor(&indexer, &["--code-hash", &code_hash]);
+    assert_eq!(check(&report, "codeHash")["status"], "ok");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn doctor_finds_rate_limits() {
+    let rpc = MockRpc::start(START).await.unwrap();
+    rpc.chain().mine_to(START);
+    rpc.rate_limit(20);
+
+    let indexer = Indexer::new(&rpc);
+    let (passed, report) = doctor(&indexer, &[]);
+    assert!(!passed, "no contract is deployed");
+    let rate = check(&report, "rateLimit");
+    assert_eq!(rate["status"], "warning", "{}", report);
+    assert!(rate["details"]["rateLimited"].as_u64().unwrap() > 0);
+}
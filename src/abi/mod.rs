@@ -1,2 +1,2 @@
-
+#[allow(clippy::all)]
 pub mod neurolend_contract;
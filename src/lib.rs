@@ -1,5 +1,7 @@
 mod abi;
 mod pb;
+#[cfg(test)]
+mod tests;
 use hex_literal::hex;
 use pb::contract::v1 as contract;
 use substreams::Hex;
@@ -8,8 +10,6 @@ use substreams_ethereum::Event;
 
 #[allow(unused_imports)]
 use num_traits::cast::ToPrimitive;
-use std::str::FromStr;
-use substreams::scalar::BigDecimal;
 
 substreams_ethereum::init!();
 
@@ -627,7 +627,7 @@ fn map_generic_events(blk: &eth::Block, events: &mut contract::Events) {
                     .filter(|log| {
                         // Skip events we already handle specifically
                         log.address != NEUROLEND_TRACKED_CONTRACT &&
-                        !(log.topics.len() >= 1 && 
+                        !(!log.topics.is_empty() && 
                           (log.topics[0] == ERC20_TRANSFER_EVENT_SIG || 
                            log.topics[0] == ERC20_APPROVAL_EVENT_SIG))
                     })
@@ -700,7 +700,6 @@ fn map_neurolend_calls(blk: &eth::Block, calls: &mut contract::Calls) {
                                 price_update: decoded_call
                                     .price_update
                                     .into_iter()
-                                    .map(|x| x)
                                     .collect::<Vec<_>>(),
                             }),
                             Err(_) => None,
@@ -988,7 +987,6 @@ fn map_neurolend_calls(blk: &eth::Block, calls: &mut contract::Calls) {
                                 price_update: decoded_call
                                     .price_update
                                     .into_iter()
-                                    .map(|x| x)
                                     .collect::<Vec<_>>(),
                             }),
                             Err(_) => None,
@@ -1045,7 +1043,6 @@ fn map_neurolend_calls(blk: &eth::Block, calls: &mut contract::Calls) {
                                 price_update: decoded_call
                                     .price_update
                                     .into_iter()
-                                    .map(|x| x)
                                     .collect::<Vec<_>>(),
                             }),
                             Err(_) => None,
@@ -1108,7 +1105,6 @@ fn map_neurolend_calls(blk: &eth::Block, calls: &mut contract::Calls) {
                                 price_update: decoded_call
                                     .price_update
                                     .into_iter()
-                                    .map(|x| x)
                                     .collect::<Vec<_>>(),
                                 remove_amount: decoded_call.remove_amount.to_string(),
                             }),
@@ -1132,7 +1128,7 @@ fn map_neurolend_calls(blk: &eth::Block, calls: &mut contract::Calls) {
                     })
                     .filter_map(|call| {
                         match abi::neurolend_contract::functions::RenounceOwnership::decode(call) {
-                            Ok(decoded_call) => Some(contract::NeurolendRenounceOwnershipCall {
+                            Ok(_decoded_call) => Some(contract::NeurolendRenounceOwnershipCall {
                                 call_tx_hash: Hex(&tx.hash).to_string(),
                                 call_block_time: Some(blk.timestamp().to_owned()),
                                 call_block_number: blk.number,
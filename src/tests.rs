@@ -0,0 +1,1181 @@
+//! Decoding tests for `map_events` and `map_calls`.
+//!
+//! Every fixture is built from synthetic `eth::Block`s whose logs and call
+//! inputs are ABI-encoded from the canonical Solidity signatures, so a drift
+//! in the generated bindings (for example the `u_` sanitization done in
+//! `build.rs`) shows up as a decoding failure here.
+
+use super::*;
+use ethabi::param_type::Reader;
+use ethabi::{Token, Uint};
+use prost_types::Timestamp;
+
+const BLOCK_NUMBER: u64 = 6914400;
+const BLOCK_SECONDS: i64 = 1727000000;
+const TX_HASH: [u8; 32] = [0xab; 32];
+const TX_HASH_HEX: &str = "abababababababababababababababababababababababababababababababab";
+
+const LENDER: [u8; 20] = hex!("1111111111111111111111111111111111111111");
+const BORROWER: [u8; 20] = hex!("2222222222222222222222222222222222222222");
+const TOKEN: [u8; 20] = hex!("3333333333333333333333333333333333333333");
+const COLLATERAL: [u8; 20] = hex!("4444444444444444444444444444444444444444");
+const OTHER_CONTRACT: [u8; 20] = hex!("5555555555555555555555555555555555555555");
+const FEED_ID: [u8; 32] = [0x42; 32];
+
+fn signature_hash(signature: &str) -> [u8; 32] {
+    let (name, params) = signature
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .expect("signature must look like Name(type,...)");
+    let params = params
+        .split(',')
+        .filter(|p| !p.is_empty())
+        .map(|p| Reader::read(p).expect("valid solidity type"))
+        .collect::<Vec<_>>();
+    ethabi::long_signature(name, &params).0
+}
+
+fn uint(value: u64) -> Token {
+    Token::Uint(Uint::from(value))
+}
+
+fn address(value: [u8; 20]) -> Token {
+    Token::Address(value.into())
+}
+
+fn price_update() -> Token {
+    Token::Array(vec![
+        Token::Bytes(vec![0xde, 0xad]),
+        Token::Bytes(vec![0xbe, 0xef, 0x01]),
+    ])
+}
+
+fn block_time() -> Option<Timestamp> {
+    Some(Timestamp {
+        seconds: BLOCK_SECONDS,
+        nanos: 0,
+    })
+}
+
+fn log(address: [u8; 20], signature: &str, indexed: &[Token], data: &[Token]) -> eth::Log {
+    let mut topics = vec![signature_hash(signature).to_vec()];
+    topics.extend(indexed.iter().map(|t| ethabi::encode(std::slice::from_ref(t))));
+
+    eth::Log {
+        address: address.to_vec(),
+        topics,
+        data: ethabi::encode(data),
+        ..Default::default()
+    }
+}
+
+fn neurolend_log(signature: &str, indexed: &[Token], data: &[Token]) -> eth::Log {
+    log(NEUROLEND_TRACKED_CONTRACT, signature, indexed, data)
+}
+
+fn neurolend_call(signature: &str, args: &[Token]) -> eth::Call {
+    let mut input = signature_hash(signature)[..4].to_vec();
+    input.extend(ethabi::encode(args));
+
+    eth::Call {
+        address: NEUROLEND_TRACKED_CONTRACT.to_vec(),
+        input,
+        begin_ordinal: 7,
+        ..Default::default()
+    }
+}
+
+fn transaction(
+    status: eth::TransactionTraceStatus,
+    logs: Vec<eth::Log>,
+    calls: Vec<eth::Call>,
+) -> eth::TransactionTrace {
+    eth::TransactionTrace {
+        hash: TX_HASH.to_vec(),
+        status: status as i32,
+        receipt: Some(eth::TransactionReceipt {
+            logs,
+            ..Default::default()
+        }),
+        calls,
+        ..Default::default()
+    }
+}
+
+fn block(transaction_traces: Vec<eth::TransactionTrace>) -> eth::Block {
+    eth::Block {
+        number: BLOCK_NUMBER,
+        header: Some(eth::BlockHeader {
+            number: BLOCK_NUMBER,
+            timestamp: block_time(),
+            ..Default::default()
+        }),
+        transaction_traces,
+        ..Default::default()
+    }
+}
+
+fn block_with_logs(mut logs: Vec<eth::Log>) -> eth::Block {
+    for (index, log) in logs.iter_mut().enumerate() {
+        log.block_index = index as u32;
+    }
+    block(vec![transaction(
+        eth::TransactionTraceStatus::Succeeded,
+        logs,
+        vec![],
+    )])
+}
+
+fn block_with_calls(calls: Vec<eth::Call>) -> eth::Block {
+    block(vec![transaction(
+        eth::TransactionTraceStatus::Succeeded,
+        vec![],
+        calls,
+    )])
+}
+
+/// Mirrors the `map_events` handler, which cannot be called directly once
+/// the `substreams` macro has turned it into a wasm export.
+fn run_map_events(blk: &eth::Block) -> contract::Events {
+    let mut events = contract::Events::default();
+    map_neurolend_events(blk, &mut events);
+    map_erc20_events(blk, &mut events);
+    map_generic_events(blk, &mut events);
+    events
+}
+
+/// Mirrors the `map_calls` handler.
+fn run_map_calls(blk: &eth::Block) -> contract::Calls {
+    let mut calls = contract::Calls::default();
+    map_neurolend_calls(blk, &mut calls);
+    calls
+}
+
+fn events_for(log: eth::Log) -> contract::Events {
+    run_map_events(&block_with_logs(vec![log]))
+}
+
+fn calls_for(call: eth::Call) -> contract::Calls {
+    run_map_calls(&block_with_calls(vec![call]))
+}
+
+#[test]
+fn collateral_added() {
+    let events = events_for(neurolend_log(
+        "CollateralAdded(uint256,address,uint256,uint256,uint256)",
+        &[uint(1), address(BORROWER)],
+        &[uint(500), uint(15000), uint(1700000000)],
+    ));
+
+    assert_eq!(
+        events.neurolend_collateral_addeds,
+        vec![contract::NeurolendCollateralAdded {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "1".to_string(),
+            borrower: BORROWER.to_vec(),
+            amount: "500".to_string(),
+            new_collateral_ratio: "15000".to_string(),
+            timestamp: "1700000000".to_string(),
+        }]
+    );
+    assert!(events.generic_logs.is_empty());
+}
+
+#[test]
+fn collateral_removed() {
+    let events = events_for(neurolend_log(
+        "CollateralRemoved(uint256,address,uint256,uint256,uint256)",
+        &[uint(2), address(BORROWER)],
+        &[uint(250), uint(14000), uint(1700000001)],
+    ));
+
+    assert_eq!(
+        events.neurolend_collateral_removeds,
+        vec![contract::NeurolendCollateralRemoved {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "2".to_string(),
+            borrower: BORROWER.to_vec(),
+            amount: "250".to_string(),
+            new_collateral_ratio: "14000".to_string(),
+            timestamp: "1700000001".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_accepted() {
+    let events = events_for(neurolend_log(
+        "LoanAccepted(uint256,address,uint256,uint256)",
+        &[uint(3), address(BORROWER)],
+        &[uint(1700000002), uint(16000)],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_accepteds,
+        vec![contract::NeurolendLoanAccepted {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "3".to_string(),
+            borrower: BORROWER.to_vec(),
+            timestamp: "1700000002".to_string(),
+            initial_collateral_ratio: "16000".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_created() {
+    let events = events_for(neurolend_log(
+        "LoanCreated(uint256,address,address,uint256,uint256,uint256,address,uint256,uint256,uint256,uint256)",
+        &[uint(4), address(LENDER), address(TOKEN)],
+        &[
+            uint(1000),
+            uint(500),
+            uint(86400),
+            address(COLLATERAL),
+            uint(2000),
+            uint(15000),
+            uint(12000),
+            uint(3600),
+        ],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_createds,
+        vec![contract::NeurolendLoanCreated {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "4".to_string(),
+            lender: LENDER.to_vec(),
+            token_address: TOKEN.to_vec(),
+            amount: "1000".to_string(),
+            interest_rate: "500".to_string(),
+            duration: "86400".to_string(),
+            collateral_address: COLLATERAL.to_vec(),
+            collateral_amount: "2000".to_string(),
+            min_collateral_ratio_bps: "15000".to_string(),
+            liquidation_threshold_bps: "12000".to_string(),
+            max_price_staleness: "3600".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_liquidated() {
+    let events = events_for(neurolend_log(
+        "LoanLiquidated(uint256,address,uint256,uint256,uint256)",
+        &[uint(5), address(LENDER)],
+        &[uint(1900), uint(100), uint(1700000003)],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_liquidateds,
+        vec![contract::NeurolendLoanLiquidated {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "5".to_string(),
+            liquidator: LENDER.to_vec(),
+            collateral_claimed_by_lender: "1900".to_string(),
+            liquidator_reward: "100".to_string(),
+            timestamp: "1700000003".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_matched() {
+    let events = events_for(neurolend_log(
+        "LoanMatched(uint256,uint256,uint256,address,address,uint256,uint256,uint256)",
+        &[uint(6), uint(60), uint(600)],
+        &[
+            address(LENDER),
+            address(BORROWER),
+            uint(1000),
+            uint(450),
+            uint(1700000004),
+        ],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_matcheds,
+        vec![contract::NeurolendLoanMatched {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "6".to_string(),
+            offer_id: "60".to_string(),
+            request_id: "600".to_string(),
+            lender: LENDER.to_vec(),
+            borrower: BORROWER.to_vec(),
+            amount: "1000".to_string(),
+            interest_rate: "450".to_string(),
+            timestamp: "1700000004".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_offer_cancelled() {
+    let events = events_for(neurolend_log(
+        "LoanOfferCancelled(uint256,address,uint256)",
+        &[uint(7), address(LENDER)],
+        &[uint(1700000005)],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_offer_cancelleds,
+        vec![contract::NeurolendLoanOfferCancelled {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "7".to_string(),
+            lender: LENDER.to_vec(),
+            timestamp: "1700000005".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_offer_removed() {
+    let events = events_for(neurolend_log(
+        "LoanOfferRemoved(uint256,string)",
+        &[uint(8)],
+        &[Token::String("Offer accepted".to_string())],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_offer_removeds,
+        vec![contract::NeurolendLoanOfferRemoved {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "8".to_string(),
+            reason: "Offer accepted".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_repaid() {
+    let events = events_for(neurolend_log(
+        "LoanRepaid(uint256,address,uint256,uint256)",
+        &[uint(9), address(BORROWER)],
+        &[uint(1050), uint(1700000006)],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_repaids,
+        vec![contract::NeurolendLoanRepaid {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "9".to_string(),
+            borrower: BORROWER.to_vec(),
+            repayment_amount: "1050".to_string(),
+            timestamp: "1700000006".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_request_cancelled() {
+    let events = events_for(neurolend_log(
+        "LoanRequestCancelled(uint256,address,uint256)",
+        &[uint(10), address(BORROWER)],
+        &[uint(1700000007)],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_request_cancelleds,
+        vec![contract::NeurolendLoanRequestCancelled {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            request_id: "10".to_string(),
+            borrower: BORROWER.to_vec(),
+            timestamp: "1700000007".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_request_created() {
+    let events = events_for(neurolend_log(
+        "LoanRequestCreated(uint256,address,address,uint256,uint256,uint256,address,uint256,uint256,uint256,uint256)",
+        &[uint(11), address(BORROWER), address(TOKEN)],
+        &[
+            uint(3000),
+            uint(800),
+            uint(172800),
+            address(COLLATERAL),
+            uint(6000),
+            uint(15000),
+            uint(12000),
+            uint(1800),
+        ],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_request_createds,
+        vec![contract::NeurolendLoanRequestCreated {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            request_id: "11".to_string(),
+            borrower: BORROWER.to_vec(),
+            token_address: TOKEN.to_vec(),
+            amount: "3000".to_string(),
+            max_interest_rate: "800".to_string(),
+            duration: "172800".to_string(),
+            collateral_address: COLLATERAL.to_vec(),
+            collateral_amount: "6000".to_string(),
+            min_collateral_ratio_bps: "15000".to_string(),
+            liquidation_threshold_bps: "12000".to_string(),
+            max_price_staleness: "1800".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn loan_request_removed() {
+    let events = events_for(neurolend_log(
+        "LoanRequestRemoved(uint256,string)",
+        &[uint(12)],
+        &[Token::String("Request filled".to_string())],
+    ));
+
+    assert_eq!(
+        events.neurolend_loan_request_removeds,
+        vec![contract::NeurolendLoanRequestRemoved {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            request_id: "12".to_string(),
+            reason: "Request filled".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn ownership_transferred() {
+    let events = events_for(neurolend_log(
+        "OwnershipTransferred(address,address)",
+        &[address(LENDER), address(BORROWER)],
+        &[],
+    ));
+
+    assert_eq!(
+        events.neurolend_ownership_transferreds,
+        vec![contract::NeurolendOwnershipTransferred {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            previous_owner: LENDER.to_vec(),
+            new_owner: BORROWER.to_vec(),
+        }]
+    );
+}
+
+#[test]
+fn partial_repayment() {
+    let events = events_for(neurolend_log(
+        "PartialRepayment(uint256,address,uint256,uint256,uint256,uint256)",
+        &[uint(13), address(BORROWER)],
+        &[uint(200), uint(700), uint(350), uint(1700000008)],
+    ));
+
+    assert_eq!(
+        events.neurolend_partial_repayments,
+        vec![contract::NeurolendPartialRepayment {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "13".to_string(),
+            borrower: BORROWER.to_vec(),
+            repayment_amount: "200".to_string(),
+            total_repaid_amount: "700".to_string(),
+            remaining_amount: "350".to_string(),
+            timestamp: "1700000008".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn price_feed_set() {
+    let events = events_for(neurolend_log(
+        "PriceFeedSet(address,bytes32)",
+        &[address(TOKEN), Token::FixedBytes(FEED_ID.to_vec())],
+        &[],
+    ));
+
+    assert_eq!(
+        events.neurolend_price_feed_sets,
+        vec![contract::NeurolendPriceFeedSet {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            token_address: TOKEN.to_vec(),
+            feed_id: FEED_ID.to_vec(),
+        }]
+    );
+}
+
+#[test]
+fn price_update_paid() {
+    let events = events_for(neurolend_log(
+        "PriceUpdatePaid(uint256,uint256,uint256)",
+        &[uint(14)],
+        &[uint(1), uint(1700000009)],
+    ));
+
+    assert_eq!(
+        events.neurolend_price_update_paids,
+        vec![contract::NeurolendPriceUpdatePaid {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            loan_id: "14".to_string(),
+            update_fee: "1".to_string(),
+            timestamp: "1700000009".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn log_index_follows_block_position() {
+    let events = run_map_events(&block_with_logs(vec![
+        neurolend_log(
+            "LoanRepaid(uint256,address,uint256,uint256)",
+            &[uint(1), address(BORROWER)],
+            &[uint(10), uint(1700000000)],
+        ),
+        neurolend_log(
+            "LoanRepaid(uint256,address,uint256,uint256)",
+            &[uint(2), address(BORROWER)],
+            &[uint(20), uint(1700000000)],
+        ),
+    ]));
+
+    let indexes = events
+        .neurolend_loan_repaids
+        .iter()
+        .map(|e| (e.loan_id.as_str(), e.evt_index))
+        .collect::<Vec<_>>();
+    assert_eq!(indexes, vec![("1", 0), ("2", 1)]);
+}
+
+#[test]
+fn neurolend_event_from_other_contract_is_generic() {
+    let events = events_for(log(
+        OTHER_CONTRACT,
+        "LoanRepaid(uint256,address,uint256,uint256)",
+        &[uint(1), address(BORROWER)],
+        &[uint(10), uint(1700000000)],
+    ));
+
+    assert!(events.neurolend_loan_repaids.is_empty());
+    assert_eq!(events.generic_logs.len(), 1);
+    assert_eq!(events.generic_logs[0].contract_address, OTHER_CONTRACT.to_vec());
+}
+
+#[test]
+fn unknown_neurolend_topic_is_dropped() {
+    let events = events_for(neurolend_log("Unknown(uint256)", &[uint(1)], &[]));
+
+    assert_eq!(events, contract::Events::default());
+}
+
+#[test]
+fn malformed_neurolend_log_is_dropped() {
+    // Right topic, but `timestamp` is missing from the data section.
+    let events = events_for(neurolend_log(
+        "LoanRepaid(uint256,address,uint256,uint256)",
+        &[uint(1), address(BORROWER)],
+        &[uint(10)],
+    ));
+
+    assert!(events.neurolend_loan_repaids.is_empty());
+}
+
+#[test]
+fn erc20_transfer() {
+    let events = events_for(log(
+        TOKEN,
+        "Transfer(address,address,uint256)",
+        &[address(BORROWER), address(NEUROLEND_TRACKED_CONTRACT)],
+        &[uint(1000)],
+    ));
+
+    assert_eq!(
+        events.erc20_transfers,
+        vec![contract::Erc20Transfer {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            contract_address: TOKEN.to_vec(),
+            from: BORROWER.to_vec(),
+            to: NEUROLEND_TRACKED_CONTRACT.to_vec(),
+            value: "1000".to_string(),
+        }]
+    );
+    assert!(events.generic_logs.is_empty());
+}
+
+#[test]
+fn erc20_mint_uses_zero_address() {
+    let events = events_for(log(
+        TOKEN,
+        "Transfer(address,address,uint256)",
+        &[address([0u8; 20]), address(LENDER)],
+        &[uint(5)],
+    ));
+
+    assert_eq!(events.erc20_transfers[0].from, vec![0u8; 20]);
+    assert_eq!(events.erc20_transfers[0].to, LENDER.to_vec());
+}
+
+#[test]
+fn erc20_approval() {
+    let events = events_for(log(
+        TOKEN,
+        "Approval(address,address,uint256)",
+        &[address(LENDER), address(NEUROLEND_TRACKED_CONTRACT)],
+        &[uint(u64::MAX)],
+    ));
+
+    assert_eq!(
+        events.erc20_approvals,
+        vec![contract::Erc20Approval {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            contract_address: TOKEN.to_vec(),
+            owner: LENDER.to_vec(),
+            spender: NEUROLEND_TRACKED_CONTRACT.to_vec(),
+            value: u64::MAX.to_string(),
+        }]
+    );
+}
+
+#[test]
+fn generic_log() {
+    let events = events_for(log(
+        OTHER_CONTRACT,
+        "Sync(uint112,uint112)",
+        &[],
+        &[uint(1), uint(2)],
+    ));
+
+    assert_eq!(
+        events.generic_logs,
+        vec![contract::GenericLog {
+            evt_tx_hash: TX_HASH_HEX.to_string(),
+            evt_index: 0,
+            evt_block_time: block_time(),
+            evt_block_number: BLOCK_NUMBER,
+            contract_address: OTHER_CONTRACT.to_vec(),
+            topics: vec![signature_hash("Sync(uint112,uint112)").to_vec()],
+            data: ethabi::encode(&[uint(1), uint(2)]),
+        }]
+    );
+}
+
+#[test]
+fn failed_transaction_is_skipped() {
+    let blk = block(vec![transaction(
+        eth::TransactionTraceStatus::Failed,
+        vec![neurolend_log(
+            "LoanRepaid(uint256,address,uint256,uint256)",
+            &[uint(1), address(BORROWER)],
+            &[uint(10), uint(1700000000)],
+        )],
+        vec![neurolend_call("repayLoan(uint256)", &[uint(1)])],
+    )]);
+
+    assert_eq!(run_map_events(&blk), contract::Events::default());
+    assert_eq!(run_map_calls(&blk), contract::Calls::default());
+}
+
+#[test]
+fn accept_loan_offer_1() {
+    let calls = calls_for(neurolend_call("acceptLoanOffer(uint256)", &[uint(1)]));
+
+    assert_eq!(
+        calls.neurolend_call_accept_loan_offer_1s,
+        vec![contract::NeurolendAcceptLoanOffer1call {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            loan_id: "1".to_string(),
+        }]
+    );
+    assert!(calls.neurolend_call_accept_loan_offer_2s.is_empty());
+}
+
+#[test]
+fn accept_loan_offer_2() {
+    let calls = calls_for(neurolend_call(
+        "acceptLoanOffer(uint256,bytes[])",
+        &[uint(2), price_update()],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_accept_loan_offer_2s,
+        vec![contract::NeurolendAcceptLoanOffer2call {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            loan_id: "2".to_string(),
+            price_update: vec![vec![0xde, 0xad], vec![0xbe, 0xef, 0x01]],
+        }]
+    );
+    assert!(calls.neurolend_call_accept_loan_offer_1s.is_empty());
+}
+
+#[test]
+fn add_collateral() {
+    let calls = calls_for(neurolend_call(
+        "addCollateral(uint256,uint256)",
+        &[uint(3), uint(400)],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_add_collaterals,
+        vec![contract::NeurolendAddCollateralCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            loan_id: "3".to_string(),
+            additional_amount: "400".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn cancel_loan_offer() {
+    let calls = calls_for(neurolend_call("cancelLoanOffer(uint256)", &[uint(4)]));
+
+    assert_eq!(
+        calls.neurolend_call_cancel_loan_offers,
+        vec![contract::NeurolendCancelLoanOfferCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            loan_id: "4".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn cancel_loan_request() {
+    let calls = calls_for(neurolend_call("cancelLoanRequest(uint256)", &[uint(5)]));
+
+    assert_eq!(
+        calls.neurolend_call_cancel_loan_requests,
+        vec![contract::NeurolendCancelLoanRequestCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            request_id: "5".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn create_loan_offer_1() {
+    let calls = calls_for(neurolend_call(
+        "createLoanOffer(address,uint256,uint256,uint256,address,uint256,uint256,uint256,uint256)",
+        &[
+            address(TOKEN),
+            uint(1000),
+            uint(500),
+            uint(86400),
+            address(COLLATERAL),
+            uint(2000),
+            uint(15000),
+            uint(12000),
+            uint(3600),
+        ],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_create_loan_offer_1s,
+        vec![contract::NeurolendCreateLoanOffer1call {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            u_token_address: TOKEN.to_vec(),
+            u_amount: "1000".to_string(),
+            u_interest_rate: "500".to_string(),
+            u_duration: "86400".to_string(),
+            u_collateral_address: COLLATERAL.to_vec(),
+            u_collateral_amount: "2000".to_string(),
+            u_min_collateral_ratio_bps: "15000".to_string(),
+            u_liquidation_threshold_bps: "12000".to_string(),
+            u_max_price_staleness: "3600".to_string(),
+        }]
+    );
+    assert!(calls.neurolend_call_create_loan_offer_2s.is_empty());
+}
+
+#[test]
+fn create_loan_offer_2() {
+    let calls = calls_for(neurolend_call(
+        "createLoanOffer(address,uint256,uint256,uint256,address,uint256)",
+        &[
+            address(TOKEN),
+            uint(1000),
+            uint(500),
+            uint(86400),
+            address(COLLATERAL),
+            uint(2000),
+        ],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_create_loan_offer_2s,
+        vec![contract::NeurolendCreateLoanOffer2call {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            u_token_address: TOKEN.to_vec(),
+            u_amount: "1000".to_string(),
+            u_interest_rate: "500".to_string(),
+            u_duration: "86400".to_string(),
+            u_collateral_address: COLLATERAL.to_vec(),
+            u_collateral_amount: "2000".to_string(),
+        }]
+    );
+    assert!(calls.neurolend_call_create_loan_offer_1s.is_empty());
+}
+
+#[test]
+fn create_loan_request_1() {
+    let calls = calls_for(neurolend_call(
+        "createLoanRequest(address,uint256,uint256,uint256,address,uint256)",
+        &[
+            address(TOKEN),
+            uint(3000),
+            uint(800),
+            uint(172800),
+            address(COLLATERAL),
+            uint(6000),
+        ],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_create_loan_request_1s,
+        vec![contract::NeurolendCreateLoanRequest1call {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            u_token_address: TOKEN.to_vec(),
+            u_amount: "3000".to_string(),
+            u_max_interest_rate: "800".to_string(),
+            u_duration: "172800".to_string(),
+            u_collateral_address: COLLATERAL.to_vec(),
+            u_collateral_amount: "6000".to_string(),
+        }]
+    );
+    assert!(calls.neurolend_call_create_loan_request_2s.is_empty());
+}
+
+#[test]
+fn create_loan_request_2() {
+    let calls = calls_for(neurolend_call(
+        "createLoanRequest(address,uint256,uint256,uint256,address,uint256,uint256,uint256,uint256)",
+        &[
+            address(TOKEN),
+            uint(3000),
+            uint(800),
+            uint(172800),
+            address(COLLATERAL),
+            uint(6000),
+            uint(15000),
+            uint(12000),
+            uint(1800),
+        ],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_create_loan_request_2s,
+        vec![contract::NeurolendCreateLoanRequest2call {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            u_token_address: TOKEN.to_vec(),
+            u_amount: "3000".to_string(),
+            u_max_interest_rate: "800".to_string(),
+            u_duration: "172800".to_string(),
+            u_collateral_address: COLLATERAL.to_vec(),
+            u_collateral_amount: "6000".to_string(),
+            u_min_collateral_ratio_bps: "15000".to_string(),
+            u_liquidation_threshold_bps: "12000".to_string(),
+            u_max_price_staleness: "1800".to_string(),
+        }]
+    );
+    assert!(calls.neurolend_call_create_loan_request_1s.is_empty());
+}
+
+#[test]
+fn fill_loan_offer_1() {
+    let calls = calls_for(neurolend_call("fillLoanOffer(uint256)", &[uint(6)]));
+
+    assert_eq!(
+        calls.neurolend_call_fill_loan_offer_1s,
+        vec![contract::NeurolendFillLoanOffer1call {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            offer_id: "6".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn fill_loan_offer_2() {
+    let calls = calls_for(neurolend_call(
+        "fillLoanOffer(uint256,bytes[])",
+        &[uint(7), price_update()],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_fill_loan_offer_2s,
+        vec![contract::NeurolendFillLoanOffer2call {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            offer_id: "7".to_string(),
+            price_update: vec![vec![0xde, 0xad], vec![0xbe, 0xef, 0x01]],
+        }]
+    );
+}
+
+#[test]
+fn fill_loan_request() {
+    let calls = calls_for(neurolend_call("fillLoanRequest(uint256)", &[uint(8)]));
+
+    assert_eq!(
+        calls.neurolend_call_fill_loan_requests,
+        vec![contract::NeurolendFillLoanRequestCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            request_id: "8".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn liquidate_loan() {
+    let calls = calls_for(neurolend_call(
+        "liquidateLoan(uint256,bytes[])",
+        &[uint(9), price_update()],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_liquidate_loans,
+        vec![contract::NeurolendLiquidateLoanCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            loan_id: "9".to_string(),
+            price_update: vec![vec![0xde, 0xad], vec![0xbe, 0xef, 0x01]],
+        }]
+    );
+}
+
+#[test]
+fn make_partial_repayment() {
+    let calls = calls_for(neurolend_call(
+        "makePartialRepayment(uint256,uint256)",
+        &[uint(10), uint(250)],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_make_partial_repayments,
+        vec![contract::NeurolendMakePartialRepaymentCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            loan_id: "10".to_string(),
+            repayment_amount: "250".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn remove_collateral() {
+    let calls = calls_for(neurolend_call(
+        "removeCollateral(uint256,uint256,bytes[])",
+        &[uint(11), uint(125), price_update()],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_remove_collaterals,
+        vec![contract::NeurolendRemoveCollateralCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            loan_id: "11".to_string(),
+            remove_amount: "125".to_string(),
+            price_update: vec![vec![0xde, 0xad], vec![0xbe, 0xef, 0x01]],
+        }]
+    );
+}
+
+#[test]
+fn renounce_ownership() {
+    let calls = calls_for(neurolend_call("renounceOwnership()", &[]));
+
+    assert_eq!(
+        calls.neurolend_call_renounce_ownerships,
+        vec![contract::NeurolendRenounceOwnershipCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+        }]
+    );
+}
+
+#[test]
+fn repay_loan() {
+    let calls = calls_for(neurolend_call("repayLoan(uint256)", &[uint(12)]));
+
+    assert_eq!(
+        calls.neurolend_call_repay_loans,
+        vec![contract::NeurolendRepayLoanCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            loan_id: "12".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn set_token_price_feed_id() {
+    let calls = calls_for(neurolend_call(
+        "setTokenPriceFeedId(address,bytes32)",
+        &[address(TOKEN), Token::FixedBytes(FEED_ID.to_vec())],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_set_token_price_feed_ids,
+        vec![contract::NeurolendSetTokenPriceFeedIdCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            u_token_address: TOKEN.to_vec(),
+            u_feed_id: FEED_ID.to_vec(),
+        }]
+    );
+}
+
+#[test]
+fn transfer_ownership() {
+    let calls = calls_for(neurolend_call(
+        "transferOwnership(address)",
+        &[address(BORROWER)],
+    ));
+
+    assert_eq!(
+        calls.neurolend_call_transfer_ownerships,
+        vec![contract::NeurolendTransferOwnershipCall {
+            call_tx_hash: TX_HASH_HEX.to_string(),
+            call_block_time: block_time(),
+            call_block_number: BLOCK_NUMBER,
+            call_ordinal: 7,
+            call_success: true,
+            new_owner: BORROWER.to_vec(),
+        }]
+    );
+}
+
+#[test]
+fn reverted_call_is_recorded_as_unsuccessful() {
+    let mut call = neurolend_call("repayLoan(uint256)", &[uint(13)]);
+    call.state_reverted = true;
+    let calls = calls_for(call);
+
+    assert_eq!(calls.neurolend_call_repay_loans.len(), 1);
+    assert!(!calls.neurolend_call_repay_loans[0].call_success);
+    assert_eq!(calls.neurolend_call_repay_loans[0].loan_id, "13");
+}
+
+#[test]
+fn call_to_other_contract_is_ignored() {
+    let mut call = neurolend_call("repayLoan(uint256)", &[uint(14)]);
+    call.address = OTHER_CONTRACT.to_vec();
+
+    assert_eq!(calls_for(call), contract::Calls::default());
+}
+
+#[test]
+fn truncated_call_input_is_dropped() {
+    let mut call = neurolend_call("addCollateral(uint256,uint256)", &[uint(15), uint(1)]);
+    call.input.truncate(4 + 32);
+
+    assert!(calls_for(call).neurolend_call_add_collaterals.is_empty());
+}